@@ -0,0 +1,51 @@
+//! Compares the default SipHash with `FxHasher` for a large set of used txids, as kept with
+//! `TxIdScope::PerClient`. Run with `cargo bench --bench hashers`
+
+use std::{
+    collections::HashSet,
+    hash::{BuildHasher, BuildHasherDefault, RandomState},
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use payeng::fx_hash::FxHasher;
+
+const IDS: u32 = 5_000_000;
+
+/// The client and tx of every id, spread over the clients like the ids of an input
+fn key(tx: u32) -> (u16, u32) {
+    ((tx % 5000) as u16, tx)
+}
+
+/// The time to insert every id, and to look each of them up again
+fn measure<S: BuildHasher + Default>() -> (Duration, Duration) {
+    let mut set: HashSet<(u16, u32), S> = HashSet::default();
+    let start = Instant::now();
+    for tx in 0..IDS {
+        set.insert(key(tx));
+    }
+    let inserted = start.elapsed();
+
+    let start = Instant::now();
+    let found = (0..IDS).filter(|tx| set.contains(&key(*tx))).count();
+    let looked_up = start.elapsed();
+    assert_eq!(IDS as usize, black_box(found));
+    (inserted, looked_up)
+}
+
+fn main() {
+    println!("{} used txids", IDS);
+    let (sip_insert, sip_contains) = measure::<RandomState>();
+    println!(
+        "{:<8} insert {:>8.1?}, contains {:>8.1?}",
+        "SipHash", sip_insert, sip_contains
+    );
+    let (fx_insert, fx_contains) = measure::<BuildHasherDefault<FxHasher>>();
+    println!(
+        "{:<8} insert {:>8.1?}, contains {:>8.1?}, {:.1}x faster",
+        "FxHash",
+        fx_insert,
+        fx_contains,
+        (sip_insert + sip_contains).as_secs_f64() / (fx_insert + fx_contains).as_secs_f64()
+    );
+}
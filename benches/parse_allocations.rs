@@ -0,0 +1,90 @@
+//! Counts the allocations of parsing `testdata/650k_ish.csv`, comparing a fresh record for
+//! every row trimmed by the reader with the reused records of `input::transactions`. Run with
+//! `cargo bench --bench parse_allocations`
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    hint::black_box,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use payeng::{Input, input};
+
+/// Counts every allocation, including those that grow an existing one
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const RUNS: usize = 5;
+
+/// The fastest of the runs, and the allocations of one of them
+fn measure(name: &str, data: &[u8], parse: impl Fn(&[u8]) -> usize) -> (Duration, usize) {
+    let mut fastest = Duration::MAX;
+    let mut allocations = 0;
+    let mut rows = 0;
+    for _ in 0..RUNS {
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        let start = Instant::now();
+        rows = black_box(parse(data));
+        fastest = fastest.min(start.elapsed());
+        allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    }
+    println!(
+        "{:<16} {:>8.1?} {:>10} allocations, {:.3} per row",
+        name,
+        fastest,
+        allocations,
+        allocations as f64 / rows as f64
+    );
+    (fastest, allocations)
+}
+
+fn main() {
+    let data = std::fs::read("testdata/650k_ish.csv").expect("run from the crate root");
+
+    // how the rows were read before, with `records` giving a new record for every row, and
+    // the reader trimming each of them into yet another one
+    let (fresh_time, fresh_allocations) = measure("fresh records", &data, |data| {
+        let mut reader = input::create_input_deserializer_from_reader(data);
+        let headers = reader.headers().unwrap().clone();
+        reader
+            .records()
+            .filter(|row| {
+                let record = row.as_ref().unwrap();
+                !record.iter().all(str::is_empty)
+                    && black_box(record.deserialize::<Input>(Some(&headers))).is_ok()
+            })
+            .count()
+    });
+    let (reused_time, reused_allocations) = measure("reused record", &data, |data| {
+        input::transactions(data)
+            .filter(|row| black_box(row).is_ok())
+            .count()
+    });
+
+    println!(
+        "{:.1}x fewer allocations, {:.2}x the time",
+        fresh_allocations as f64 / reused_allocations.max(1) as f64,
+        reused_time.as_secs_f64() / fresh_time.as_secs_f64()
+    );
+}
@@ -0,0 +1,135 @@
+//! End to end throughput of the engine, on a generated stream of mixed transactions so that
+//! no test data has to be checked in. Run with `cargo bench --bench pipeline`
+//!
+//! Every measurement is repeated and the fastest run is reported, which is the least
+//! disturbed by whatever else the machine is doing
+
+use std::{
+    hint::black_box,
+    io::Write,
+    time::{Duration, Instant},
+};
+
+use payeng::{AccountStorage, FixedPoint, Input, input};
+
+const ROWS: u32 = 1_000_000;
+const CLIENTS: u64 = 1000;
+const RUNS: usize = 5;
+
+/// Mostly deposits and withdrawals, with disputes of earlier deposits of the same client and
+/// some of those resolved or charged back, the same stream every run
+fn generate() -> Vec<Input> {
+    // a small linear congruential generator, so that nothing outside std is needed
+    let mut seed = 0x2545_f491_4f6c_dd1du64;
+    let mut next = move |bound: u64| {
+        seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (seed >> 33) % bound
+    };
+
+    let mut inputs = Vec::with_capacity(ROWS as usize);
+    for tx in 1..=ROWS {
+        let amount = FixedPoint::from_decimillis(1 + next(1_000_000) as i128);
+        // a tx of the same client some rounds back, likely a deposit
+        let back = u32::try_from(CLIENTS * (1 + next(10))).unwrap();
+        let client = (u64::from(tx) % CLIENTS) as u16;
+        let earlier = tx.saturating_sub(back).max(1);
+        let builder = Input::builder();
+        let builder = match next(100) {
+            0..=59 => builder.deposit(client, tx, amount),
+            60..=89 => builder.withdrawal(client, tx, amount),
+            90..=95 => builder.dispute(client, earlier),
+            96..=98 => builder.resolve(client, earlier),
+            _ => builder.chargeback(client, earlier),
+        };
+        inputs.push(builder.build().unwrap());
+    }
+    inputs
+}
+
+/// The stream as the csv of a partner
+fn to_csv(inputs: &[Input]) -> Vec<u8> {
+    let mut csv = Vec::new();
+    writeln!(csv, "type, client, tx, amount").unwrap();
+    for input in inputs {
+        let amount = input.amount().map(|a| a.to_string()).unwrap_or_default();
+        writeln!(
+            csv,
+            "{}, {}, {}, {}",
+            input.r#type().name(),
+            input.client(),
+            input.tx(),
+            amount
+        )
+        .unwrap();
+    }
+    csv
+}
+
+/// Runs `f` `RUNS` times, each on a fresh copy of the setup, and reports the fastest
+fn bench<T: Clone>(name: &str, setup: &T, mut f: impl FnMut(T) -> usize) {
+    let mut fastest = Duration::MAX;
+    for _ in 0..RUNS {
+        let data = setup.clone();
+        let start = Instant::now();
+        let rows = black_box(f(data));
+        fastest = fastest.min(start.elapsed());
+        assert_eq!(ROWS as usize, rows, "{}", name);
+    }
+    println!(
+        "{:<28} {:>8.1?} {:>6.2} M rows/s",
+        name,
+        fastest,
+        f64::from(ROWS) / fastest.as_secs_f64() / 1e6
+    );
+}
+
+fn main() {
+    let inputs = generate();
+    let csv = to_csv(&inputs);
+    println!(
+        "{} rows over {} clients, {:.1} MiB of csv",
+        ROWS,
+        CLIENTS,
+        csv.len() as f64 / (1024.0 * 1024.0)
+    );
+
+    bench("handle_transaction", &inputs, |inputs| {
+        let mut accounts = AccountStorage::new();
+        let rows = inputs.len();
+        for input in inputs {
+            let _ = accounts.handle_transaction(input);
+        }
+        black_box(&accounts);
+        rows
+    });
+
+    bench("parse with transactions", &(), |()| {
+        input::transactions(csv.as_slice())
+            .try_fold(0, |rows, row| row.map(|_| rows + 1))
+            .unwrap()
+    });
+
+    let path = std::env::temp_dir().join(format!("payeng-bench-{}.csv", std::process::id()));
+    std::fs::write(&path, &csv).unwrap();
+    bench("parse a file", &(), |()| {
+        let mut reader = input::create_input_deserializer(path.to_str().unwrap()).unwrap();
+        reader
+            .deserialize::<Input>()
+            .try_fold(0, |rows, row| row.map(|_| rows + 1))
+            .unwrap()
+    });
+    let _ = std::fs::remove_file(&path);
+
+    bench("parse and handle", &(), |()| {
+        let mut accounts = AccountStorage::new();
+        let mut rows = 0;
+        for input in input::transactions(csv.as_slice()) {
+            let _ = accounts.handle_transaction(input.unwrap());
+            rows += 1;
+        }
+        black_box(&accounts);
+        rows
+    });
+}
@@ -0,0 +1,64 @@
+//! Compares the memory taken by the txid sets for a dense range of ids, as a large input with
+//! increasing tx ids would give. Run with `cargo bench --bench txid_memory`
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    collections::HashSet,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+use payeng::accounts::{TxIdBitmap, TxIdSet};
+
+/// Keeps track of the bytes currently allocated, so the size of a set is what it adds
+struct Counting;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const IDS: u32 = 10_000_000;
+
+fn measure<S: TxIdSet>(name: &str, new: impl FnOnce() -> S) {
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let mut set = new();
+    for tx in 0..IDS {
+        set.insert(tx);
+    }
+    let inserted = start.elapsed();
+    let bytes = ALLOCATED.load(Ordering::Relaxed) - before;
+
+    let start = Instant::now();
+    let found = (0..IDS).filter(|tx| set.contains(*tx)).count();
+    let looked_up = start.elapsed();
+    assert_eq!(IDS as usize, found);
+
+    println!(
+        "{:<12} {:>8.2} MiB {:>6.2} bytes/id, insert {:>8.1?}, contains {:>8.1?}",
+        name,
+        bytes as f64 / (1024.0 * 1024.0),
+        bytes as f64 / f64::from(IDS),
+        inserted,
+        looked_up
+    );
+}
+
+fn main() {
+    println!("{} dense tx ids", IDS);
+    measure("HashSet", HashSet::<u32>::new);
+    measure("TxIdBitmap", TxIdBitmap::new);
+}
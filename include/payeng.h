@@ -0,0 +1,76 @@
+/* The C ABI of the payeng ledger, built with the `ffi` feature, see src/ffi.rs */
+
+#ifndef PAYENG_H
+#define PAYENG_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* The accounts behind a handle, only ever used through a pointer */
+typedef struct PengHandle PengHandle;
+
+/* Returned by peng_handle, every TransactionError has a positive code */
+#define PENG_OK 0
+#define PENG_NULL_HANDLE -1
+#define PENG_UNKNOWN_TYPE -2
+
+#define PENG_ERR_MALFORMED_INPUT 1
+#define PENG_ERR_UNEXPECTED_AMOUNT 2
+#define PENG_ERR_NOT_ENOUGH_AVAILABLE_FUNDS 3
+#define PENG_ERR_MISSING_TX_ID 4
+#define PENG_ERR_DUPLICATE_TX_ID 5
+#define PENG_ERR_TX_BELONGS_TO_OTHER_CLIENT 6
+#define PENG_ERR_ACCOUNT_LOCKED 7
+#define PENG_ERR_ACCOUNT_FROZEN 8
+#define PENG_ERR_INVALID_TX 9
+#define PENG_ERR_INVALID_TX_FOR_DISPUTE 10
+#define PENG_ERR_MISSING_DISPUTE_TX 11
+#define PENG_ERR_DISPUTE_ALREADY_EXIST 12
+#define PENG_ERR_DISPUTE_ALREADY_HANDLED 13
+#define PENG_ERR_HISTORY_UNAVAILABLE 14
+#define PENG_ERR_BALANCE_OVERFLOW 15
+#define PENG_ERR_UNLOCK_NOT_ALLOWED 16
+#define PENG_ERR_HELD_WOULD_GO_NEGATIVE 17
+#define PENG_ERR_INVALID_TX_FOR_VOID 18
+#define PENG_ERR_FUNDS_ALREADY_MOVED 19
+#define PENG_ERR_DISPUTE_EXCEEDS_TX 20
+#define PENG_ERR_UNKNOWN_ACCOUNT 21
+#define PENG_ERR_ACCOUNT_NOT_EMPTY 22
+#define PENG_ERR_OPEN_DISPUTES 23
+#define PENG_ERR_DISPUTES_DISABLED 24
+#define PENG_ERR_UNREPRESENTABLE_AMOUNT 25
+
+/* The type codes of peng_handle, transfers are not supported */
+#define PENG_DEPOSIT 0u
+#define PENG_WITHDRAWAL 1u
+#define PENG_DISPUTE 2u
+#define PENG_RESOLVE 3u
+#define PENG_CHARGEBACK 4u
+#define PENG_INQUIRY 5u
+#define PENG_UNLOCK 6u
+#define PENG_VOID 8u
+
+/* A new empty storage, owned by the caller until it is passed to peng_free */
+PengHandle *peng_new(void);
+
+/* Releases the handle, which must not be used afterwards. NULL is ignored */
+void peng_free(PengHandle *handle);
+
+/* Handles a transaction. amount points to its amount in ten thousandths, and is only read
+ * during the call. It is NULL for resolve, chargeback, inquiry, unlock and void, which give
+ * PENG_ERR_UNEXPECTED_AMOUNT otherwise. A dispute takes NULL for the whole transaction, or
+ * the part of it that is disputed */
+int32_t peng_handle(PengHandle *handle, uint32_t type, uint16_t client, uint32_t tx,
+                    const int64_t *amount);
+
+/* The available funds of the client in ten thousandths, 0 for an unknown client or NULL */
+int64_t peng_available(const PengHandle *handle, uint16_t client);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -1,76 +1,845 @@
-use std::collections::{BTreeMap, HashSet, btree_map};
+use alloc::{
+    borrow::ToOwned,
+    boxed::Box,
+    collections::{BTreeMap, BTreeSet, VecDeque, btree_map},
+    string::String,
+    vec::Vec,
+};
+use core::fmt;
+#[cfg(feature = "std")]
+use std::{collections::HashSet, hash::BuildHasher, sync::mpsc, thread};
+
+use serde::{Deserialize, Serialize};
 
+#[cfg(feature = "trace")]
+use crate::trace;
 use crate::{
     FixedPoint,
+    amount::AmountOps,
+    fx_hash::{FxHashMap, FxHashSet},
+    history::{HistoryBackend, HistoryError, TxHistory},
     input::{Input, TransactionType},
+    ledger::Ledger,
 };
 
-pub struct AccountStorage {
-    accounts: BTreeMap<u16, Account>,
-    used_txids: HashSet<u32>,
+pub struct AccountStorage<A: AmountOps = FixedPoint> {
+    accounts: BTreeMap<u16, Account<A>>,
+    used_txids: UsedTxIds,
+    /// where new accounts keep their transaction history
+    history: HistoryBackend,
+    /// if `TransactionType::Unlock` is handled or rejected
+    allow_unlock: bool,
+    /// the dispute policy of new accounts
+    dispute_policy: DisputePolicy,
+    /// the chargeback policy of new accounts
+    chargeback_policy: ChargebackPolicy,
+    /// if new accounts accept deposits while locked
+    locked_deposit_policy: LockedDepositPolicy,
+    /// if new accounts keep an audit log
+    audit_log: bool,
+    /// how many of the latest txids of each account are kept, all of them if `None`
+    retention: Option<usize>,
+    /// if disputes, resolves, chargebacks and voids are rejected, and no history is kept
+    disputes_disabled: bool,
+    /// where the balances are persisted, see `with_ledger`
+    ledger: Option<Box<dyn Ledger<A> + Send>>,
+}
+
+/// Configures an `AccountStorage` before any transactions are handled
+#[derive(Clone, Default)]
+pub struct AccountStorageBuilder {
+    history: HistoryBackend,
+    allow_unlock: bool,
+    dispute_policy: DisputePolicy,
+    chargeback_policy: ChargebackPolicy,
+    locked_deposit_policy: LockedDepositPolicy,
+    audit_log: bool,
+    txid_scope: TxIdScope,
+    retention: Option<usize>,
+    disputes_disabled: bool,
+}
+
+impl AccountStorageBuilder {
+    /// Selects where the transaction history of each account is kept, defaults to memory
+    pub fn history(mut self, history: HistoryBackend) -> Self {
+        self.history = history;
+        self
+    }
+
+    /// Handles unlock transactions, reactivating locked accounts, instead of rejecting them.
+    /// Only meant for input from operations that have reviewed the accounts, defaults to false
+    pub fn allow_unlock(mut self, allow_unlock: bool) -> Self {
+        self.allow_unlock = allow_unlock;
+        self
+    }
+
+    /// Selects if disputes may leave the available funds of an account negative, defaults to
+    /// `DisputePolicy::AllowNegative`
+    pub fn dispute_policy(mut self, dispute_policy: DisputePolicy) -> Self {
+        self.dispute_policy = dispute_policy;
+        self
+    }
+
+    /// Selects if a chargeback locks the account completely or only freezes it, defaults to
+    /// `ChargebackPolicy::Lock`
+    pub fn chargeback_policy(mut self, chargeback_policy: ChargebackPolicy) -> Self {
+        self.chargeback_policy = chargeback_policy;
+        self
+    }
+
+    /// Selects if a locked account still accepts deposits, and transfers to it, defaults to
+    /// `LockedDepositPolicy::Reject`
+    pub fn locked_deposit_policy(mut self, locked_deposit_policy: LockedDepositPolicy) -> Self {
+        self.locked_deposit_policy = locked_deposit_policy;
+        self
+    }
+
+    /// Keeps an audit log of the applied transactions in every account, which grows with the
+    /// input, so it defaults to false
+    pub fn audit_log(mut self, audit_log: bool) -> Self {
+        self.audit_log = audit_log;
+        self
+    }
+
+    /// Selects if tx ids have to be unique among all clients or only for each client, defaults
+    /// to `TxIdScope::Global`
+    pub fn txid_scope(mut self, txid_scope: TxIdScope) -> Self {
+        self.txid_scope = txid_scope;
+        self
+    }
+
+    /// Only keeps the latest `retention` deposits, withdrawals and transfers of each account,
+    /// so that memory stays bounded for long streams. Older transactions can no longer be
+    /// disputed, and their txids are forgotten and may be reused. A transaction under an
+    /// active dispute is kept until the dispute is settled. Defaults to keeping everything
+    ///
+    /// This is the streaming mode, the history and used txids then hold at most `retention`
+    /// transactions plus those under dispute for each of the at most `u16::MAX` clients, no
+    /// matter how long the input is. Idempotency keys and the audit log are not pruned, so
+    /// neither should be used for unbounded input
+    pub fn retention(mut self, retention: usize) -> Self {
+        self.retention = Some(retention);
+        self
+    }
+
+    /// Rejects every dispute, resolve, chargeback and void with
+    /// `TransactionError::DisputesDisabled`, for inputs that only ever move funds. The accounts
+    /// then keep no transaction history, which is most of their memory. The txids are still
+    /// checked for duplicates. Defaults to false
+    pub fn disable_disputes(mut self, disable: bool) -> Self {
+        self.disputes_disabled = disable;
+        self
+    }
+
+    pub fn build(self) -> AccountStorage {
+        self.build_with_amount()
+    }
+
+    /// Builds a storage keeping the balances in another type than `FixedPoint`, amounts of
+    /// transactions that cannot be represented in it are rejected with
+    /// `TransactionError::UnrepresentableAmount`
+    pub fn build_with_amount<A: AmountOps>(self) -> AccountStorage<A> {
+        AccountStorage {
+            accounts: BTreeMap::new(),
+            used_txids: UsedTxIds::new(self.txid_scope),
+            history: self.history,
+            allow_unlock: self.allow_unlock,
+            dispute_policy: self.dispute_policy,
+            chargeback_policy: self.chargeback_policy,
+            locked_deposit_policy: self.locked_deposit_policy,
+            audit_log: self.audit_log,
+            retention: self.retention,
+            disputes_disabled: self.disputes_disabled,
+            ledger: None,
+        }
+    }
+
+    /// Handles the inputs on `shards` threads, each owning the accounts of the clients with
+    /// the same `client % shards`, then merges the shards into one storage. The result is the
+    /// same as handling the inputs in order with `handle_transaction`.
+    ///
+    /// The txids are checked by the calling thread as it hands out the inputs, since with a
+    /// set shared by the workers the timing of the threads would decide which of two uses of
+    /// an id is rejected. It also remembers which client used each id, so that a dispute of
+    /// the tx of a client in another shard is rejected before it reaches a shard, which
+    /// would otherwise create an account for the disputing client. A transfer between clients of different shards makes both of them
+    /// wait until it has been applied, so inputs with many of those gain little from this.
+    ///
+    /// Meant for batch input, so idempotency keys and a retention window are not supported:
+    /// the keys of reused txids and of transfers between shards are not recorded, and the
+    /// txids are never forgotten
+    #[cfg(feature = "std")]
+    pub fn process_parallel<I: IntoIterator<Item = Input>>(
+        self,
+        inputs: I,
+        shards: usize,
+    ) -> Result<AccountStorage, MergeError> {
+        let shards = shards.max(1);
+        let mut used_txids = UsedTxIds::new(self.txid_scope);
+        // the client of every deposit and withdrawal, only these are ever in a history
+        let mut owners: FxHashMap<u32, u16> = FxHashMap::default();
+        let check_owners = self.txid_scope == TxIdScope::Global && !self.disputes_disabled;
+
+        let storages: Vec<AccountStorage> = thread::scope(|scope| {
+            let (senders, workers): (Vec<_>, Vec<_>) = (0..shards)
+                .map(|_| {
+                    // bounded, so that a slow shard holds up the reading instead of the input
+                    // piling up in memory
+                    let (sender, receiver) = mpsc::sync_channel(1024);
+                    let mut storage = self.clone().build();
+                    let worker = scope.spawn(move || {
+                        for message in receiver {
+                            storage.handle_shard_message(message);
+                        }
+                        storage
+                    });
+                    (sender, worker)
+                })
+                .unzip();
+            let shard_of = |client: u16| usize::from(client) % shards;
+
+            for input in inputs {
+                let claims_txid = matches!(
+                    input.r#type(),
+                    TransactionType::Deposit
+                        | TransactionType::Withdrawal
+                        | TransactionType::Transfer
+                );
+                if claims_txid && input.valid() {
+                    if used_txids.contains(input.client(), input.tx()) {
+                        // rejected without changing anything, so it never has to go to a shard
+                        continue;
+                    }
+                    used_txids.insert(input.client(), input.tx());
+                    if check_owners && !matches!(input.r#type(), TransactionType::Transfer) {
+                        owners.insert(input.tx(), input.client());
+                    }
+                }
+
+                let from = shard_of(input.client());
+                let refers_to_tx = matches!(
+                    input.r#type(),
+                    TransactionType::Dispute
+                        | TransactionType::Resolve
+                        | TransactionType::Chargeback
+                        | TransactionType::Void
+                );
+                if refers_to_tx
+                    && input.valid()
+                    && let Some(&owner) = owners.get(&input.tx())
+                    && owner != input.client()
+                    && shard_of(owner) != from
+                {
+                    // the shard of the client only knows its own accounts, so the shard of the
+                    // owner is asked, as the deposit of the owner may also have been rejected
+                    match tx_in_history(&senders[shard_of(owner)], owner, input.tx()) {
+                        // rejected with `TxBelongsToOtherClient`, which changes nothing
+                        Some(true) => continue,
+                        Some(false) => {}
+                        None => break,
+                    }
+                }
+                let between_shards = match input.to() {
+                    Some(to)
+                        if input.valid() && matches!(input.r#type(), TransactionType::Transfer) =>
+                    {
+                        Some(shard_of(to)).filter(|to| *to != from)
+                    }
+                    _ => None,
+                };
+                let sent = match between_shards {
+                    Some(to) => transfer_between_shards(&senders[from], &senders[to], input),
+                    None => senders[from].send(ShardMessage::Transaction(input)).is_ok(),
+                };
+                if !sent {
+                    // the worker is gone, which the join below reports
+                    break;
+                }
+            }
+            drop(senders);
+
+            workers
+                .into_iter()
+                .map(|worker| worker.join().expect("a shard worker panicked"))
+                .collect()
+        });
+
+        let mut storages = storages.into_iter();
+        let mut merged = storages.next().expect("there is always at least one shard");
+        for storage in storages {
+            merged.merge(storage)?;
+        }
+        Ok(merged)
+    }
+}
+
+impl<A: AmountOps> Default for AccountStorage<A> {
+    fn default() -> Self {
+        AccountStorageBuilder::default().build_with_amount()
+    }
 }
 
 impl AccountStorage {
     /// create a new account storage
     pub fn new() -> Self {
-        Self {
-            accounts: BTreeMap::new(),
-            used_txids: HashSet::new(),
-        }
+        Self::builder().build()
+    }
+
+    /// configure a new account storage
+    pub fn builder() -> AccountStorageBuilder {
+        AccountStorageBuilder::default()
     }
+}
 
-    /// get client entry
-    pub fn entry(&mut self, client: u16) -> btree_map::Entry<'_, u16, Account> {
-        self.accounts.entry(client)
+impl<A: AmountOps> AccountStorage<A> {
+    /// Records how every applied transaction changes the balances in the ledger, and loads
+    /// the accounts that are not in memory yet from it before a transaction is applied to
+    /// them, so that the balances outlive the process. Accounts already in the storage are
+    /// not recorded until they change
+    pub fn with_ledger(mut self, ledger: impl Ledger<A> + Send + 'static) -> Self {
+        self.ledger = Some(Box::new(ledger));
+        self
     }
 
     /// Get a reference to the account storage's accounts.
-    pub fn accounts(&self) -> &BTreeMap<u16, Account> {
+    pub fn accounts(&self) -> &BTreeMap<u16, Account<A>> {
         &self.accounts
     }
 
+    /// The held funds of every account together
+    pub fn total_held(&self) -> A {
+        self.accounts
+            .values()
+            .fold(A::ZERO, |total, account| total + account.held())
+    }
+
+    /// The available funds of every account together
+    pub fn total_available(&self) -> A {
+        self.accounts
+            .values()
+            .fold(A::ZERO, |total, account| total + account.available())
+    }
+
+    /// If every fund is accounted for, given the deposits minus the withdrawals that were
+    /// applied. Disputes, resolves and transfers only move funds, so they never change the
+    /// total, while a chargeback reverses its transaction and has to be taken out of
+    /// `applied_net` as well
+    pub fn verify_conservation(&self, applied_net: A) -> bool {
+        self.total_available() + self.total_held() == applied_net
+    }
+
+    /// Get the account of a single client, if it has one
+    pub fn get(&self, client: u16) -> Option<&Account<A>> {
+        self.accounts.get(&client)
+    }
+
+    /// Removes the account of the client, which has to be unlocked, without funds and without
+    /// open disputes. Like with `evict_cold`, the txids of the account can still not be reused
+    pub fn close(&mut self, client: u16) -> Result<(), TransactionError> {
+        let account = self
+            .accounts
+            .get(&client)
+            .ok_or(TransactionError::UnknownAccount { client })?;
+        if account.locked() {
+            return Err(TransactionError::AccountLocked);
+        }
+        if account.has_active_disputes() {
+            return Err(TransactionError::OpenDisputes);
+        }
+        if !account.available().is_zero() || !account.held().is_zero() {
+            return Err(TransactionError::AccountNotEmpty);
+        }
+        self.accounts.remove(&client);
+        Ok(())
+    }
+
+    /// Combines the result of processing another shard of the input into this storage. A
+    /// client in both has its balances summed and its history joined, any other account is
+    /// moved over as is. Fails without changing anything if a txid was used in both, or if
+    /// the balances would overflow, apart from a history that fails to be written
+    pub fn merge(&mut self, other: AccountStorage<A>) -> Result<(), MergeError> {
+        for (client, theirs) in &other.accounts {
+            if let Some(ours) = self.accounts.get(client) {
+                ours.available
+                    .checked_add(theirs.available)
+                    .and(ours.held.checked_add(theirs.held))
+                    .ok_or(MergeError::BalanceOverflow { client: *client })?;
+            }
+        }
+        self.used_txids.merge(other.used_txids)?;
+
+        for (client, theirs) in other.accounts {
+            match self.accounts.entry(client) {
+                btree_map::Entry::Vacant(entry) => {
+                    entry.insert(theirs);
+                }
+                btree_map::Entry::Occupied(entry) => entry.into_mut().merge(theirs)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves out every account that has a zero balance, is unlocked and has no active disputes,
+    /// so that long running services can keep the map of accounts small. The caller is expected
+    /// to persist the returned accounts somewhere else.
+    ///
+    /// The txids used by the evicted accounts are kept, so they can still not be reused
+    pub fn evict_cold(&mut self) -> Vec<(u16, Account<A>)> {
+        let cold: Vec<u16> = self
+            .accounts
+            .iter()
+            .filter(|(_, account)| account.is_cold())
+            .map(|(client, _)| *client)
+            .collect();
+
+        cold.into_iter()
+            .filter_map(|client| self.accounts.remove(&client).map(|a| (client, a)))
+            .collect()
+    }
+
+    /// Checks that the state of every account is internally consistent, returns every
+    /// inconsistency found, so an empty list means that everything checks out
+    pub fn check_integrity(&self) -> Vec<IntegrityIssue> {
+        let mut issues = Vec::new();
+        for (client, account) in &self.accounts {
+            for (tx, dispute) in &account.disputes {
+                if !account.contains_txid(*tx) {
+                    issues.push(IntegrityIssue::DisputeWithoutTx {
+                        client: *client,
+                        tx: *tx,
+                    });
+                }
+                // an account unlocked after review keeps its charged back disputes
+                if *dispute == DisputeState::Reimbursed && !account.locked() && !account.reactivated
+                {
+                    issues.push(IntegrityIssue::ReimbursedButUnlocked {
+                        client: *client,
+                        tx: *tx,
+                    });
+                }
+            }
+        }
+        issues
+    }
+
+    /// Captures the whole state, so that it can be persisted and restored with
+    /// `from_snapshot`. Fails if the history of an account cannot be read back
+    pub fn to_snapshot(&self) -> Result<Snapshot<A>, HistoryError> {
+        let accounts = self
+            .accounts
+            .iter()
+            .map(|(client, account)| Ok((*client, account.to_snapshot()?)))
+            .collect::<Result<_, HistoryError>>()?;
+        let (txid_scope, used_txids, used_client_txids) = match &self.used_txids {
+            UsedTxIds::Global(ids) => (TxIdScope::Global, ids.iter().collect(), [].into()),
+            UsedTxIds::PerClient(ids) => (
+                TxIdScope::PerClient,
+                [].into(),
+                ids.iter().copied().collect(),
+            ),
+        };
+        Ok(Snapshot {
+            accounts,
+            txid_scope,
+            used_txids,
+            used_client_txids,
+            retention: self.retention,
+            allow_unlock: self.allow_unlock,
+            dispute_policy: self.dispute_policy,
+            chargeback_policy: self.chargeback_policy,
+            locked_deposit_policy: self.locked_deposit_policy,
+            disputes_disabled: self.disputes_disabled,
+        })
+    }
+
+    /// The accounts as they should be shown to the outside, such as in an API response.
+    /// Fails if the history of an account cannot be read back
+    pub fn view(&self) -> Result<StorageView<A>, HistoryError> {
+        let accounts = self
+            .accounts
+            .iter()
+            .map(|(client, account)| Ok((*client, account.view()?)))
+            .collect::<Result<_, HistoryError>>()?;
+        Ok(StorageView { accounts })
+    }
+
+    /// Restores the state captured by `to_snapshot`, with the transaction history kept in
+    /// memory and no audit log, and the rest of the configuration, such as the retention
+    /// window, as it was when the snapshot was taken
+    pub fn from_snapshot(snapshot: Snapshot<A>) -> Self {
+        let mut storage = AccountStorageBuilder {
+            allow_unlock: snapshot.allow_unlock,
+            dispute_policy: snapshot.dispute_policy,
+            chargeback_policy: snapshot.chargeback_policy,
+            locked_deposit_policy: snapshot.locked_deposit_policy,
+            txid_scope: snapshot.txid_scope,
+            retention: snapshot.retention,
+            disputes_disabled: snapshot.disputes_disabled,
+            ..Default::default()
+        }
+        .build_with_amount();
+        storage.used_txids = match snapshot.txid_scope {
+            TxIdScope::Global => UsedTxIds::Global(snapshot.used_txids.into_iter().collect()),
+            TxIdScope::PerClient => {
+                UsedTxIds::PerClient(snapshot.used_client_txids.into_iter().collect())
+            }
+        };
+        for (client, account) in snapshot.accounts {
+            let restored = Account::from_snapshot(account, storage.history.new_history());
+            storage.accounts.insert(client, restored);
+        }
+        storage
+    }
+
+    /// Same as `handle_transaction`, with the outcome also reported to the sink
+    pub fn handle_transaction_with_sink<S: TransactionSink + ?Sized>(
+        &mut self,
+        input: Input,
+        sink: &mut S,
+    ) -> Result<(), TransactionError> {
+        let observed = input.clone();
+        let outcome = self.handle_transaction(input);
+        match &outcome {
+            Ok(()) => sink.on_applied(&observed),
+            Err(e) => sink.on_rejected(&observed, e),
+        }
+        outcome
+    }
+
     pub fn handle_transaction(&mut self, input: Input) -> Result<(), TransactionError> {
+        let client = input.client();
+        #[cfg(feature = "trace")]
+        let tx = input.tx();
+        #[cfg(feature = "trace")]
+        let _span = trace::span("transaction", client, tx);
+        let key = input.idempotency_key().map(str::to_owned);
+
+        if let Some(key) = &key {
+            let prior = self
+                .accounts
+                .get(&client)
+                .and_then(|account| account.idempotency_keys.get(key));
+            if let Some(outcome) = prior {
+                // A retry of an operation that has already been handled, so nothing is applied
+                // again. This is checked before the txid, since a retry may reuse it
+                return outcome.clone();
+            }
+        }
+
+        let outcome = match self.ledger.is_some() {
+            true => self.apply_recorded(input),
+            false => self.apply_transaction(input),
+        };
+
+        if let Some(key) = key {
+            // If the transaction never reached an account, there is nothing that the key
+            // could protect against applying twice
+            if let Some(account) = self.accounts.get_mut(&client) {
+                account.idempotency_keys.insert(key, outcome.clone());
+            }
+        }
+        #[cfg(feature = "trace")]
+        if let Err(e) = &outcome {
+            trace::event("rejected", client, tx, Some(e.name()));
+        }
+        outcome
+    }
+
+    /// Applies the transaction after loading its accounts from the ledger, and records how
+    /// it changed each of them
+    fn apply_recorded(&mut self, input: Input) -> Result<(), TransactionError> {
+        let clients = [Some(input.client()), input.to()];
+        let mut before = [None; 2];
+        for (client, before) in clients.into_iter().zip(&mut before) {
+            let Some(client) = client else { continue };
+            self.load_from_ledger(client);
+            *before = self.accounts.get(&client).map(Account::balances);
+        }
+
+        self.apply_transaction(input)?;
+
+        for (client, before) in clients.into_iter().zip(before) {
+            let Some(client) = client else { continue };
+            let Some(account) = self.accounts.get(&client) else {
+                continue;
+            };
+            let (available, held, locked) = before.unwrap_or_default();
+            let delta = BalanceDelta {
+                available: account.available - available,
+                held: account.held - held,
+                locked: (account.locked() != locked).then_some(account.locked()),
+            };
+            if delta != BalanceDelta::default()
+                && let Some(ledger) = &mut self.ledger
+            {
+                ledger.record(client, delta);
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates the account of the client with its persisted balances, if it is not in memory
+    /// but in the ledger
+    fn load_from_ledger(&mut self, client: u16) {
+        if self.accounts.contains_key(&client) {
+            return;
+        }
+        let Some(state) = self.ledger.as_ref().and_then(|ledger| ledger.load(client)) else {
+            return;
+        };
+        let account = self.account_mut(client);
+        account.available = state.available;
+        account.held = state.held;
+        if state.locked {
+            account.status = AccountStatus::Locked;
+        }
+    }
+
+    /// Fails if the tx is not in the history of the client, but in that of another client,
+    /// which could be an attempt at disputing someone else's transaction. When tx ids are only
+    /// unique for each client, a tx of another client is not an error, just a missing tx
+    fn check_tx_owner(&self, client: u16, tx: u32) -> Result<(), TransactionError> {
+        let UsedTxIds::Global(used_txids) = &self.used_txids else {
+            return Ok(());
+        };
+        let own = self
+            .accounts
+            .get(&client)
+            .is_some_and(|account| account.contains_txid(tx));
+        // only ids that have been used can be in any history, which saves looking
+        // through every account for the common case of a missing tx
+        if own || !used_txids.contains(tx) {
+            return Ok(());
+        }
+        match self
+            .accounts
+            .iter()
+            .find(|(_, account)| account.contains_txid(tx))
+        {
+            Some((owner, _)) => Err(TransactionError::TxBelongsToOtherClient {
+                client,
+                tx,
+                owner: *owner,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// The account of the client, created if it does not exist yet
+    fn account_mut(&mut self, client: u16) -> &mut Account<A> {
+        let history = &self.history;
+        let dispute_policy = self.dispute_policy;
+        let chargeback_policy = self.chargeback_policy;
+        let locked_deposit_policy = self.locked_deposit_policy;
+        let audit_log = self.audit_log;
+        let disputes_disabled = self.disputes_disabled;
+        self.accounts.entry(client).or_insert_with(|| {
+            let history = match disputes_disabled {
+                true => TxHistory::Disabled,
+                false => history.new_history(),
+            };
+            let account = Account::with_history(history)
+                .with_dispute_policy(dispute_policy)
+                .with_chargeback_policy(chargeback_policy)
+                .with_locked_deposit_policy(locked_deposit_policy);
+            if audit_log {
+                account.with_audit_log()
+            } else {
+                account
+            }
+        })
+    }
+
+    /// Records the txid as the most recent of the client, and forgets the txids that have fallen
+    /// out of the retention window, so that they no longer count as used
+    fn retain_recent(&mut self, client: u16, tx: u32, retention: usize) {
+        // a transfer from a client without an account never got one, and since nothing of
+        // it is kept, neither is its txid
+        let Some(account) = self.accounts.get_mut(&client) else {
+            self.used_txids.remove(client, tx);
+            return;
+        };
+        account.recent_txids.push_back(tx);
+        for pruned in account.prune_history(retention) {
+            self.used_txids.remove(client, pruned);
+        }
+    }
+
+    /// Handles a message from `AccountStorageBuilder::process_parallel` in a shard worker
+    #[cfg(feature = "std")]
+    fn handle_shard_message(&mut self, message: ShardMessage) {
+        match message {
+            ShardMessage::Transaction(input) => {
+                // the outcome is in the accounts, just like for a sequential run
+                let _ = self.handle_transaction(input);
+            }
+            ShardMessage::CheckSender(input, reply) => {
+                // the txid is used even if the transfer fails, as with `handle_transaction`
+                self.used_txids.insert(input.client(), input.tx());
+                let _ = reply.send(self.check_transfer_sender(&input));
+            }
+            ShardMessage::CheckReceiver(input, reply) => {
+                let _ = reply.send(self.check_transfer_receiver(&input));
+            }
+            ShardMessage::Send(input) => self.send_transfer(&input),
+            ShardMessage::Receive(input) => self.receive_transfer(&input),
+            ShardMessage::ContainsTx(client, tx, reply) => {
+                let contains = self
+                    .accounts
+                    .get(&client)
+                    .is_some_and(|account| account.contains_txid(tx));
+                let _ = reply.send(contains);
+            }
+        }
+    }
+
+    /// Moves available funds between two accounts, either both balances change or neither
+    fn transfer(&mut self, input: &Input) -> Result<(), TransactionError> {
+        self.check_transfer_sender(input)?;
+        self.check_transfer_receiver(input)?;
+        self.receive_transfer(input);
+        self.send_transfer(input);
+        Ok(())
+    }
+
+    /// Fails if the sender of the transfer cannot send its amount
+    fn check_transfer_sender(&self, input: &Input) -> Result<(), TransactionError> {
+        // Safe because of the validity check on the transaction
+        let amount: A = amount_of(input.amount_as_fp().unwrap())?;
+        let source = self
+            .accounts
+            .get(&input.client())
+            .ok_or(TransactionError::NotEnoughAvailableFunds)?;
+        match source.status() {
+            AccountStatus::Active => {}
+            AccountStatus::Frozen => return Err(TransactionError::AccountFrozen),
+            AccountStatus::Locked => return Err(TransactionError::AccountLocked),
+        }
+        if source.available < amount {
+            return Err(TransactionError::NotEnoughAvailableFunds);
+        }
+        Ok(())
+    }
+
+    /// Fails if the receiver of the transfer cannot be credited its amount
+    fn check_transfer_receiver(&self, input: &Input) -> Result<(), TransactionError> {
+        let amount: A = amount_of(input.amount_as_fp().unwrap())?;
+        let destination = self.accounts.get(&input.to().unwrap());
+        // a frozen account can still receive funds, like with a deposit
+        if destination.is_some_and(|account| !account.accepts_deposits()) {
+            return Err(TransactionError::AccountLocked);
+        }
+        if let Some(destination) = destination {
+            destination.credited(amount)?;
+        }
+        Ok(())
+    }
+
+    /// Credits the receiver of a transfer that has passed `check_transfer_receiver`
+    fn receive_transfer(&mut self, input: &Input) {
+        let destination = self.account_mut(input.to().unwrap());
+        // representable, since the checks convert it as well
+        destination.available += amount_of(input.amount_as_fp().unwrap()).unwrap();
+        destination.applied(input);
+    }
+
+    /// Debits the sender of a transfer that has passed `check_transfer_sender`
+    fn send_transfer(&mut self, input: &Input) {
+        let source = self.account_mut(input.client());
+        source.available -= amount_of(input.amount_as_fp().unwrap()).unwrap();
+        source.applied(input);
+    }
+
+    fn apply_transaction(&mut self, input: Input) -> Result<(), TransactionError> {
         if input.valid() {
             match input.r#type() {
                 // safeguard agains duplicate transaction IDs by checking
                 // if any previous transactions has used it
-                TransactionType::Deposit | TransactionType::Withdrawal => {
-                    if self.used_txids.contains(&input.tx()) {
-                        return Err(TransactionError::DuplicateTxId);
+                TransactionType::Deposit
+                | TransactionType::Withdrawal
+                | TransactionType::Transfer => {
+                    if self.used_txids.contains(input.client(), input.tx()) {
+                        return Err(TransactionError::DuplicateTxId {
+                            client: input.client(),
+                            tx: input.tx(),
+                        });
                     }
                     // we store the txid since the input is both valid, has not been used before
                     // This is based upon the assumption that a transaction that fails,
                     // still was valid
-                    self.used_txids.insert(input.tx());
+                    self.used_txids.insert(input.client(), input.tx());
+                }
+                TransactionType::Unlock if !self.allow_unlock => {
+                    return Err(TransactionError::UnlockNotAllowed);
+                }
+                TransactionType::Dispute
+                | TransactionType::Resolve
+                | TransactionType::Chargeback
+                | TransactionType::Void
+                    if self.disputes_disabled =>
+                {
+                    return Err(TransactionError::DisputesDisabled);
+                }
+                TransactionType::Dispute
+                | TransactionType::Resolve
+                | TransactionType::Chargeback
+                | TransactionType::Void => {
+                    self.check_tx_owner(input.client(), input.tx())?;
                 }
                 _ => {
                     // The other types of transactions should act upon existing txids, but also on
                     // the specific account, thus we check that per account
                 }
             }
-            let account = self.entry(input.client()).or_insert(Account::new());
+            let (client, tx) = (input.client(), input.tx());
+            let claims_txid = matches!(
+                input.r#type(),
+                TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Transfer
+            );
+            let outcome = if let TransactionType::Transfer = input.r#type() {
+                self.transfer(&input)
+            } else if let TransactionType::Inquiry = input.r#type()
+                && !self.accounts.contains_key(&client)
+            {
+                // there is nothing to read, and creating an account would change the state
+                Ok(())
+            } else {
+                let account = self.account_mut(client);
 
-            // By consuming the input, we are safeguarding that we cannot use the input twice by mistake
-            account.handle_transaction(input)?;
-            Ok(())
+                // By consuming the input, we are safeguarding that we cannot use the input twice by mistake
+                account.handle_transaction(input).map(|_| ())
+            };
+            if claims_txid && let Some(retention) = self.retention {
+                self.retain_recent(client, tx, retention);
+            }
+            outcome
+        } else if input.has_unexpected_amount() {
+            Err(TransactionError::UnexpectedAmount)
         } else {
             Err(TransactionError::MalformedInput)
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TransactionError {
     /// The transaction Input was not incorrectly formed and thus should fail
     MalformedInput,
+    /// A resolve, chargeback, inquiry, unlock or void had an amount, they only refer to a
+    /// transaction or the account and never carry one
+    UnexpectedAmount,
     /// There was not enough funds on the account to  handle the requested transaction
     NotEnoughAvailableFunds,
     /// The Transaction ID could not be found
-    MissingTxId,
+    MissingTxId { client: u16, tx: u32 },
     /// The transaction has already been handled
-    DuplicateTxId,
+    DuplicateTxId { client: u16, tx: u32 },
+    /// The client referred to a transaction of another client, `owner`
+    TxBelongsToOtherClient { client: u16, tx: u32, owner: u16 },
     /// Account has been locked, and thus no transaction should be valid
     AccountLocked,
+    /// Account has been frozen, so nothing can be withdrawn from it
+    AccountFrozen,
     /// The transaction was not valid for some reason
     InvalidTx,
     /// The transactio ID to dispute was invalid for some reason
@@ -81,163 +850,1166 @@ pub enum TransactionError {
     DisputeAlreadyExist,
     /// The Dispute has already been resolved one way or another
     DisputeAlreadyHandled,
+    /// The transaction history could not be written or read back
+    HistoryUnavailable,
+    /// The balance would go beyond what can be represented
+    BalanceOverflow,
+    /// An unlock was given to an `AccountStorage` not built to allow them
+    UnlockNotAllowed,
+    /// Resolving the dispute would release more than is held
+    HeldWouldGoNegative,
+    /// Only a deposit that has not been voided can be voided
+    InvalidTxForVoid,
+    /// The funds of the deposit to void have been withdrawn, moved, held or charged back
+    FundsAlreadyMoved,
+    /// A partial dispute was for more than the amount of the disputed transaction
+    DisputeExceedsTx,
+    /// There is no account for the client to close
+    UnknownAccount { client: u16 },
+    /// Only an account without any available or held funds can be closed
+    AccountNotEmpty,
+    /// An account cannot be closed while it has disputes that are not settled
+    OpenDisputes,
+    /// A dispute, resolve, chargeback or void was given to an `AccountStorage` built with
+    /// disputes disabled
+    DisputesDisabled,
+    /// The amount cannot be represented in the amount type of the accounts, see
+    /// `AccountStorageBuilder::build_with_amount`
+    UnrepresentableAmount,
 }
 
-#[derive(PartialEq, Eq)]
-pub enum DisputeState {
-    Started,
-    Reimbursed,
-    Resolved,
+impl TransactionError {
+    /// A short name of the kind of error without its context, for grouping errors
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransactionError::MalformedInput => "malformed_input",
+            TransactionError::UnexpectedAmount => "unexpected_amount",
+            TransactionError::NotEnoughAvailableFunds => "not_enough_available_funds",
+            TransactionError::MissingTxId { .. } => "missing_tx_id",
+            TransactionError::DuplicateTxId { .. } => "duplicate_tx_id",
+            TransactionError::TxBelongsToOtherClient { .. } => "tx_belongs_to_other_client",
+            TransactionError::AccountLocked => "account_locked",
+            TransactionError::AccountFrozen => "account_frozen",
+            TransactionError::InvalidTx => "invalid_tx",
+            TransactionError::InvalidTxForDispute => "invalid_tx_for_dispute",
+            TransactionError::MissingDisputeTx => "missing_dispute_tx",
+            TransactionError::DisputeAlreadyExist => "dispute_already_exist",
+            TransactionError::DisputeAlreadyHandled => "dispute_already_handled",
+            TransactionError::HistoryUnavailable => "history_unavailable",
+            TransactionError::BalanceOverflow => "balance_overflow",
+            TransactionError::UnlockNotAllowed => "unlock_not_allowed",
+            TransactionError::HeldWouldGoNegative => "held_would_go_negative",
+            TransactionError::InvalidTxForVoid => "invalid_tx_for_void",
+            TransactionError::FundsAlreadyMoved => "funds_already_moved",
+            TransactionError::DisputeExceedsTx => "dispute_exceeds_tx",
+            TransactionError::UnknownAccount { .. } => "unknown_account",
+            TransactionError::AccountNotEmpty => "account_not_empty",
+            TransactionError::OpenDisputes => "open_disputes",
+            TransactionError::DisputesDisabled => "disputes_disabled",
+            TransactionError::UnrepresentableAmount => "unrepresentable_amount",
+        }
+    }
 }
 
-impl DisputeState {
-    fn new() -> Self {
-        Self::Started
+impl fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            TransactionError::MissingTxId { client, tx } => {
+                return write!(
+                    f,
+                    "the transaction {} of client {} could not be found",
+                    tx, client
+                );
+            }
+            TransactionError::TxBelongsToOtherClient { client, tx, owner } => {
+                return write!(
+                    f,
+                    "client {} referred to the transaction {} of client {}",
+                    client, tx, owner
+                );
+            }
+            TransactionError::DuplicateTxId { client, tx } => {
+                return write!(
+                    f,
+                    "the transaction {} of client {} has already been handled",
+                    tx, client
+                );
+            }
+            TransactionError::UnknownAccount { client } => {
+                return write!(f, "client {} has no account", client);
+            }
+            TransactionError::MalformedInput => "the transaction input was incorrectly formed",
+            TransactionError::UnexpectedAmount => {
+                "the transaction had an amount, but its type never carries one"
+            }
+            TransactionError::NotEnoughAvailableFunds => {
+                "there was not enough funds on the account to handle the transaction"
+            }
+            TransactionError::AccountLocked => "the account has been locked",
+            TransactionError::AccountFrozen => "the account has been frozen",
+            TransactionError::InvalidTx => "the transaction was not valid",
+            TransactionError::InvalidTxForDispute => "the transaction cannot be disputed",
+            TransactionError::MissingDisputeTx => "the transaction is not under dispute",
+            TransactionError::DisputeAlreadyExist => "the dispute has already been started",
+            TransactionError::DisputeAlreadyHandled => {
+                "the dispute has already been resolved one way or another"
+            }
+            TransactionError::HistoryUnavailable => {
+                "the transaction history could not be written or read back"
+            }
+            TransactionError::BalanceOverflow => {
+                "the balance would go beyond what can be represented"
+            }
+            TransactionError::UnlockNotAllowed => "unlocking accounts is not allowed",
+            TransactionError::HeldWouldGoNegative => {
+                "resolving the dispute would release more than is held"
+            }
+            TransactionError::InvalidTxForVoid => "only deposits can be voided",
+            TransactionError::FundsAlreadyMoved => {
+                "the funds of the deposit are no longer available"
+            }
+            TransactionError::DisputeExceedsTx => {
+                "the disputed amount is more than that of the transaction"
+            }
+            TransactionError::AccountNotEmpty => "the account still has funds",
+            TransactionError::OpenDisputes => "the account has disputes that are not settled",
+            TransactionError::DisputesDisabled => "disputes are disabled",
+            TransactionError::UnrepresentableAmount => {
+                "the amount cannot be represented in the balances of the accounts"
+            }
+        };
+        f.write_str(message)
     }
 }
 
-pub struct Account {
-    /// amount of usable funds for withdrawal, trading, etc
-    available: FixedPoint,
+impl core::error::Error for TransactionError {}
 
-    /// amount of held funds for dispute
-    held: FixedPoint,
+impl From<HistoryError> for TransactionError {
+    fn from(_: HistoryError) -> Self {
+        TransactionError::HistoryUnavailable
+    }
+}
 
-    /// if the account is locked or not
-    locked: bool,
+/// Why two `AccountStorage` could not be merged
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeError {
+    /// The txids were used in both storages, in order
+    ConflictingTxIds(Vec<u32>),
+    /// One storage has txids that are unique among all clients, the other per client
+    TxIdScopeMismatch,
+    /// Summing the balances of the client would overflow
+    BalanceOverflow { client: u16 },
+    /// The history of an account could not be read or written
+    HistoryUnavailable,
+}
 
-    /// Just store an entire history of each transaction performed
-    tx_history: BTreeMap<u32, Input>,
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MergeError::ConflictingTxIds(txs) => {
+                write!(f, "the transactions {:?} were used in both storages", txs)
+            }
+            MergeError::TxIdScopeMismatch => write!(f, "the storages have different txid scopes"),
+            MergeError::BalanceOverflow { client } => {
+                write!(f, "the balances of client {} would overflow", client)
+            }
+            MergeError::HistoryUnavailable => write!(f, "the transaction history is unavailable"),
+        }
+    }
+}
 
-    /// disputes
-    disputes: BTreeMap<u32, DisputeState>,
+impl core::error::Error for MergeError {}
+
+impl From<HistoryError> for MergeError {
+    fn from(_: HistoryError) -> Self {
+        MergeError::HistoryUnavailable
+    }
 }
 
-impl<'a> Account {
-    /// Generates a new empty Account
-    pub fn new() -> Self {
-        Account {
-            available: FixedPoint::from_f64(0.0),
-            held: FixedPoint::from_f64(0.0),
-            locked: false,
-            disputes: BTreeMap::new(),
-            tx_history: BTreeMap::new(),
-        }
+/// Observes every transaction given to `AccountStorage::handle_transaction_with_sink`, such as
+/// for logging or metrics
+pub trait TransactionSink {
+    /// The transaction was handled successfully
+    fn on_applied(&mut self, input: &Input);
+    /// The transaction was rejected, without changing any account
+    fn on_rejected(&mut self, input: &Input, err: &TransactionError);
+}
+
+/// If a tx id may only be used once among all clients, or once for every client
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TxIdScope {
+    /// A deposit of client 2 is rejected if client 1 already used its tx id
+    #[default]
+    Global,
+    /// Every client has its own tx ids, as some upstreams reuse them between clients
+    PerClient,
+}
+
+/// A set of tx ids, as used to reject a tx id that is used twice
+pub trait TxIdSet {
+    fn contains(&self, tx: u32) -> bool;
+    /// Adds the id, returning false if it was already in the set
+    fn insert(&mut self, tx: u32) -> bool;
+    /// Removes the id, returning false if it was not in the set
+    fn remove(&mut self, tx: u32) -> bool;
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
     }
-    /// available
-    pub fn available(&self) -> FixedPoint {
-        self.available
+    /// Every id of the set, in no particular order
+    fn iter(&self) -> Box<dyn Iterator<Item = u32> + '_>;
+}
+
+#[cfg(feature = "std")]
+impl<S: BuildHasher> TxIdSet for HashSet<u32, S> {
+    fn contains(&self, tx: u32) -> bool {
+        HashSet::contains(self, &tx)
     }
 
-    pub fn contains_txid(&self, txid: u32) -> bool {
-        self.tx_history.contains_key(&txid)
+    fn insert(&mut self, tx: u32) -> bool {
+        HashSet::insert(self, tx)
     }
 
-    /// Get the account's held.
-    pub fn held(&self) -> FixedPoint {
-        self.held
+    fn remove(&mut self, tx: u32) -> bool {
+        HashSet::remove(self, &tx)
     }
-    pub fn total(&self) -> FixedPoint {
-        self.held + self.available
+
+    fn len(&self) -> usize {
+        HashSet::len(self)
     }
 
-    fn lock(&mut self) {
-        self.locked = true;
+    fn iter(&self) -> Box<dyn Iterator<Item = u32> + '_> {
+        Box::new(HashSet::iter(self).copied())
     }
+}
 
-    /// Handle a transaction request on this account
-    pub fn handle_transaction(&mut self, transaction: Input) -> Result<(), TransactionError> {
-        if !transaction.valid() {
-            return Err(TransactionError::InvalidTx);
-        }
-        if self.locked {
-            // This is probably a much more complex case, since an account probably can have multiple
-            // active disputes. But I also feel like trying to handle this without careful consideration
-            // could be quite exploitable, which is unwanted. So I'll play it safe here, and just not handle more transactions
-            // after a chargeback has occured
-            return Err(TransactionError::AccountLocked);
-        }
+/// A set of tx ids laid out like a roaring bitmap. The ids are grouped by their upper 16
+/// bits, and each group is kept as a sorted array of the lower bits while it has few ids,
+/// and as a bitmap of all 65536 of them once that is smaller. Ids from a dense range then
+/// take little more than a bit each, where a `HashSet` takes several bytes
+#[derive(Debug, Clone, Default)]
+pub struct TxIdBitmap {
+    containers: BTreeMap<u16, Container>,
+    len: usize,
+}
 
-        let tx_res = match transaction.r#type() {
-            TransactionType::Deposit => {
-                // Safe because of the validity check on the transaction
-                let amount = transaction.amount_as_fp().unwrap();
-                self.deposit(amount);
+/// The ids of a `TxIdBitmap` that share their upper 16 bits
+#[derive(Debug, Clone)]
+enum Container {
+    /// The sorted lower bits of at most `Container::ARRAY_MAX` ids
+    Array(Vec<u16>),
+    Bitmap(Box<[u64; 1024]>),
+}
 
-                self.tx_history.insert(transaction.tx(), transaction);
-                Ok(())
-            }
-            TransactionType::Withdrawal => {
-                // Safe because of the validity check on the transaction
-                let amount = transaction.amount_as_fp().unwrap();
-                self.withdraw(amount)
-            }
-            TransactionType::Dispute => {
-                // we need to look back into all of the history related to this client ( and this client only ),
-                // to validate wheter the TX exists, and then we need to hold the amount found in that tx
-                self.dispute(transaction.tx())
-            }
-            TransactionType::Resolve => {
-                // We shall unlock the held funds, if the held funds exist ofcourse
-                // If the held funds are already spent, for example by a withdrawal, then a dispute
-                self.resolve(transaction.tx())
-            }
-            TransactionType::Chargeback => self.chargeback(transaction.tx()),
-        };
+impl Container {
+    /// An array of this many ids takes as much memory as a bitmap
+    const ARRAY_MAX: usize = 4096;
 
-        tx_res
+    fn contains(&self, low: u16) -> bool {
+        match self {
+            Container::Array(ids) => ids.binary_search(&low).is_ok(),
+            Container::Bitmap(bits) => bits[usize::from(low) / 64] & (1 << (low % 64)) != 0,
+        }
     }
 
-    fn deposit(&mut self, amount: FixedPoint) {
-        self.available += amount;
+    fn insert(&mut self, low: u16) -> bool {
+        let ids = match self {
+            Container::Array(ids) => ids,
+            Container::Bitmap(bits) => {
+                let added = bits[usize::from(low) / 64] & (1 << (low % 64)) == 0;
+                bits[usize::from(low) / 64] |= 1 << (low % 64);
+                return added;
+            }
+        };
+        let Err(position) = ids.binary_search(&low) else {
+            return false;
+        };
+        if ids.len() < Self::ARRAY_MAX {
+            ids.insert(position, low);
+            return true;
+        }
+        let mut bits = Box::new([0u64; 1024]);
+        for id in ids.iter().copied().chain([low]) {
+            bits[usize::from(id) / 64] |= 1 << (id % 64);
+        }
+        *self = Container::Bitmap(bits);
+        true
     }
 
-    fn withdraw(&mut self, amount: FixedPoint) -> Result<(), TransactionError> {
-        if self.locked() {
-            return Err(TransactionError::AccountLocked);
-        }
-        if self.available >= amount {
-            self.available -= amount;
-            Ok(())
-        } else {
-            Err(TransactionError::NotEnoughAvailableFunds)
+    fn remove(&mut self, low: u16) -> bool {
+        match self {
+            Container::Array(ids) => match ids.binary_search(&low) {
+                Ok(position) => {
+                    ids.remove(position);
+                    true
+                }
+                Err(_) => false,
+            },
+            Container::Bitmap(bits) => {
+                let removed = bits[usize::from(low) / 64] & (1 << (low % 64)) != 0;
+                bits[usize::from(low) / 64] &= !(1 << (low % 64));
+                removed
+            }
         }
     }
 
-    fn chargeback(&mut self, tx: u32) -> Result<(), TransactionError> {
+    fn is_empty(&self) -> bool {
+        match self {
+            Container::Array(ids) => ids.is_empty(),
+            Container::Bitmap(bits) => bits.iter().all(|word| *word == 0),
+        }
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = u16> + '_> {
+        match self {
+            Container::Array(ids) => Box::new(ids.iter().copied()),
+            Container::Bitmap(_) => Box::new((0..=u16::MAX).filter(|low| self.contains(*low))),
+        }
+    }
+}
+
+impl TxIdBitmap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn split(tx: u32) -> (u16, u16) {
+        ((tx >> 16) as u16, tx as u16)
+    }
+}
+
+impl TxIdSet for TxIdBitmap {
+    fn contains(&self, tx: u32) -> bool {
+        let (high, low) = Self::split(tx);
+        self.containers
+            .get(&high)
+            .is_some_and(|container| container.contains(low))
+    }
+
+    fn insert(&mut self, tx: u32) -> bool {
+        let (high, low) = Self::split(tx);
+        let added = self
+            .containers
+            .entry(high)
+            .or_insert_with(|| Container::Array(Vec::new()))
+            .insert(low);
+        self.len += usize::from(added);
+        added
+    }
+
+    fn remove(&mut self, tx: u32) -> bool {
+        let (high, low) = Self::split(tx);
+        let Some(container) = self.containers.get_mut(&high) else {
+            return false;
+        };
+        let removed = container.remove(low);
+        if container.is_empty() {
+            self.containers.remove(&high);
+        }
+        self.len -= usize::from(removed);
+        removed
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    /// In ascending order
+    fn iter(&self) -> Box<dyn Iterator<Item = u32> + '_> {
+        Box::new(self.containers.iter().flat_map(|(high, container)| {
+            container
+                .iter()
+                .map(move |low| (u32::from(*high) << 16) | u32::from(low))
+        }))
+    }
+}
+
+impl FromIterator<u32> for TxIdBitmap {
+    fn from_iter<I: IntoIterator<Item = u32>>(iter: I) -> Self {
+        let mut set = Self::new();
+        for tx in iter {
+            set.insert(tx);
+        }
+        set
+    }
+}
+
+/// The tx ids that have been used, by scope
+enum UsedTxIds {
+    Global(TxIdBitmap),
+    PerClient(FxHashSet<(u16, u32)>),
+}
+
+impl UsedTxIds {
+    fn new(scope: TxIdScope) -> Self {
+        match scope {
+            TxIdScope::Global => Self::Global(TxIdBitmap::new()),
+            TxIdScope::PerClient => Self::PerClient(FxHashSet::default()),
+        }
+    }
+
+    fn contains(&self, client: u16, tx: u32) -> bool {
+        match self {
+            Self::Global(ids) => ids.contains(tx),
+            Self::PerClient(ids) => ids.contains(&(client, tx)),
+        }
+    }
+
+    fn insert(&mut self, client: u16, tx: u32) {
+        match self {
+            Self::Global(ids) => ids.insert(tx),
+            Self::PerClient(ids) => ids.insert((client, tx)),
+        };
+    }
+
+    fn remove(&mut self, client: u16, tx: u32) {
+        match self {
+            Self::Global(ids) => ids.remove(tx),
+            Self::PerClient(ids) => ids.remove(&(client, tx)),
+        };
+    }
+
+    /// Adds the ids of the other set, unless any of them is in both or the scopes differ, in
+    /// which case nothing is added
+    fn merge(&mut self, other: UsedTxIds) -> Result<(), MergeError> {
+        let mut conflicts: Vec<u32> = match (&*self, &other) {
+            (Self::Global(ours), Self::Global(theirs)) => {
+                theirs.iter().filter(|tx| ours.contains(*tx)).collect()
+            }
+            (Self::PerClient(ours), Self::PerClient(theirs)) => {
+                ours.intersection(theirs).map(|(_, tx)| *tx).collect()
+            }
+            _ => return Err(MergeError::TxIdScopeMismatch),
+        };
+        if !conflicts.is_empty() {
+            conflicts.sort_unstable();
+            conflicts.dedup();
+            return Err(MergeError::ConflictingTxIds(conflicts));
+        }
+        match (self, other) {
+            (Self::Global(ours), Self::Global(theirs)) => {
+                for tx in theirs.iter() {
+                    ours.insert(tx);
+                }
+            }
+            (Self::PerClient(ours), Self::PerClient(theirs)) => ours.extend(theirs),
+            _ => unreachable!("the scopes were compared above"),
+        }
+        Ok(())
+    }
+}
+
+/// The work given to a shard by `AccountStorageBuilder::process_parallel`
+#[cfg(feature = "std")]
+enum ShardMessage {
+    Transaction(Input),
+    /// The side of the sender of a transfer to another shard, which is answered with the
+    /// outcome of `check_transfer_sender`
+    CheckSender(Input, mpsc::SyncSender<Result<(), TransactionError>>),
+    /// The side of the receiver, answered with the outcome of `check_transfer_receiver`
+    CheckReceiver(Input, mpsc::SyncSender<Result<(), TransactionError>>),
+    /// Applies the checked transfer to the sender
+    Send(Input),
+    /// Applies the checked transfer to the receiver
+    Receive(Input),
+    /// Answered with whether the tx is in the history of the client
+    ContainsTx(u16, u32, mpsc::SyncSender<bool>),
+}
+
+/// Asks the shard whether the tx is in the history of the client, after everything that was
+/// handed to it before. Returns None if the shard is gone
+#[cfg(feature = "std")]
+fn tx_in_history(shard: &mpsc::SyncSender<ShardMessage>, client: u16, tx: u32) -> Option<bool> {
+    let (reply, answer) = mpsc::sync_channel(1);
+    shard
+        .send(ShardMessage::ContainsTx(client, tx, reply))
+        .ok()?;
+    answer.recv().ok()
+}
+
+/// Moves the funds of a transfer between the accounts of two shards, only once both have
+/// checked their side. Waiting for the answers means that neither shard handles anything in
+/// between, so that the transfer is applied at the same point of the input as it would be by
+/// `handle_transaction`. Returns false if a shard is gone
+#[cfg(feature = "std")]
+fn transfer_between_shards(
+    sender: &mpsc::SyncSender<ShardMessage>,
+    receiver: &mpsc::SyncSender<ShardMessage>,
+    input: Input,
+) -> bool {
+    let check = |shard: &mpsc::SyncSender<ShardMessage>, message: fn(_, _) -> ShardMessage| {
+        let (reply, answer) = mpsc::sync_channel(1);
+        shard.send(message(input.clone(), reply)).ok()?;
+        answer.recv().ok()
+    };
+    let Some(sendable) = check(sender, ShardMessage::CheckSender) else {
+        return false;
+    };
+    if sendable.is_err() {
+        return true;
+    }
+    let Some(receivable) = check(receiver, ShardMessage::CheckReceiver) else {
+        return false;
+    };
+    if receivable.is_err() {
+        return true;
+    }
+    receiver.send(ShardMessage::Receive(input.clone())).is_ok()
+        && sender.send(ShardMessage::Send(input)).is_ok()
+}
+
+/// The state of an `AccountStorage`, see `AccountStorage::to_snapshot`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct Snapshot<A: AmountOps = FixedPoint> {
+    pub accounts: BTreeMap<u16, AccountSnapshot<A>>,
+    #[serde(default)]
+    pub txid_scope: TxIdScope,
+    /// every deposit, withdrawal and transfer id that has been seen, with `TxIdScope::Global`
+    pub used_txids: BTreeSet<u32>,
+    /// the same, by client, with `TxIdScope::PerClient`
+    #[serde(default)]
+    pub used_client_txids: BTreeSet<(u16, u32)>,
+    /// the configuration of the storage, see `AccountStorageBuilder`
+    #[serde(default)]
+    pub retention: Option<usize>,
+    #[serde(default)]
+    pub allow_unlock: bool,
+    #[serde(default)]
+    pub dispute_policy: DisputePolicy,
+    #[serde(default)]
+    pub chargeback_policy: ChargebackPolicy,
+    #[serde(default)]
+    pub locked_deposit_policy: LockedDepositPolicy,
+    #[serde(default)]
+    pub disputes_disabled: bool,
+}
+
+/// The state of a single `Account`. The whole transaction history is included, since any
+/// of it can still be disputed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct AccountSnapshot<A: AmountOps = FixedPoint> {
+    pub available: A,
+    pub held: A,
+    pub status: AccountStatus,
+    pub reactivated: bool,
+    pub disputes: BTreeMap<u32, DisputeState<A>>,
+    pub history: Vec<Input>,
+    pub idempotency_keys: BTreeMap<String, Result<(), TransactionError>>,
+    pub dispute_policy: DisputePolicy,
+    #[serde(default)]
+    pub chargeback_policy: ChargebackPolicy,
+    #[serde(default)]
+    pub locked_deposit_policy: LockedDepositPolicy,
+    #[serde(default)]
+    pub transaction_count: u64,
+    /// the txids in the retention window, oldest first, with `AccountStorageBuilder::retention`
+    #[serde(default)]
+    pub recent_txids: Vec<u32>,
+}
+
+/// What an `Account` looks like from the outside, for API responses, see `Account::view`.
+/// Unlike a snapshot it leaves out how the account is configured
+#[derive(Debug, Clone, Serialize)]
+#[serde(bound = "")]
+pub struct AccountView<A: AmountOps = FixedPoint> {
+    pub available: A,
+    pub held: A,
+    pub total: A,
+    pub locked: bool,
+    pub disputes: BTreeMap<u32, DisputeState<A>>,
+    /// the transactions that can be disputed, in the order of their tx
+    pub tx_history: Vec<Input>,
+}
+
+/// Every account of an `AccountStorage`, by client, see `AccountStorage::view`
+#[derive(Debug, Clone, Serialize)]
+#[serde(bound = "")]
+pub struct StorageView<A: AmountOps = FixedPoint> {
+    pub accounts: BTreeMap<u16, AccountView<A>>,
+}
+
+/// A transaction applied to an account, with the balances right after it, see
+/// `Account::audit_log`
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEntry<A: AmountOps = FixedPoint> {
+    pub r#type: TransactionType,
+    pub tx: u32,
+    /// the amount of the transaction itself, none for those that refer to another
+    pub amount: Option<A>,
+    pub available: A,
+    pub held: A,
+}
+
+/// How a transaction changed the balances of an account
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct BalanceDelta<A: AmountOps = FixedPoint> {
+    /// the change to the available funds
+    pub available: A,
+    /// the change to the held funds
+    pub held: A,
+    /// if the account was locked or unlocked, the new state
+    pub locked: Option<bool>,
+}
+
+/// Inconsistencies in the account state found by `AccountStorage::check_integrity`
+#[derive(Debug, PartialEq, Eq)]
+pub enum IntegrityIssue {
+    /// A dispute references a transaction that is not in the history of the account
+    DisputeWithoutTx { client: u16, tx: u32 },
+    /// A dispute has been charged back, but the account is not locked
+    ReimbursedButUnlocked { client: u16, tx: u32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub enum DisputeState<A: AmountOps = FixedPoint> {
+    /// The dispute is ongoing, with `held` of the transaction held, which is less than its
+    /// amount when only part of it is disputed
+    Started {
+        held: A,
+    },
+    Reimbursed,
+    Resolved,
+}
+
+impl<A: AmountOps> DisputeState<A> {
+    /// If the dispute is not yet resolved or charged back
+    pub fn is_active(&self) -> bool {
+        matches!(self, DisputeState::Started { .. })
+    }
+}
+
+/// What happens when a disputed deposit has already been partly withdrawn, so holding it
+/// would take more than is available
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DisputePolicy {
+    /// The whole amount is held anyway, leaving available negative
+    #[default]
+    AllowNegative,
+    /// The dispute fails with `TransactionError::NotEnoughAvailableFunds`
+    RequireAvailable,
+}
+
+/// The status of an account, only an active account handles every transaction
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum AccountStatus {
+    #[default]
+    Active,
+    /// Nothing can be withdrawn or transferred away, but deposits and disputes are handled
+    Frozen,
+    /// No transactions are handled, apart from inquiries and unlocks
+    Locked,
+}
+
+/// If a locked account still accepts deposits, such as to cover a negative balance left by a
+/// chargeback. Withdrawals and disputes are rejected either way
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LockedDepositPolicy {
+    /// Nothing but inquiries and unlocks are handled on a locked account
+    #[default]
+    Reject,
+    /// Deposits and incoming transfers are handled on a locked account as well
+    Accept,
+}
+
+/// What a chargeback does to the status of the account
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChargebackPolicy {
+    /// The account is locked, so nothing more is handled until it is unlocked
+    #[default]
+    Lock,
+    /// The account is frozen, so that the client can still deposit and settle disputes
+    Freeze,
+}
+
+#[derive(Default)]
+pub struct Account<A: AmountOps = FixedPoint> {
+    /// amount of usable funds for withdrawal, trading, etc
+    available: A,
+
+    /// amount of held funds for dispute
+    held: A,
+
+    /// if the account is active, frozen or locked
+    status: AccountStatus,
+
+    /// if the account has been unlocked after being locked
+    reactivated: bool,
+
+    /// Just store an entire history of each transaction performed
+    tx_history: TxHistory,
+
+    /// disputes
+    disputes: BTreeMap<u32, DisputeState<A>>,
+
+    /// outcome of every transaction that came with an idempotency key
+    idempotency_keys: FxHashMap<String, Result<(), TransactionError>>,
+
+    /// if disputes may push available below zero
+    dispute_policy: DisputePolicy,
+
+    /// what a chargeback does to the status
+    chargeback_policy: ChargebackPolicy,
+
+    /// if deposits are accepted while locked
+    locked_deposit_policy: LockedDepositPolicy,
+
+    /// txids in the order they were used, only kept with a retention window
+    recent_txids: VecDeque<u32>,
+
+    /// how many transactions have been applied successfully
+    transaction_count: u64,
+
+    /// the applied transactions with the resulting balances, only kept when enabled
+    audit_log: Option<Vec<AuditEntry<A>>>,
+}
+
+impl Account {
+    /// Generates a new empty Account
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<A: AmountOps> Account<A> {
+    /// Generates a new empty Account keeping its transactions in the given history
+    pub fn with_history(tx_history: TxHistory) -> Self {
+        Account {
+            tx_history,
+            ..Self::default()
+        }
+    }
+    /// The balances, status, disputes and history of the account, see `AccountView`
+    pub fn view(&self) -> Result<AccountView<A>, HistoryError> {
+        Ok(AccountView {
+            available: self.available,
+            held: self.held,
+            total: self.total(),
+            locked: self.locked(),
+            disputes: self.disputes.clone(),
+            tx_history: self.tx_history.inputs()?,
+        })
+    }
+
+    fn to_snapshot(&self) -> Result<AccountSnapshot<A>, HistoryError> {
+        Ok(AccountSnapshot {
+            available: self.available,
+            held: self.held,
+            status: self.status,
+            reactivated: self.reactivated,
+            disputes: self.disputes.clone(),
+            history: self.tx_history.inputs()?,
+            idempotency_keys: self
+                .idempotency_keys
+                .iter()
+                .map(|(key, outcome)| (key.clone(), outcome.clone()))
+                .collect(),
+            dispute_policy: self.dispute_policy,
+            chargeback_policy: self.chargeback_policy,
+            locked_deposit_policy: self.locked_deposit_policy,
+            transaction_count: self.transaction_count,
+            recent_txids: self.recent_txids.iter().copied().collect(),
+        })
+    }
+
+    /// Restores a snapshot, with the transactions of its history put into `tx_history`
+    fn from_snapshot(snapshot: AccountSnapshot<A>, mut tx_history: TxHistory) -> Self {
+        for input in snapshot.history {
+            // a fresh memory history has nowhere to fail writing to
+            tx_history
+                .insert(input)
+                .expect("memory history cannot fail");
+        }
+        Account {
+            available: snapshot.available,
+            held: snapshot.held,
+            status: snapshot.status,
+            reactivated: snapshot.reactivated,
+            tx_history,
+            disputes: snapshot.disputes,
+            idempotency_keys: snapshot.idempotency_keys.into_iter().collect(),
+            dispute_policy: snapshot.dispute_policy,
+            chargeback_policy: snapshot.chargeback_policy,
+            locked_deposit_policy: snapshot.locked_deposit_policy,
+            recent_txids: snapshot.recent_txids.into(),
+            transaction_count: snapshot.transaction_count,
+            audit_log: None,
+        }
+    }
+
+    /// Removes the oldest txids until at most `retention` remain, skipping those under an active
+    /// dispute, together with their history and settled disputes. Returns the removed txids
+    fn prune_history(&mut self, retention: usize) -> Vec<u32> {
+        let mut pruned = Vec::new();
+        while self.recent_txids.len() > retention {
+            let Some(position) = self
+                .recent_txids
+                .iter()
+                .position(|tx| !self.disputes.get(tx).is_some_and(DisputeState::is_active))
+            else {
+                break;
+            };
+            if let Some(tx) = self.recent_txids.remove(position) {
+                self.tx_history.remove(tx);
+                self.disputes.remove(&tx);
+                pruned.push(tx);
+            }
+        }
+        pruned
+    }
+
+    /// Adds the balances, history and disputes of the other account of the same client, an
+    /// idempotency key in both keeps the outcome of this account
+    fn merge(&mut self, other: Account<A>) -> Result<(), HistoryError> {
+        for input in other.tx_history.inputs()? {
+            self.tx_history.insert(input)?;
+        }
+        self.available += other.available;
+        self.held += other.held;
+        self.status = self.status.max(other.status);
+        self.reactivated |= other.reactivated;
+        self.disputes.extend(other.disputes);
+        for (key, outcome) in other.idempotency_keys {
+            self.idempotency_keys.entry(key).or_insert(outcome);
+        }
+        self.recent_txids.extend(other.recent_txids);
+        self.transaction_count += other.transaction_count;
+        if let (Some(ours), Some(theirs)) = (&mut self.audit_log, other.audit_log) {
+            // the shards were processed independently, so there is no order between them
+            ours.extend(theirs);
+        }
+        Ok(())
+    }
+
+    /// Sets if a chargeback locks or freezes the account
+    pub fn with_chargeback_policy(mut self, chargeback_policy: ChargebackPolicy) -> Self {
+        self.chargeback_policy = chargeback_policy;
+        self
+    }
+
+    /// Sets if deposits are accepted while the account is locked
+    pub fn with_locked_deposit_policy(
+        mut self,
+        locked_deposit_policy: LockedDepositPolicy,
+    ) -> Self {
+        self.locked_deposit_policy = locked_deposit_policy;
+        self
+    }
+
+    /// If funds can be added to the account in its current status
+    fn accepts_deposits(&self) -> bool {
+        self.status != AccountStatus::Locked
+            || self.locked_deposit_policy == LockedDepositPolicy::Accept
+    }
+
+    /// Sets if disputes may leave the available funds negative
+    pub fn with_dispute_policy(mut self, dispute_policy: DisputePolicy) -> Self {
+        self.dispute_policy = dispute_policy;
+        self
+    }
+
+    /// available
+    pub fn available(&self) -> A {
+        self.available
+    }
+
+    /// The amount that could be withdrawn right now, nothing can be withdrawn from a locked
+    /// account or one that is already below zero
+    pub fn withdrawable(&self) -> A {
+        if self.locked() || self.available.is_negative() {
+            A::ZERO
+        } else {
+            self.available
+        }
+    }
+
+    /// Counts a transaction that has been applied, and records it in the audit log if the
+    /// account keeps one
+    fn applied(&mut self, input: &Input) {
+        self.transaction_count += 1;
+        let (available, held) = (self.available, self.held);
+        if let Some(audit_log) = &mut self.audit_log {
+            audit_log.push(AuditEntry {
+                r#type: *input.r#type(),
+                tx: input.tx(),
+                amount: input.amount().and_then(A::from_fixed_point),
+                available,
+                held,
+            });
+        }
+    }
+
+    /// Every transaction applied to the account, in order, inquiries included, if the account
+    /// keeps an audit log, see `AccountStorageBuilder::audit_log`
+    pub fn audit_log(&self) -> &[AuditEntry<A>] {
+        self.audit_log.as_deref().unwrap_or_default()
+    }
+
+    /// Keeps an audit log of the transactions applied to the account
+    pub fn with_audit_log(mut self) -> Self {
+        self.audit_log = Some(Vec::new());
+        self
+    }
+
+    /// How many transactions have been applied to the account, a transfer counts for both
+    /// of its accounts while rejected transactions do not count at all
+    pub fn transaction_count(&self) -> u64 {
+        self.transaction_count
+    }
+
+    pub fn contains_txid(&self, txid: u32) -> bool {
+        self.tx_history.contains(txid)
+    }
+
+    /// Get the account's held.
+    pub fn held(&self) -> A {
+        self.held
+    }
+    pub fn total(&self) -> A {
+        self.held + self.available
+    }
+
+    /// If the account has any disputes that are not yet resolved or charged back
+    pub fn has_active_disputes(&self) -> bool {
+        self.disputes.values().any(DisputeState::is_active)
+    }
+
+    /// Every dispute of the account, by the tx that was disputed
+    pub fn disputes(&self) -> &BTreeMap<u32, DisputeState<A>> {
+        &self.disputes
+    }
+
+    /// The state of the dispute of the tx, None if it has never been disputed
+    pub fn dispute_state(&self, tx: u32) -> Option<&DisputeState<A>> {
+        self.disputes.get(&tx)
+    }
+
+    /// An account is cold when there is nothing left on it that can change, no funds,
+    /// no lock and no ongoing disputes
+    fn is_cold(&self) -> bool {
+        self.available.is_zero()
+            && self.held.is_zero()
+            && !self.locked()
+            && !self.has_active_disputes()
+    }
+
+    /// Locks or freezes the account after a chargeback, a locked account is never only frozen
+    fn lock(&mut self) {
+        let status = match self.chargeback_policy {
+            ChargebackPolicy::Lock => AccountStatus::Locked,
+            ChargebackPolicy::Freeze => AccountStatus::Frozen,
+        };
+        self.status = self.status.max(status);
+    }
+
+    /// Reactivates a locked or frozen account, such as after a chargeback has been reviewed,
+    /// so that it handles transactions again
+    pub fn unlock(&mut self) {
+        if self.locked() {
+            self.status = AccountStatus::Active;
+            self.reactivated = true;
+        }
+    }
+
+    /// Handle a transaction request on this account, returning how the balances changed
+    pub fn handle_transaction(
+        &mut self,
+        transaction: Input,
+    ) -> Result<BalanceDelta<A>, TransactionError> {
+        let (available, held, locked) = (self.available, self.held, self.locked());
+        let observed = self.audit_log.is_some().then(|| transaction.clone());
+        self.apply(transaction)?;
+        match &observed {
+            Some(input) => self.applied(input),
+            None => self.transaction_count += 1,
+        }
+        Ok(BalanceDelta {
+            available: self.available - available,
+            held: self.held - held,
+            locked: (self.locked() != locked).then_some(self.locked()),
+        })
+    }
+
+    fn apply(&mut self, transaction: Input) -> Result<(), TransactionError> {
+        if transaction.has_unexpected_amount() {
+            return Err(TransactionError::UnexpectedAmount);
+        }
+        if !transaction.valid() {
+            return Err(TransactionError::InvalidTx);
+        }
+        match transaction.r#type() {
+            // Reading the balance never changes the account, so it is fine even when locked
+            TransactionType::Inquiry => {
+                #[cfg(feature = "trace")]
+                trace::event("inquiry", transaction.client(), transaction.tx(), None);
+                return Ok(());
+            }
+            TransactionType::Unlock => {
+                self.unlock();
+                return Ok(());
+            }
+            _ => {}
+        }
+        // a frozen account only stops the funds from leaving, which the withdrawal checks
+        let accepted_deposit =
+            matches!(transaction.r#type(), TransactionType::Deposit) && self.accepts_deposits();
+        if self.status == AccountStatus::Locked && !accepted_deposit {
+            // This is probably a much more complex case, since an account probably can have multiple
+            // active disputes. But I also feel like trying to handle this without careful consideration
+            // could be quite exploitable, which is unwanted. So I'll play it safe here, and just not handle more transactions
+            // after a chargeback has occured
+            return Err(TransactionError::AccountLocked);
+        }
+
+        match transaction.r#type() {
+            TransactionType::Deposit => {
+                // Safe because of the validity check on the transaction
+                let amount = amount_of(transaction.amount_as_fp().unwrap())?;
+                self.try_deposit(amount)?;
+                if let Err(e) = self.tx_history.insert(transaction) {
+                    // a deposit should never be applied without being disputable
+                    self.available -= amount;
+                    return Err(e.into());
+                }
+                Ok(())
+            }
+            TransactionType::Withdrawal => {
+                // Safe because of the validity check on the transaction
+                let amount = amount_of(transaction.amount_as_fp().unwrap())?;
+                self.withdraw(amount)?;
+                if let Err(e) = self.tx_history.insert(transaction) {
+                    // withdrawals can be disputed as well, so the same goes for them
+                    self.available += amount;
+                    return Err(e.into());
+                }
+                Ok(())
+            }
+            TransactionType::Dispute => {
+                // we need to look back into all of the history related to this client ( and this client only ),
+                // to validate wheter the TX exists, and then we need to hold the amount found in that tx,
+                // or the part of it that is disputed
+                let disputed = transaction.amount().map(amount_of).transpose()?;
+                self.dispute(transaction.client(), transaction.tx(), disputed)
+            }
+            TransactionType::Resolve => {
+                // We shall unlock the held funds, if the held funds exist ofcourse
+                // If the held funds are already spent, for example by a withdrawal, then a dispute
+                self.resolve(transaction.client(), transaction.tx())
+            }
+            TransactionType::Chargeback => self.chargeback(transaction.client(), transaction.tx()),
+            TransactionType::Void => self.void(transaction.client(), transaction.tx()),
+            // already handled before checking the lock
+            TransactionType::Inquiry | TransactionType::Unlock => Ok(()),
+            // a transfer changes two accounts, so only `AccountStorage` can handle it
+            TransactionType::Transfer => Err(TransactionError::InvalidTx),
+        }
+    }
+
+    /// Deposits unless the balance would overflow, in which case nothing is changed
+    fn try_deposit(&mut self, amount: A) -> Result<(), TransactionError> {
+        self.available = self.credited(amount)?;
+        Ok(())
+    }
+
+    /// The available funds after crediting the amount, fails if either those or the total
+    /// with the held funds would overflow
+    fn credited(&self, amount: A) -> Result<A, TransactionError> {
+        let available = self
+            .available
+            .checked_add(amount)
+            .ok_or(TransactionError::BalanceOverflow)?;
+        available
+            .checked_add(self.held)
+            .ok_or(TransactionError::BalanceOverflow)?;
+        Ok(available)
+    }
+
+    fn withdraw(&mut self, amount: A) -> Result<(), TransactionError> {
+        match self.status {
+            AccountStatus::Active => {}
+            AccountStatus::Frozen => return Err(TransactionError::AccountFrozen),
+            AccountStatus::Locked => return Err(TransactionError::AccountLocked),
+        }
+        if self.available >= amount {
+            self.available -= amount;
+            Ok(())
+        } else {
+            Err(TransactionError::NotEnoughAvailableFunds)
+        }
+    }
+
+    /// Voids a deposit, taking its funds out of available again. The deposit is replaced by the
+    /// void in the history, so it can neither be disputed nor voided again
+    fn void(&mut self, client: u16, tx: u32) -> Result<(), TransactionError> {
         let input = self
             .tx_history
+            .get(tx)?
+            .ok_or(TransactionError::MissingTxId { client, tx })?;
+        let (TransactionType::Deposit, Some(amount)) = (input.r#type(), input.amount_as_fp())
+        else {
+            return Err(TransactionError::InvalidTxForVoid);
+        };
+        let amount: A = amount_of(amount)?;
+        // held funds of an ongoing dispute are not available either, and a charged back
+        // deposit has had its funds taken already, only a resolved dispute leaves them
+        let disputed = self
+            .disputes
             .get(&tx)
-            .ok_or(TransactionError::MissingTxId)?;
+            .is_some_and(|dispute| !matches!(dispute, DisputeState::Resolved));
+        if disputed || self.available < amount {
+            return Err(TransactionError::FundsAlreadyMoved);
+        }
+
+        self.tx_history
+            .insert(Input::new(TransactionType::Void, client, tx, None))?;
+        self.available -= amount;
+        Ok(())
+    }
+
+    /// Charges back a dispute, releasing its held funds. If held does not cover them, all of
+    /// held is released and the residual is taken from available, which may leave it negative,
+    /// so that a chargeback always removes the whole disputed amount
+    fn chargeback(&mut self, client: u16, tx: u32) -> Result<(), TransactionError> {
+        let input = self
+            .tx_history
+            .get(tx)?
+            .ok_or(TransactionError::MissingTxId { client, tx })?;
 
         let dispute = self
             .disputes
             .get_mut(&tx)
             .ok_or(TransactionError::MissingDisputeTx)?;
 
-        // println!("checking dispute state input {:?}", input);
-        if *dispute == DisputeState::Started {
-            // println!("dispute has started");
-            if let Some(amount) = input.amount_as_fp() {
-                // println!("the tx in question has an amount");
-                if self.held <= amount {
-                    println!("the held amount covers the dispute reimbursement");
-                    self.held -= amount;
-                }
+        if let DisputeState::Started { held: amount } = *dispute {
+            // the held funds of this dispute are always released, other disputes can keep
+            // theirs, but held should never go below zero
+            let released = self.held.min(amount);
+            self.held -= released;
+            self.available -= amount - released;
+            if let TransactionType::Withdrawal = input.r#type() {
+                // the withdrawal is reversed, so the client gets the funds back
+                self.available += amount;
             }
             *dispute = DisputeState::Reimbursed;
             self.lock();
+            #[cfg(feature = "trace")]
+            trace::event("chargeback", client, tx, None);
             Ok(())
         } else {
             Err(TransactionError::DisputeAlreadyHandled)
         }
     }
 
-    fn resolve(&mut self, tx: u32) -> Result<(), TransactionError> {
+    /// Resolves a dispute, releasing its held funds. Fails with
+    /// `TransactionError::HeldWouldGoNegative`, changing nothing, if held does not cover them
+    fn resolve(&mut self, client: u16, tx: u32) -> Result<(), TransactionError> {
         let input = self
             .tx_history
-            .get(&tx)
-            .ok_or(TransactionError::MissingTxId)?;
+            .get(tx)?
+            .ok_or(TransactionError::MissingTxId { client, tx })?;
 
         // fetch the the tx under dispute, apply the reverse if state is disputed
         let dispute = self
@@ -245,61 +2017,107 @@ impl<'a> Account {
             .get_mut(&tx)
             .ok_or(TransactionError::MissingDisputeTx)?;
 
-        if *dispute == DisputeState::Started {
-            if let Some(amount) = input.amount_as_fp() {
-                let heldres = self.held - amount;
-                if heldres < FixedPoint::from_f64(0.0) {
-                    eprintln!(
-                        "resolved a dispute resulting in negative held amount for TX: {}",
-                        tx
-                    );
-                }
-                self.held = heldres;
+        if let DisputeState::Started { held: amount } = *dispute {
+            // held can never go below zero, so nothing is changed if it would
+            let heldres = self.held - amount;
+            if heldres.is_negative() {
+                return Err(TransactionError::HeldWouldGoNegative);
+            }
+            self.held = heldres;
+            if let TransactionType::Deposit = input.r#type() {
                 self.available += amount;
-                *dispute = DisputeState::Resolved;
-                Ok(())
-            } else {
-                Err(TransactionError::InvalidTx)
             }
+            // a resolved withdrawal stands, so the funds credited while disputed are dropped
+            *dispute = DisputeState::Resolved;
+            #[cfg(feature = "trace")]
+            trace::event("resolve", client, tx, None);
+            Ok(())
         } else {
             Err(TransactionError::DisputeAlreadyHandled)
         }
     }
 
-    fn dispute(&mut self, tx: u32) -> Result<(), TransactionError> {
+    /// Disputes the tx, holding `disputed` of it, or all of it if no amount is given
+    fn dispute(
+        &mut self,
+        client: u16,
+        tx: u32,
+        disputed: Option<A>,
+    ) -> Result<(), TransactionError> {
         // Fetch the tx that is to be disputed
         let input = self
             .tx_history
-            .get(&tx)
-            .ok_or(TransactionError::MissingTxId)?;
+            .get(tx)?
+            .ok_or(TransactionError::MissingTxId { client, tx })?;
 
         match input.r#type() {
-            TransactionType::Deposit => {
-                if self.disputes.contains_key(&tx) {
-                    Err(TransactionError::DisputeAlreadyExist)
-                } else {
-                    let amount = input
-                        .amount_as_fp()
-                        .ok_or(TransactionError::InvalidTxForDispute)?;
-
-                    // store the tx under dispute, unless already handled
-                    // hold the funds related in the dispute
-                    self.disputes.insert(tx, DisputeState::new());
-                    self.available -= amount;
-                    self.held += amount;
-                    Ok(())
+            TransactionType::Deposit | TransactionType::Withdrawal => {
+                // a resolved dispute can be reopened, but a charged back one is final
+                match self.disputes.get(&tx) {
+                    None | Some(DisputeState::Resolved) => {}
+                    Some(DisputeState::Started { .. } | DisputeState::Reimbursed) => {
+                        return Err(TransactionError::DisputeAlreadyExist);
+                    }
                 }
+                let original = input
+                    .amount_as_fp()
+                    .ok_or(TransactionError::InvalidTxForDispute)
+                    .and_then(amount_of)?;
+                let amount = disputed.unwrap_or(original);
+                if amount > original {
+                    return Err(TransactionError::DisputeExceedsTx);
+                }
+                if let (TransactionType::Deposit, DisputePolicy::RequireAvailable) =
+                    (input.r#type(), self.dispute_policy)
+                    && self.available < amount
+                {
+                    return Err(TransactionError::NotEnoughAvailableFunds);
+                }
+                if let TransactionType::Withdrawal = input.r#type() {
+                    // the held funds are added to the total, which has to stay representable
+                    self.held
+                        .checked_add(amount)
+                        .and_then(|held| held.checked_add(self.available))
+                        .ok_or(TransactionError::BalanceOverflow)?;
+                }
+
+                // store the tx under dispute, unless already handled
+                // hold the funds related in the dispute
+                self.disputes
+                    .insert(tx, DisputeState::Started { held: amount });
+                if let TransactionType::Deposit = input.r#type() {
+                    self.available -= amount;
+                }
+                // the withdrawn funds are already gone, so they are credited back
+                // as held until the dispute is settled
+                self.held += amount;
+                Ok(())
             }
             _ => Err(TransactionError::InvalidTxForDispute),
         }
     }
 
-    /// Get the account's locked status
+    /// If the account is locked or frozen, that is not active
     pub fn locked(&self) -> bool {
-        self.locked
+        self.status != AccountStatus::Active
+    }
+
+    /// The available and held funds, and if the account is locked
+    fn balances(&self) -> (A, A, bool) {
+        (self.available, self.held, self.locked())
+    }
+
+    /// Get the account's status
+    pub fn status(&self) -> AccountStatus {
+        self.status
     }
 }
 
+/// The amount of a transaction in the amount type of the accounts
+fn amount_of<A: AmountOps>(amount: FixedPoint) -> Result<A, TransactionError> {
+    A::from_fixed_point(amount).ok_or(TransactionError::UnrepresentableAmount)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -309,324 +2127,2027 @@ mod tests {
     fn test_account_deposit() {
         let mut account = Account::new();
 
-        let transaction = Input::new(TransactionType::Deposit, 1, 1, Some(55.1234));
-        let res = account.handle_transaction(transaction);
-        assert!(res.is_ok(), "Deposit failed: {:?}", res);
+        let transaction = Input::from_f64(TransactionType::Deposit, 1, 1, Some(55.1234));
+        let res = account.handle_transaction(transaction);
+        assert!(res.is_ok(), "Deposit failed: {:?}", res);
+
+        assert_eq!(55.1234, account.available());
+        assert_eq!(55.1234, account.total());
+    }
+
+    #[test]
+    /// Withdrawing more than the available balance should fail and leave balance unchanged.
+    fn test_account_withdraw_too_much() {
+        let mut account = Account::new();
+
+        // Start with a deposit
+        let deposit = Input::from_f64(TransactionType::Deposit, 1, 1, Some(55.1234));
+        account.handle_transaction(deposit).unwrap();
+
+        // Attempt to overdraw
+        let withdraw = Input::from_f64(TransactionType::Withdrawal, 1, 2, Some(56.1234));
+        let res = account.handle_transaction(withdraw);
+        assert!(res.is_err(), "Expected withdrawal to fail");
+
+        // Balance should remain unchanged
+        assert_eq!(55.1234, account.available());
+        assert_eq!(55.1234, account.total());
+    }
+
+    #[test]
+    /// Withdrawing a valid small amount should succeed and reduce the balance.
+    fn test_account_withdraw_partial() {
+        let mut account = Account::new();
+
+        // Start with a deposit
+        let deposit = Input::from_f64(TransactionType::Deposit, 1, 1, Some(55.1234));
+        account.handle_transaction(deposit).unwrap();
+
+        // Withdraw a small amount
+        let withdraw = Input::from_f64(TransactionType::Withdrawal, 1, 2, Some(0.1234));
+        let res = account.handle_transaction(withdraw);
+        assert!(res.is_ok(), "Partial withdrawal failed: {:?}", res);
+
+        // Balance should decrease
+        assert_eq!(55.0, account.available());
+        assert_eq!(55.0, account.total());
+    }
+
+    #[test]
+    /// Testing that chargebacks after a withdrawal of partial funds still succeeds
+    /// the assumption here is that a chargeback is not something that we can control,
+    /// but something that someone else is forcing upon us
+    fn test_account_chargeback_after_withdrawal() {
+        let mut account = Account::new();
+
+        // Start with a deposit
+        let deposit = Input::from_f64(TransactionType::Deposit, 1, 1, Some(55.1234));
+        account.handle_transaction(deposit).unwrap();
+
+        // Withdraw a small amount
+        let withdraw = Input::from_f64(TransactionType::Withdrawal, 1, 2, Some(0.1234));
+        let res = account.handle_transaction(withdraw);
+        assert!(res.is_ok(), "Partial withdrawal failed: {:?}", res);
+
+        // Balance should decrease
+        assert_eq!(55.0, account.available());
+        assert_eq!(55.0, account.total());
+
+        let dispute = Input::new(TransactionType::Dispute, 1, 1, None);
+        let res = account.handle_transaction(dispute);
+        assert!(
+            res.is_ok(),
+            "dispute should fail since the funds are no longer there {:?}",
+            res
+        );
+        // Balance should stay the same
+        assert_eq!(-0.1234, account.available());
+        assert_eq!(55., account.total());
+
+        let chargeback = Input::new(TransactionType::Chargeback, 1, 1, None);
+        let res = account.handle_transaction(chargeback);
+        assert!(
+            res.is_ok(),
+            "dispute should fail since the funds are no longer there {:?}",
+            res
+        );
+        // Balance should stay the same
+        assert_eq!(-0.1234, account.available());
+        assert_eq!(-0.1234, account.total());
+        assert_eq!(0.0, account.held());
+        assert!(account.locked());
+    }
+
+    #[test]
+    /// However much of the disputed amount held covers, a chargeback should remove exactly
+    /// the disputed amount, with the residual taken from available
+    fn chargeback_residual_against_available() {
+        for (held, available) in [(50.0, 5.0), (20.0, -25.0), (0.0, -45.0)] {
+            let mut account = Account::new();
+            let deposit = Input::builder()
+                .deposit(1, 1, FixedPoint::from_f64(50.0))
+                .build()
+                .unwrap();
+            account.handle_transaction(deposit).unwrap();
+            let deposit = Input::builder()
+                .deposit(1, 2, FixedPoint::from_f64(5.0))
+                .build()
+                .unwrap();
+            account.handle_transaction(deposit).unwrap();
+            let dispute = Input::builder().dispute(1, 1).build().unwrap();
+            account.handle_transaction(dispute).unwrap();
+            // held can not normally get below a disputed amount, so force it
+            account.held = FixedPoint::from_f64(held);
+
+            let chargeback = Input::builder().chargeback(1, 1).build().unwrap();
+            account.handle_transaction(chargeback).unwrap();
+            assert_eq!(0.0, account.held(), "held {}", held);
+            assert_eq!(available, account.available(), "held {}", held);
+            assert!(account.locked());
+        }
+    }
+
+    #[test]
+    /// Withdrawing the full remaining balance should succeed and leave account at zero.
+    fn test_account_withdraw_all() {
+        let mut account = Account::new();
+
+        // Start with a deposit
+        let deposit = Input::from_f64(TransactionType::Deposit, 1, 1, Some(55.0));
+        account.handle_transaction(deposit).unwrap();
+
+        // Withdraw everything
+        let withdraw = Input::from_f64(TransactionType::Withdrawal, 1, 2, Some(55.0));
+        let res = account.handle_transaction(withdraw);
+        assert!(res.is_ok(), "Full withdrawal failed: {:?}", res);
+
+        // Account should be empty
+        assert_eq!(0.0, account.available());
+        assert_eq!(0.0, account.total());
+    }
+
+    #[test]
+    /// tests that withdrawing not held funds still works even though an active dispute is underway
+    fn account_deposited_dispute() {
+        let mut account = Account::new();
+
+        let transaction = Input::from_f64(TransactionType::Deposit, 1, 1, Some(50.0));
+        let res = account.handle_transaction(transaction);
+        assert!(res.is_ok(), "{:?}", res);
+
+        let transaction = Input::from_f64(TransactionType::Deposit, 1, 2, Some(5.1234));
+        let res = account.handle_transaction(transaction);
+        assert!(res.is_ok(), "{:?}", res);
+        // Withdrawing to much should fail
+        assert_eq!(55.1234, account.available());
+
+        // Withdrawing to much should fail
+        let transaction = Input::new(TransactionType::Dispute, 1, 1, None);
+        let res = account.handle_transaction(transaction);
+        assert!(res.is_ok(), "{:?}", res);
+        assert_eq!(55.1234, account.total());
+        assert_eq!(5.1234, account.available());
+        assert_eq!(50.0, account.held());
+
+        // Withdrawing a small amount should work, and in this case leave exactly 5.0000 left
+        let transaction = Input::from_f64(TransactionType::Withdrawal, 1, 3, Some(0.1234));
+        let res = account.handle_transaction(transaction);
+        assert!(res.is_ok(), "{:?}", res);
+        assert_eq!(5.0, account.available());
+        assert_eq!(50.0, account.held());
+        assert_eq!(55.0, account.total());
+    }
+
+    #[test]
+    /// Tests the dispute and chargeback
+    /// by depositing into a clients account, then
+    /// disputing that deposit and returning the deposited amount
+    fn account_dispute_chargeback() {
+        let mut account = Account::new();
+
+        let deposit = Input::from_f64(TransactionType::Deposit, 1, 1, Some(50.0));
+        let res = account.handle_transaction(deposit);
+        assert!(res.is_ok(), "{:?}", res);
+
+        let dispute = Input::new(TransactionType::Dispute, 1, 1, None);
+        let res = account.handle_transaction(dispute);
+        assert!(res.is_ok(), "{:?}", res);
+        assert_eq!(0.0, account.available());
+        assert_eq!(50.0, account.held());
+        assert_eq!(50.0, account.total());
+        assert!(!account.locked(), "account locked state was wrong");
+
+        let chargeback = Input::new(TransactionType::Chargeback, 1, 1, None);
+        let res = account.handle_transaction(chargeback);
+        assert!(res.is_ok(), "{:?}", res);
+        assert_eq!(0.0, account.held(), "held amount was wrong");
+        assert_eq!(0.0, account.available(), "available amount was wrong");
+        assert_eq!(0.0, account.total(), "total amount was wrong");
+        assert!(account.locked(), "account locked state was wrong");
+    }
+
+    #[test]
+    /// With two disputes active, charging back one should only release the held funds of that one
+    fn chargeback_with_concurrent_disputes() {
+        let mut account = Account::new();
+
+        for (tx, amount) in [(1, 50.0), (2, 20.0)] {
+            let deposit = Input::from_f64(TransactionType::Deposit, 1, tx, Some(amount));
+            account.handle_transaction(deposit).unwrap();
+            let dispute = Input::new(TransactionType::Dispute, 1, tx, None);
+            account.handle_transaction(dispute).unwrap();
+        }
+        assert_eq!(70.0, account.held());
+
+        let chargeback = Input::new(TransactionType::Chargeback, 1, 2, None);
+        let res = account.handle_transaction(chargeback);
+        assert!(res.is_ok(), "{:?}", res);
+        assert_eq!(50.0, account.held());
+        assert_eq!(0.0, account.available());
+        assert_eq!(50.0, account.total());
+        assert!(account.locked());
+    }
+
+    #[test]
+    /// A resolved dispute can be disputed again, holding the funds once more, while a charged
+    /// back one cannot
+    fn dispute_again_after_resolve() {
+        let mut account = Account::new();
+
+        let deposit = Input::from_f64(TransactionType::Deposit, 1, 1, Some(50.0));
+        account.handle_transaction(deposit).unwrap();
+        let dispute = Input::new(TransactionType::Dispute, 1, 1, None);
+        account.handle_transaction(dispute).unwrap();
+        let resolve = Input::new(TransactionType::Resolve, 1, 1, None);
+        account.handle_transaction(resolve).unwrap();
+        assert_eq!(50.0, account.available());
+
+        let dispute = Input::new(TransactionType::Dispute, 1, 1, None);
+        let res = account.handle_transaction(dispute);
+        assert!(res.is_ok(), "{:?}", res);
+        assert_eq!(0.0, account.available());
+        assert_eq!(50.0, account.held());
+        assert_eq!(
+            Some(&DisputeState::Started {
+                held: FixedPoint::from_f64(50.0)
+            }),
+            account.dispute_state(1)
+        );
+
+        let chargeback = Input::new(TransactionType::Chargeback, 1, 1, None);
+        account.handle_transaction(chargeback).unwrap();
+        account.unlock();
+        let dispute = Input::new(TransactionType::Dispute, 1, 1, None);
+        let res = account.handle_transaction(dispute);
+        assert_eq!(Err(TransactionError::DisputeAlreadyExist), res);
+        assert_eq!(0.0, account.held());
+    }
+
+    #[test]
+    /// Releasing more than is held should fail on resolve, and clamp to zero on chargeback
+    fn held_never_goes_negative() {
+        let mut account = Account::new();
+        let deposit = Input::from_f64(TransactionType::Deposit, 1, 1, Some(50.0));
+        account.handle_transaction(deposit).unwrap();
+        let dispute = Input::new(TransactionType::Dispute, 1, 1, None);
+        account.handle_transaction(dispute).unwrap();
+        // held can not normally get below a disputed amount, so force it
+        account.held = FixedPoint::from_f64(20.0);
+
+        let resolve = Input::new(TransactionType::Resolve, 1, 1, None);
+        let res = account.handle_transaction(resolve);
+        assert_eq!(Err(TransactionError::HeldWouldGoNegative), res);
+        assert_eq!(20.0, account.held());
+        assert_eq!(0.0, account.available());
+        assert_eq!(
+            Some(&DisputeState::Started {
+                held: FixedPoint::from_f64(50.0)
+            }),
+            account.dispute_state(1)
+        );
+
+        let chargeback = Input::new(TransactionType::Chargeback, 1, 1, None);
+        let res = account.handle_transaction(chargeback);
+        // the 30.0 that held did not cover is taken from available
+        let delta = BalanceDelta {
+            available: FixedPoint::from_f64(-30.0),
+            held: FixedPoint::from_f64(-20.0),
+            locked: Some(true),
+        };
+        assert_eq!(Ok(delta), res);
+        assert_eq!(0.0, account.held());
+        assert_eq!(-30.0, account.available());
+        assert!(account.locked());
+    }
+
+    /// An account with 50.0 deposited and 20.0 of it withdrawn, where the withdrawal is disputed
+    fn disputed_withdrawal() -> Account {
+        let mut account = Account::new();
+
+        let deposit = Input::from_f64(TransactionType::Deposit, 1, 1, Some(50.0));
+        account.handle_transaction(deposit).unwrap();
+        let withdrawal = Input::from_f64(TransactionType::Withdrawal, 1, 2, Some(20.0));
+        account.handle_transaction(withdrawal).unwrap();
+
+        let dispute = Input::new(TransactionType::Dispute, 1, 2, None);
+        let res = account.handle_transaction(dispute);
+        assert!(res.is_ok(), "Dispute failed: {:?}", res);
+        // the withdrawn funds are held until the dispute is settled
+        assert_eq!(30.0, account.available());
+        assert_eq!(20.0, account.held());
+        assert_eq!(50.0, account.total());
+        assert!(!account.locked());
+        account
+    }
+
+    #[test]
+    /// Disputing a deposit that has partly been withdrawn should only push available
+    /// negative when the policy allows it
+    fn dispute_policy_for_withdrawn_funds() {
+        for policy in [
+            DisputePolicy::AllowNegative,
+            DisputePolicy::RequireAvailable,
+        ] {
+            let mut accounts = AccountStorage::builder().dispute_policy(policy).build();
+
+            let deposit = Input::from_f64(TransactionType::Deposit, 1, 1, Some(50.0));
+            accounts.handle_transaction(deposit).unwrap();
+            let withdrawal = Input::from_f64(TransactionType::Withdrawal, 1, 2, Some(10.0));
+            accounts.handle_transaction(withdrawal).unwrap();
+
+            let dispute = Input::new(TransactionType::Dispute, 1, 1, None);
+            let res = accounts.handle_transaction(dispute);
+            let account = &accounts.accounts[&1];
+            match policy {
+                DisputePolicy::AllowNegative => {
+                    assert_eq!(Ok(()), res);
+                    assert_eq!(-10.0, account.available());
+                    assert_eq!(50.0, account.held());
+                }
+                DisputePolicy::RequireAvailable => {
+                    assert_eq!(Err(TransactionError::NotEnoughAvailableFunds), res);
+                    assert_eq!(40.0, account.available());
+                    assert_eq!(0.0, account.held());
+                    assert_eq!(None, account.dispute_state(1));
+                }
+            }
+        }
+    }
+
+    #[test]
+    /// Without disputes the balances should be the same as with them, while every dispute is
+    /// rejected and nothing is kept in the history
+    fn disabled_disputes() {
+        let f = FixedPoint::from_f64;
+        let inputs = [
+            Input::new(TransactionType::Deposit, 1, 1, Some(f(50.0))),
+            Input::new(TransactionType::Deposit, 2, 2, Some(f(5.0))),
+            Input::new(TransactionType::Withdrawal, 1, 3, Some(f(10.0))),
+            // too much, rejected either way
+            Input::new(TransactionType::Withdrawal, 2, 4, Some(f(10.0))),
+            // the txid is still checked
+            Input::new(TransactionType::Deposit, 2, 1, Some(f(1.0))),
+        ];
+        let mut with = AccountStorage::new();
+        let mut without = AccountStorage::builder().disable_disputes(true).build();
+        for input in inputs {
+            assert_eq!(
+                with.handle_transaction(input.clone()),
+                without.handle_transaction(input)
+            );
+        }
+        for client in [1, 2] {
+            let (with, without) = (&with.accounts[&client], &without.accounts[&client]);
+            assert_eq!(with.available(), without.available());
+            assert_eq!(with.held(), without.held());
+        }
+        assert!(!without.accounts[&1].contains_txid(1));
+
+        for r#type in [
+            TransactionType::Dispute,
+            TransactionType::Resolve,
+            TransactionType::Chargeback,
+            TransactionType::Void,
+        ] {
+            let input = Input::new(r#type, 1, 1, None);
+            assert_eq!(
+                Err(TransactionError::DisputesDisabled),
+                without.handle_transaction(input)
+            );
+        }
+        assert_eq!(40.0, without.accounts[&1].available());
+        assert_eq!(0.0, without.accounts[&1].held());
+        assert!(!without.accounts[&1].locked());
+    }
+
+    #[test]
+    /// A locked account should handle transactions again once unlocked, but only when the
+    /// storage allows unlocks
+    fn unlock_after_chargeback() {
+        for allow_unlock in [false, true] {
+            let mut accounts = AccountStorage::builder().allow_unlock(allow_unlock).build();
+
+            let deposit = Input::from_f64(TransactionType::Deposit, 1, 1, Some(50.0));
+            accounts.handle_transaction(deposit).unwrap();
+            let dispute = Input::new(TransactionType::Dispute, 1, 1, None);
+            accounts.handle_transaction(dispute).unwrap();
+            let chargeback = Input::new(TransactionType::Chargeback, 1, 1, None);
+            accounts.handle_transaction(chargeback).unwrap();
+            assert!(accounts.accounts[&1].locked());
+
+            let unlock = Input::new(TransactionType::Unlock, 1, 2, None);
+            let res = accounts.handle_transaction(unlock);
+            let deposit = Input::from_f64(TransactionType::Deposit, 1, 3, Some(5.0));
+            let deposited = accounts.handle_transaction(deposit);
+
+            if allow_unlock {
+                assert_eq!(Ok(()), res);
+                assert_eq!(Ok(()), deposited);
+                assert!(!accounts.accounts[&1].locked());
+                assert_eq!(5.0, accounts.accounts[&1].available());
+                assert!(accounts.check_integrity().is_empty());
+            } else {
+                assert_eq!(Err(TransactionError::UnlockNotAllowed), res);
+                assert_eq!(Err(TransactionError::AccountLocked), deposited);
+                assert!(accounts.accounts[&1].locked());
+            }
+        }
+    }
+
+    #[test]
+    /// A charged back withdrawal is reversed, returning the funds to the client
+    fn account_withdrawal_dispute_chargeback() {
+        let mut account = disputed_withdrawal();
+
+        let chargeback = Input::new(TransactionType::Chargeback, 1, 2, None);
+        let res = account.handle_transaction(chargeback);
+        assert!(res.is_ok(), "{:?}", res);
+        assert_eq!(0.0, account.held(), "held amount was wrong");
+        assert_eq!(50.0, account.available(), "available amount was wrong");
+        assert_eq!(50.0, account.total(), "total amount was wrong");
+        assert!(account.locked(), "account locked state was wrong");
+    }
+
+    #[test]
+    /// A resolved withdrawal stands, so the held funds are released
+    fn account_withdrawal_dispute_resolve() {
+        let mut account = disputed_withdrawal();
+
+        let resolve = Input::new(TransactionType::Resolve, 1, 2, None);
+        let res = account.handle_transaction(resolve);
+        assert!(res.is_ok(), "Resolve failed: {:?}", res);
+        assert_eq!(30.0, account.available());
+        assert_eq!(0.0, account.held());
+        assert_eq!(30.0, account.total());
+        assert!(!account.locked());
+    }
+
+    #[test]
+    fn account_dispute_resolve() {
+        let mut account = Account::new();
+
+        // Deposit funds into the account
+        let deposit = Input::from_f64(TransactionType::Deposit, 1, 1, Some(50.0));
+        let res = account.handle_transaction(deposit);
+        assert!(res.is_ok(), "Deposit failed: {:?}", res);
+
+        // Dispute the deposit: should move funds to `held`
+        let dispute = Input::new(TransactionType::Dispute, 1, 1, None);
+        let res = account.handle_transaction(dispute);
+        assert!(res.is_ok(), "Dispute failed: {:?}", res);
+        assert_eq!(
+            Some(&DisputeState::Started {
+                held: FixedPoint::from_f64(50.0)
+            }),
+            account.dispute_state(1)
+        );
+        assert_eq!(1, account.disputes().len());
+
+        assert_eq!(0.0, account.available());
+        assert_eq!(50.0, account.held());
+        assert_eq!(50.0, account.total());
+        assert!(
+            !account.locked(),
+            "Account should not be locked after a dispute"
+        );
+
+        // Resolve the dispute: should move funds back to `available`
+        let resolve = Input::new(TransactionType::Resolve, 1, 1, None);
+        let res = account.handle_transaction(resolve);
+        assert!(res.is_ok(), "Resolve failed: {:?}", res);
+        assert_eq!(Some(&DisputeState::Resolved), account.dispute_state(1));
+        assert_eq!(None, account.dispute_state(2));
+
+        assert_eq!(
+            50.0,
+            account.available(),
+            "Available amount was not restored"
+        );
+        assert_eq!(
+            0.0,
+            account.held(),
+            "Held amount should be cleared after resolve"
+        );
+        assert_eq!(
+            50.0,
+            account.total(),
+            "Total amount should remain unchanged"
+        );
+        assert!(
+            !account.locked(),
+            "Account should be not be locked after resolve"
+        );
+    }
+
+    #[test]
+    /// Depositing using a previously used TXID should fail to deposit.
+    fn test_duplicate_transaction_same_client() {
+        let mut accounts = AccountStorage::new();
+
+        let transaction = Input::from_f64(TransactionType::Deposit, 1, 1234, Some(55.1234));
+        let res = accounts.handle_transaction(transaction);
+        assert!(res.is_ok(), "Deposit failed: {:?}", res);
+
+        let transaction = Input::from_f64(TransactionType::Deposit, 1, 1234, Some(55.1234));
+        let res = accounts.handle_transaction(transaction);
+        assert_eq!(
+            Err(TransactionError::DuplicateTxId {
+                client: 1,
+                tx: 1234
+            }),
+            res
+        );
+
+        assert_eq!(55.1234, accounts.accounts.get(&1).unwrap().available());
+        assert_eq!(55.1234, accounts.accounts.get(&1).unwrap().total());
+    }
+
+    #[test]
+    fn get_single_account() {
+        let mut accounts = AccountStorage::new();
+        assert!(accounts.get(1).is_none());
+
+        let deposit = Input::from_f64(TransactionType::Deposit, 1, 1, Some(5.0));
+        accounts.handle_transaction(deposit).unwrap();
+        assert_eq!(5.0, accounts.get(1).unwrap().available());
+        assert!(accounts.get(2).is_none());
+    }
+
+    #[test]
+    /// The funds of all accounts should add up to what has been deposited and withdrawn
+    fn funds_are_conserved() {
+        let mut accounts = AccountStorage::new();
+        let fp = FixedPoint::from_f64;
+        let inputs = [
+            Input::new(TransactionType::Deposit, 1, 1, Some(fp(10.0))),
+            Input::new(TransactionType::Deposit, 2, 2, Some(fp(5.5))),
+            Input::new(TransactionType::Withdrawal, 1, 3, Some(fp(2.25))),
+            Input::new(TransactionType::Dispute, 1, 1, None),
+            Input::new(TransactionType::Resolve, 1, 1, None),
+            Input::new(TransactionType::Dispute, 2, 2, None),
+            Input::builder().transfer(1, 3, 4, fp(1.0)).build().unwrap(),
+            // rejected, so it does not count
+            Input::new(TransactionType::Withdrawal, 3, 5, Some(fp(100.0))),
+        ];
+        for input in inputs {
+            let _ = accounts.handle_transaction(input);
+        }
+
+        assert_eq!(fp(5.5), accounts.total_held());
+        assert_eq!(fp(7.75), accounts.total_available());
+        assert!(accounts.verify_conservation(fp(10.0 + 5.5 - 2.25)));
+        assert!(!accounts.verify_conservation(fp(10.0 + 5.5)));
+
+        // the chargeback reverses the deposit of client 2
+        let chargeback = Input::new(TransactionType::Chargeback, 2, 2, None);
+        accounts.handle_transaction(chargeback).unwrap();
+        assert!(accounts.verify_conservation(fp(10.0 - 2.25)));
+    }
+
+    #[test]
+    /// Errors about a transaction should say which client and tx they were about
+    fn missing_tx_error_has_context() {
+        let mut accounts = AccountStorage::new();
+        let deposit = Input::from_f64(TransactionType::Deposit, 3, 1, Some(5.0));
+        accounts.handle_transaction(deposit).unwrap();
+
+        let dispute = Input::new(TransactionType::Dispute, 3, 9, None);
+        let res = accounts.handle_transaction(dispute);
+        assert_eq!(Err(TransactionError::MissingTxId { client: 3, tx: 9 }), res);
+    }
+
+    #[test]
+    /// Disputing the transaction of another client should be reported as such
+    fn dispute_of_other_clients_tx() {
+        let mut accounts = AccountStorage::new();
+        let deposit = Input::from_f64(TransactionType::Deposit, 1, 1, Some(5.0));
+        accounts.handle_transaction(deposit).unwrap();
+        let deposit = Input::from_f64(TransactionType::Deposit, 2, 2, Some(5.0));
+        accounts.handle_transaction(deposit).unwrap();
+
+        let dispute = Input::new(TransactionType::Dispute, 2, 1, None);
+        let res = accounts.handle_transaction(dispute);
+        assert_eq!(
+            Err(TransactionError::TxBelongsToOtherClient {
+                client: 2,
+                tx: 1,
+                owner: 1
+            }),
+            res
+        );
+        assert_eq!(0.0, accounts.accounts[&1].held());
+        assert_eq!(0.0, accounts.accounts[&2].held());
+    }
+
+    fn transfer_accounts() -> AccountStorage {
+        let mut accounts = AccountStorage::new();
+        for (client, tx) in [(1, 1), (2, 2)] {
+            let deposit = Input::from_f64(TransactionType::Deposit, client, tx, Some(10.0));
+            accounts.handle_transaction(deposit).unwrap();
+        }
+        accounts
+    }
+
+    fn transfer(client: u16, to: u16, tx: u32, amount: f64) -> Input {
+        Input::builder()
+            .transfer(client, to, tx, FixedPoint::from_f64(amount))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn transfer_moves_available_funds() {
+        let mut accounts = transfer_accounts();
+
+        let res = accounts.handle_transaction(transfer(1, 2, 3, 4.0));
+        assert_eq!(Ok(()), res);
+        assert_eq!(6.0, accounts.accounts[&1].available());
+        assert_eq!(14.0, accounts.accounts[&2].available());
+
+        // to a client without an account yet
+        let res = accounts.handle_transaction(transfer(2, 3, 4, 14.0));
+        assert_eq!(Ok(()), res);
+        assert_eq!(0.0, accounts.accounts[&2].available());
+        assert_eq!(14.0, accounts.accounts[&3].available());
+    }
+
+    #[test]
+    fn transfer_without_funds_changes_nothing() {
+        let mut accounts = transfer_accounts();
+
+        let res = accounts.handle_transaction(transfer(1, 2, 3, 10.5));
+        assert_eq!(Err(TransactionError::NotEnoughAvailableFunds), res);
+        assert_eq!(10.0, accounts.accounts[&1].available());
+        assert_eq!(10.0, accounts.accounts[&2].available());
+
+        let res = accounts.handle_transaction(transfer(4, 2, 4, 1.0));
+        assert_eq!(Err(TransactionError::NotEnoughAvailableFunds), res);
+        assert!(!accounts.accounts.contains_key(&4));
+    }
+
+    #[test]
+    fn transfer_with_locked_account_fails() {
+        let mut accounts = transfer_accounts();
+        let dispute = Input::new(TransactionType::Dispute, 2, 2, None);
+        accounts.handle_transaction(dispute).unwrap();
+        let chargeback = Input::new(TransactionType::Chargeback, 2, 2, None);
+        accounts.handle_transaction(chargeback).unwrap();
+        let deposit = Input::from_f64(TransactionType::Deposit, 3, 3, Some(10.0));
+        accounts.handle_transaction(deposit).unwrap();
+
+        // to and from the locked account
+        let res = accounts.handle_transaction(transfer(1, 2, 4, 1.0));
+        assert_eq!(Err(TransactionError::AccountLocked), res);
+        let res = accounts.handle_transaction(transfer(2, 3, 5, 1.0));
+        assert_eq!(Err(TransactionError::AccountLocked), res);
+
+        assert_eq!(10.0, accounts.accounts[&1].available());
+        assert_eq!(0.0, accounts.accounts[&2].available());
+        assert_eq!(10.0, accounts.accounts[&3].available());
+    }
+
+    #[test]
+    /// Depositing into an different clients account using a previously used TXID should not be a valid transaction
+    fn test_duplicate_transaction_different_clients() {
+        let mut accounts = AccountStorage::new();
+
+        let transaction = Input::from_f64(TransactionType::Deposit, 1, 1234, Some(55.1234));
+        let res = accounts.handle_transaction(transaction);
+        assert!(
+            res.is_ok(),
+            "Deposit failed when it should succeed: {:?}",
+            res
+        );
+
+        let transaction = Input::from_f64(TransactionType::Deposit, 2, 1234, Some(55.1234));
+        let res = accounts.handle_transaction(transaction);
+        assert!(
+            res.is_err(),
+            "Deposit succeded when it should fail: {:?}",
+            res
+        );
+
+        assert_eq!(55.1234, accounts.accounts.get(&1).unwrap().available());
+        assert!(
+            !accounts.accounts.contains_key(&2),
+            "Account 2 should not exist due to invalid input"
+        );
+    }
+
+    #[test]
+    /// Client 2 reusing a tx id of client 1 should only be allowed when tx ids are per client,
+    /// while a client reusing its own tx id is always a duplicate
+    fn txid_scope_of_duplicates() {
+        let deposit = |client, tx| Input::from_f64(TransactionType::Deposit, client, tx, Some(1.0));
+
+        let mut accounts = AccountStorage::builder()
+            .txid_scope(TxIdScope::Global)
+            .build();
+        accounts.handle_transaction(deposit(1, 7)).unwrap();
+        assert_eq!(
+            Err(TransactionError::DuplicateTxId { client: 2, tx: 7 }),
+            accounts.handle_transaction(deposit(2, 7))
+        );
+        assert!(accounts.get(2).is_none());
+
+        let mut accounts = AccountStorage::builder()
+            .txid_scope(TxIdScope::PerClient)
+            .build();
+        accounts.handle_transaction(deposit(1, 7)).unwrap();
+        accounts.handle_transaction(deposit(2, 7)).unwrap();
+        assert_eq!(
+            Err(TransactionError::DuplicateTxId { client: 1, tx: 7 }),
+            accounts.handle_transaction(deposit(1, 7))
+        );
+        // the dispute is about the deposit of client 2 only
+        let dispute = Input::new(TransactionType::Dispute, 2, 7, None);
+        accounts.handle_transaction(dispute).unwrap();
+        assert_eq!(1.0, accounts.get(1).unwrap().available());
+        assert_eq!(1.0, accounts.get(2).unwrap().held());
+
+        let mut restored = AccountStorage::from_snapshot(accounts.to_snapshot().unwrap());
+        assert_eq!(
+            Err(TransactionError::DuplicateTxId { client: 2, tx: 7 }),
+            restored.handle_transaction(deposit(2, 7))
+        );
+        restored.handle_transaction(deposit(3, 7)).unwrap();
+    }
+
+    #[test]
+    /// With a retention window the history and used txids should stay bounded, while the
+    /// recent transactions can still be disputed
+    fn retention_bounds_history() {
+        let mut accounts = AccountStorage::builder().retention(3).build();
+        let deposit = |tx| Input::from_f64(TransactionType::Deposit, 1, tx, Some(1.0));
+        let used_txids = |accounts: &AccountStorage| match &accounts.used_txids {
+            UsedTxIds::Global(ids) => ids.len(),
+            UsedTxIds::PerClient(ids) => ids.len(),
+        };
+
+        accounts.handle_transaction(deposit(1)).unwrap();
+        let dispute = Input::new(TransactionType::Dispute, 1, 1, None);
+        accounts.handle_transaction(dispute).unwrap();
+        for tx in 2..1000 {
+            accounts.handle_transaction(deposit(tx)).unwrap();
+            let account = accounts.get(1).unwrap();
+            // the disputed tx is kept on top of the window
+            assert!(account.tx_history.inputs().unwrap().len() <= 4);
+            assert!(used_txids(&accounts) <= 4);
+        }
+
+        let dispute = Input::new(TransactionType::Dispute, 1, 998, None);
+        accounts.handle_transaction(dispute).unwrap();
+        let dispute = Input::new(TransactionType::Dispute, 1, 500, None);
+        assert_eq!(
+            Err(TransactionError::MissingTxId { client: 1, tx: 500 }),
+            accounts.handle_transaction(dispute)
+        );
+
+        // once settled the old dispute falls out of the window as well
+        let resolve = Input::new(TransactionType::Resolve, 1, 1, None);
+        accounts.handle_transaction(resolve).unwrap();
+        accounts.handle_transaction(deposit(1000)).unwrap();
+        let account = accounts.get(1).unwrap();
+        assert!(!account.contains_txid(1));
+        assert!(account.contains_txid(998));
+        assert_eq!(1000.0, account.total());
+        assert!(accounts.check_integrity().is_empty());
+    }
+
+    #[test]
+    /// A long stream with disputes, failed withdrawals and transfers from unknown clients
+    /// should never keep more than the window and the open disputes of each account
+    fn streaming_keeps_maps_bounded() {
+        const RETENTION: usize = 8;
+        const CLIENTS: u32 = 50;
+        let mut accounts = AccountStorage::builder().retention(RETENTION).build();
+        let stream = (1..200_000u32).map(|tx| {
+            let client = (tx % CLIENTS) as u16;
+            let amount = Some(FixedPoint::from_f64(1.0));
+            // the deposit 5 rows before, which is disputed and then resolved 50 rows later
+            let disputed = tx.saturating_sub(5 + 50 * u32::from(tx % 100 == 60));
+            let owner = (disputed % CLIENTS) as u16;
+            match tx % 100 {
+                10 => Input::new(TransactionType::Dispute, owner, disputed, None),
+                60 => Input::new(TransactionType::Resolve, owner, disputed, None),
+                // more than has been deposited, so rejected but still using its txid
+                20 | 70 => Input::from_f64(TransactionType::Withdrawal, client, tx, Some(1e9)),
+                30 => Input::builder()
+                    .transfer(60_000, client, tx, FixedPoint::from_f64(1.0))
+                    .build()
+                    .unwrap(),
+                _ => Input::new(TransactionType::Deposit, client, tx, amount),
+            }
+        });
+
+        let mut resolved = 0;
+        for (row, input) in stream.enumerate() {
+            let resolve = matches!(input.r#type(), TransactionType::Resolve);
+            if accounts.handle_transaction(input).is_ok() && resolve {
+                resolved += 1;
+            }
+            if row % 1000 != 0 {
+                continue;
+            }
+            let mut windows = 0;
+            for account in accounts.accounts().values() {
+                let open = account
+                    .disputes()
+                    .values()
+                    .filter(|d| d.is_active())
+                    .count();
+                assert!(account.recent_txids.len() <= RETENTION + open);
+                assert!(account.tx_history.inputs().unwrap().len() <= account.recent_txids.len());
+                assert!(account.disputes().len() <= account.recent_txids.len());
+                windows += account.recent_txids.len();
+            }
+            let UsedTxIds::Global(used) = &accounts.used_txids else {
+                unreachable!()
+            };
+            assert_eq!(windows, used.len());
+        }
+        // the disputed deposits were still in the window
+        assert_eq!(2000, resolved);
+        assert!(accounts.check_integrity().is_empty());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    /// Sharding the accounts over threads should give exactly the accounts of handling the
+    /// same input in order, including reused txids, chargebacks and transfers between shards
+    fn parallel_matches_sequential() {
+        // a small linear congruential generator, so that the input is the same every run
+        let mut seed = 12345u64;
+        let mut next = move |bound: u64| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) % bound
+        };
+        let mut inputs = Vec::new();
+        for tx in 1..20_000u32 {
+            let client = next(40) as u16;
+            let amount = FixedPoint::from_decimillis(1 + next(100_000) as i128);
+            // an earlier tx, which can be of any client, including this one
+            let earlier = 1 + next(u64::from(tx)) as u32;
+            let builder = Input::builder();
+            let builder = match next(20) {
+                0..=7 => builder.deposit(client, tx, amount),
+                8..=11 => builder.withdrawal(client, tx, amount),
+                12 => builder.deposit(client, earlier, amount),
+                13 | 14 => builder.dispute(client, earlier),
+                15 => builder.resolve(client, earlier),
+                16 => builder.chargeback(client, earlier),
+                _ => builder.transfer(client, (client + 1 + next(39) as u16) % 40, tx, amount),
+            };
+            inputs.push(builder.build().unwrap());
+            if next(10) == 0 {
+                inputs.push(
+                    Input::builder()
+                        .deposit(40 + next(10) as u16, tx, amount)
+                        .build()
+                        .unwrap(),
+                );
+            }
+        }
+
+        let mut sequential = AccountStorage::new();
+        for input in inputs.clone() {
+            let _ = sequential.handle_transaction(input);
+        }
+        let snapshot = |storage: &AccountStorage| {
+            serde_json::to_string(&storage.to_snapshot().unwrap()).unwrap()
+        };
+
+        for shards in [1, 2, 3, 8] {
+            let parallel = AccountStorage::builder()
+                .process_parallel(inputs.clone(), shards)
+                .unwrap();
+            assert_eq!(
+                snapshot(&sequential),
+                snapshot(&parallel),
+                "{} shards",
+                shards
+            );
+        }
+
+        // a dispute of the deposit of a client in another shard is rejected without creating
+        // an account, while one of a rejected withdrawal is only missing its tx
+        let f = FixedPoint::from_f64;
+        let inputs = [
+            Input::builder().deposit(1, 1, f(1.0)),
+            Input::builder().dispute(2, 1),
+            Input::builder().withdrawal(3, 2, f(1.0)),
+            Input::builder().dispute(4, 2),
+        ]
+        .map(|input| input.build().unwrap());
+        let mut sequential = AccountStorage::new();
+        for input in inputs.clone() {
+            let _ = sequential.handle_transaction(input);
+        }
+        let clients: Vec<_> = sequential.accounts().keys().copied().collect();
+        assert_eq!(vec![1, 3, 4], clients);
+        for shards in [2, 3] {
+            let parallel = AccountStorage::builder()
+                .process_parallel(inputs.clone(), shards)
+                .unwrap();
+            assert_eq!(
+                snapshot(&sequential),
+                snapshot(&parallel),
+                "{} shards",
+                shards
+            );
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    /// The bitmap should answer exactly like a `HashSet`, also while its groups switch between
+    /// arrays and bitmaps
+    fn txid_bitmap_matches_hash_set() {
+        let mut seed = 42u64;
+        let mut next = move || {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 32) as u32
+        };
+        let mut bitmap = TxIdBitmap::new();
+        let mut hash_set = HashSet::new();
+        for round in 0..200_000 {
+            // mostly ids of two groups, one of which grows past the size of an array
+            let tx = match next() % 4 {
+                0 => next(),
+                1 => (5 << 16) | (next() % 3000),
+                _ => (7 << 16) | (next() % 60_000),
+            };
+            // removing less than inserting, so that the sets keep growing
+            if round % 3 == 0 {
+                assert_eq!(TxIdSet::remove(&mut hash_set, tx), bitmap.remove(tx));
+            } else {
+                assert_eq!(TxIdSet::insert(&mut hash_set, tx), bitmap.insert(tx));
+            }
+            assert_eq!(TxIdSet::contains(&hash_set, tx), bitmap.contains(tx));
+            assert_eq!(TxIdSet::len(&hash_set), bitmap.len());
+        }
+        assert!(matches!(bitmap.containers[&7], Container::Bitmap(_)));
+        assert!(matches!(bitmap.containers[&5], Container::Array(_)));
+
+        let mut expected: Vec<u32> = hash_set.into_iter().collect();
+        expected.sort_unstable();
+        assert_eq!(expected, bitmap.iter().collect::<Vec<_>>());
+
+        for tx in expected {
+            assert!(bitmap.remove(tx));
+        }
+        assert!(bitmap.is_empty());
+        assert!(bitmap.containers.is_empty());
+    }
+
+    #[test]
+    /// A deposit whose funds are all available should be voidable, once
+    fn void_deposit() {
+        let mut accounts = AccountStorage::new();
+        let deposit = Input::builder()
+            .deposit(1, 1, FixedPoint::from_f64(10.0))
+            .build()
+            .unwrap();
+        accounts.handle_transaction(deposit).unwrap();
+        let deposit = Input::builder()
+            .deposit(1, 2, FixedPoint::from_f64(2.0))
+            .build()
+            .unwrap();
+        accounts.handle_transaction(deposit).unwrap();
+
+        let void = Input::builder().void(1, 1).build().unwrap();
+        accounts.handle_transaction(void.clone()).unwrap();
+        assert_eq!(2.0, accounts.get(1).unwrap().available());
+        assert_eq!(2.0, accounts.get(1).unwrap().total());
+
+        assert_eq!(
+            Err(TransactionError::InvalidTxForVoid),
+            accounts.handle_transaction(void)
+        );
+        let dispute = Input::builder().dispute(1, 1).build().unwrap();
+        assert_eq!(
+            Err(TransactionError::InvalidTxForDispute),
+            accounts.handle_transaction(dispute)
+        );
+        assert_eq!(2.0, accounts.get(1).unwrap().available());
+    }
+
+    #[test]
+    /// A charged back deposit should not be voidable once its account is unlocked, which would
+    /// take its funds a second time
+    fn void_after_chargeback() {
+        let mut accounts = AccountStorage::builder().allow_unlock(true).build();
+        let inputs = [
+            Input::builder().deposit(1, 1, FixedPoint::from_f64(10.0)),
+            Input::builder().deposit(1, 2, FixedPoint::from_f64(10.0)),
+            Input::builder().dispute(1, 1),
+            Input::builder().chargeback(1, 1),
+            Input::builder().unlock(1, 3),
+        ];
+        for input in inputs {
+            accounts.handle_transaction(input.build().unwrap()).unwrap();
+        }
+        assert_eq!(10.0, accounts.get(1).unwrap().total());
+
+        let void = Input::builder().void(1, 1).build().unwrap();
+        assert_eq!(
+            Err(TransactionError::FundsAlreadyMoved),
+            accounts.handle_transaction(void)
+        );
+        assert_eq!(10.0, accounts.get(1).unwrap().total());
+        assert_eq!(10.0, accounts.get(1).unwrap().available());
+    }
+
+    #[test]
+    /// A deposit whose funds have been withdrawn or are held should not be voidable
+    fn void_blocked_by_moved_funds() {
+        let mut accounts = AccountStorage::new();
+        let deposit = Input::builder()
+            .deposit(1, 1, FixedPoint::from_f64(10.0))
+            .build()
+            .unwrap();
+        accounts.handle_transaction(deposit).unwrap();
+        let withdrawal = Input::builder()
+            .withdrawal(1, 2, FixedPoint::from_f64(0.5))
+            .build()
+            .unwrap();
+        accounts.handle_transaction(withdrawal).unwrap();
+
+        let void = Input::builder().void(1, 1).build().unwrap();
+        assert_eq!(
+            Err(TransactionError::FundsAlreadyMoved),
+            accounts.handle_transaction(void.clone())
+        );
+        assert_eq!(9.5, accounts.get(1).unwrap().available());
+
+        let deposit = Input::builder()
+            .deposit(1, 3, FixedPoint::from_f64(20.0))
+            .build()
+            .unwrap();
+        accounts.handle_transaction(deposit).unwrap();
+        let dispute = Input::builder().dispute(1, 1).build().unwrap();
+        accounts.handle_transaction(dispute).unwrap();
+        // enough is available, but the funds of the deposit itself are held
+        assert_eq!(
+            Err(TransactionError::FundsAlreadyMoved),
+            accounts.handle_transaction(void)
+        );
+        assert_eq!(19.5, accounts.get(1).unwrap().available());
+        assert_eq!(10.0, accounts.get(1).unwrap().held());
+
+        let void = Input::builder().void(1, 2).build().unwrap();
+        assert_eq!(
+            Err(TransactionError::InvalidTxForVoid),
+            accounts.handle_transaction(void)
+        );
+    }
+
+    #[test]
+    /// Only the transactions that were applied should be counted
+    fn count_applied_transactions() {
+        let mut accounts = AccountStorage::new();
+        let fp = FixedPoint::from_f64;
+        let inputs = [
+            (Input::builder().deposit(1, 1, fp(10.0)), true),
+            (Input::builder().withdrawal(1, 2, fp(20.0)), false),
+            (Input::builder().withdrawal(1, 3, fp(2.0)), true),
+            (Input::builder().deposit(1, 3, fp(2.0)), false),
+            (Input::builder().dispute(1, 9), false),
+            (Input::builder().dispute(1, 1), true),
+            (Input::builder().dispute(1, 1), false),
+            (Input::builder().inquiry(1, 1), true),
+            (Input::builder().transfer(2, 1, 4, fp(1.0)), false),
+        ];
+        for (input, applied) in inputs {
+            let res = accounts.handle_transaction(input.build().unwrap());
+            assert_eq!(applied, res.is_ok(), "{:?}", res);
+        }
+        assert_eq!(4, accounts.get(1).unwrap().transaction_count());
+
+        let deposit = Input::builder().deposit(2, 5, fp(1.0)).build().unwrap();
+        accounts.handle_transaction(deposit).unwrap();
+        let transfer = Input::builder().transfer(2, 1, 6, fp(1.0)).build().unwrap();
+        accounts.handle_transaction(transfer).unwrap();
+        assert_eq!(5, accounts.get(1).unwrap().transaction_count());
+        assert_eq!(2, accounts.get(2).unwrap().transaction_count());
+    }
+
+    #[test]
+    /// The delta of a dispute should be the disputed amount moving from available to held
+    fn dispute_balance_delta() {
+        let mut account = Account::new();
+        let deposit = Input::builder()
+            .deposit(1, 1, FixedPoint::from_f64(12.5))
+            .build()
+            .unwrap();
+        let delta = account.handle_transaction(deposit).unwrap();
+        assert_eq!(12.5, delta.available);
+        assert_eq!(0.0, delta.held);
+        assert_eq!(None, delta.locked);
+
+        let dispute = Input::builder().dispute(1, 1).build().unwrap();
+        let delta = account.handle_transaction(dispute).unwrap();
+        assert_eq!(
+            BalanceDelta {
+                available: FixedPoint::from_f64(-12.5),
+                held: FixedPoint::from_f64(12.5),
+                locked: None,
+            },
+            delta
+        );
+        assert_eq!(
+            FixedPoint::ZERO,
+            delta.available + delta.held,
+            "a dispute only moves funds"
+        );
+    }
+
+    #[test]
+    /// An account that has been emptied should be closeable, but its txids stay used
+    fn close_empty_account() {
+        let mut accounts = AccountStorage::new();
+        let deposit = Input::builder()
+            .deposit(1, 1, FixedPoint::from_f64(3.0))
+            .build()
+            .unwrap();
+        accounts.handle_transaction(deposit.clone()).unwrap();
+        let withdrawal = Input::builder()
+            .withdrawal(1, 2, FixedPoint::from_f64(3.0))
+            .build()
+            .unwrap();
+        accounts.handle_transaction(withdrawal).unwrap();
+
+        assert_eq!(Ok(()), accounts.close(1));
+        assert!(accounts.get(1).is_none());
+        assert_eq!(
+            Err(TransactionError::UnknownAccount { client: 1 }),
+            accounts.close(1)
+        );
+        assert_eq!(
+            Err(TransactionError::DuplicateTxId { client: 1, tx: 1 }),
+            accounts.handle_transaction(deposit)
+        );
+    }
+
+    #[test]
+    /// An account with funds, held or not, should not be closeable
+    fn close_refuses_non_empty_account() {
+        let mut accounts = AccountStorage::new();
+        let deposit = Input::builder()
+            .deposit(1, 1, FixedPoint::from_f64(3.0))
+            .build()
+            .unwrap();
+        accounts.handle_transaction(deposit).unwrap();
+        assert_eq!(Err(TransactionError::AccountNotEmpty), accounts.close(1));
+
+        let dispute = Input::builder().dispute(1, 1).build().unwrap();
+        accounts.handle_transaction(dispute).unwrap();
+        assert_eq!(Err(TransactionError::OpenDisputes), accounts.close(1));
+        assert_eq!(3.0, accounts.get(1).unwrap().total());
+    }
+
+    #[test]
+    /// A locked account should not be closeable, even when it is empty
+    fn close_refuses_locked_account() {
+        let mut accounts = AccountStorage::new();
+        let deposit = Input::builder()
+            .deposit(1, 1, FixedPoint::from_f64(3.0))
+            .build()
+            .unwrap();
+        accounts.handle_transaction(deposit).unwrap();
+        let dispute = Input::builder().dispute(1, 1).build().unwrap();
+        accounts.handle_transaction(dispute).unwrap();
+        let chargeback = Input::builder().chargeback(1, 1).build().unwrap();
+        accounts.handle_transaction(chargeback).unwrap();
+
+        assert_eq!(0.0, accounts.get(1).unwrap().total());
+        assert_eq!(Err(TransactionError::AccountLocked), accounts.close(1));
+        assert!(accounts.get(1).is_some());
+    }
+
+    #[test]
+    /// Merging shards should sum the clients in both and keep the others as they are
+    fn merge_shards() {
+        let fp = FixedPoint::from_f64;
+        let mut shards = [AccountStorage::new(), AccountStorage::new()];
+        let inputs = [
+            (0, Input::builder().deposit(1, 1, fp(10.0))),
+            (0, Input::builder().deposit(2, 2, fp(4.0))),
+            (1, Input::builder().deposit(1, 3, fp(5.0))),
+            (1, Input::builder().dispute(1, 3)),
+            (1, Input::builder().deposit(3, 4, fp(1.0))),
+        ];
+        for (shard, input) in inputs {
+            shards[shard]
+                .handle_transaction(input.build().unwrap())
+                .unwrap();
+        }
+
+        let [mut first, second] = shards;
+        first.merge(second).unwrap();
+        let account = first.get(1).unwrap();
+        assert_eq!(10.0, account.available());
+        assert_eq!(5.0, account.held());
+        assert!(account.contains_txid(1) && account.contains_txid(3));
+        assert_eq!(4.0, first.get(2).unwrap().total());
+        assert_eq!(1.0, first.get(3).unwrap().total());
+        assert!(first.check_integrity().is_empty());
+
+        // the merged dispute and txids work as if the storage had handled them itself
+        let resolve = Input::builder().resolve(1, 3).build().unwrap();
+        first.handle_transaction(resolve).unwrap();
+        assert_eq!(15.0, first.get(1).unwrap().available());
+        let deposit = Input::builder().deposit(2, 4, fp(1.0)).build().unwrap();
+        assert_eq!(
+            Err(TransactionError::DuplicateTxId { client: 2, tx: 4 }),
+            first.handle_transaction(deposit)
+        );
+    }
+
+    #[test]
+    /// A txid used in both shards should fail the merge without changing anything
+    fn merge_conflicting_txids() {
+        let fp = FixedPoint::from_f64;
+        let mut first = AccountStorage::new();
+        let mut second = AccountStorage::new();
+        let deposit = Input::builder().deposit(1, 1, fp(10.0)).build().unwrap();
+        first.handle_transaction(deposit).unwrap();
+        let deposit = Input::builder().deposit(2, 1, fp(5.0)).build().unwrap();
+        second.handle_transaction(deposit).unwrap();
+
+        assert_eq!(
+            Err(MergeError::ConflictingTxIds(vec![1])),
+            first.merge(second)
+        );
+        assert!(first.get(2).is_none());
+
+        let second = AccountStorage::builder()
+            .txid_scope(TxIdScope::PerClient)
+            .build();
+        assert_eq!(Err(MergeError::TxIdScopeMismatch), first.merge(second));
+    }
+
+    #[test]
+    /// A chargeback with the freeze policy should still allow deposits and disputes, but
+    /// nothing should leave the account
+    fn frozen_account_accepts_deposits() {
+        let fp = FixedPoint::from_f64;
+        let mut accounts = AccountStorage::builder()
+            .chargeback_policy(ChargebackPolicy::Freeze)
+            .build();
+        let inputs = [
+            Input::builder().deposit(1, 1, fp(10.0)),
+            Input::builder().deposit(1, 2, fp(3.0)),
+            Input::builder().dispute(1, 1),
+            Input::builder().chargeback(1, 1),
+        ];
+        for input in inputs {
+            accounts.handle_transaction(input.build().unwrap()).unwrap();
+        }
+        let account = accounts.get(1).unwrap();
+        assert_eq!(AccountStatus::Frozen, account.status());
+        assert!(account.locked());
+        assert_eq!(0.0, account.withdrawable());
+
+        let deposit = Input::builder().deposit(1, 3, fp(5.0)).build().unwrap();
+        accounts.handle_transaction(deposit).unwrap();
+        let dispute = Input::builder().dispute(1, 2).build().unwrap();
+        accounts.handle_transaction(dispute).unwrap();
+        let resolve = Input::builder().resolve(1, 2).build().unwrap();
+        accounts.handle_transaction(resolve).unwrap();
+        assert_eq!(8.0, accounts.get(1).unwrap().available());
+
+        let withdrawal = Input::builder().withdrawal(1, 4, fp(1.0)).build().unwrap();
+        assert_eq!(
+            Err(TransactionError::AccountFrozen),
+            accounts.handle_transaction(withdrawal)
+        );
+        let transfer = Input::builder().transfer(1, 2, 5, fp(1.0)).build().unwrap();
+        assert_eq!(
+            Err(TransactionError::AccountFrozen),
+            accounts.handle_transaction(transfer)
+        );
+        assert_eq!(8.0, accounts.get(1).unwrap().available());
+        assert!(accounts.check_integrity().is_empty());
+    }
+
+    #[test]
+    /// The default chargeback policy should lock the account, rejecting deposits as well
+    fn chargeback_locks_by_default() {
+        let mut account = Account::new();
+        let deposit = Input::builder()
+            .deposit(1, 1, FixedPoint::from_f64(1.0))
+            .build()
+            .unwrap();
+        account.handle_transaction(deposit).unwrap();
+        let dispute = Input::builder().dispute(1, 1).build().unwrap();
+        account.handle_transaction(dispute).unwrap();
+        let chargeback = Input::builder().chargeback(1, 1).build().unwrap();
+        account.handle_transaction(chargeback).unwrap();
+
+        assert_eq!(AccountStatus::Locked, account.status());
+        let deposit = Input::builder()
+            .deposit(1, 2, FixedPoint::from_f64(1.0))
+            .build()
+            .unwrap();
+        assert_eq!(
+            Err(TransactionError::AccountLocked),
+            account.handle_transaction(deposit)
+        );
+    }
+
+    #[test]
+    /// The audit log should have every applied transaction in order, with the balances
+    /// right after it
+    fn audit_log_order() {
+        let mut accounts = AccountStorage::builder().audit_log(true).build();
+        let fp = FixedPoint::from_f64;
+        let inputs = [
+            Input::builder().deposit(1, 1, fp(10.0)),
+            Input::builder().inquiry(1, 1),
+            Input::builder().dispute(1, 1),
+            Input::builder().chargeback(1, 1),
+            // rejected, the account is locked
+            Input::builder().deposit(1, 2, fp(1.0)),
+        ];
+        for input in inputs {
+            let _ = accounts.handle_transaction(input.build().unwrap());
+        }
+
+        let log: Vec<_> = accounts
+            .get(1)
+            .unwrap()
+            .audit_log()
+            .iter()
+            .map(|entry| (entry.r#type.name(), entry.tx, entry.available, entry.held))
+            .collect();
+        assert_eq!(
+            vec![
+                ("deposit", 1, fp(10.0), fp(0.0)),
+                ("inquiry", 1, fp(10.0), fp(0.0)),
+                ("dispute", 1, fp(0.0), fp(10.0)),
+                ("chargeback", 1, fp(0.0), fp(0.0)),
+            ],
+            log
+        );
+        assert_eq!(
+            Some(fp(10.0)),
+            accounts.get(1).unwrap().audit_log()[0].amount
+        );
+
+        let mut accounts = AccountStorage::new();
+        let deposit = Input::builder().deposit(1, 1, fp(1.0)).build().unwrap();
+        accounts.handle_transaction(deposit).unwrap();
+        assert!(accounts.get(1).unwrap().audit_log().is_empty());
+    }
+
+    #[test]
+    /// A deposit to a locked account should only succeed with the permissive policy, and
+    /// withdrawals should fail either way
+    fn deposit_to_locked_account() {
+        let fp = FixedPoint::from_f64;
+        for policy in [LockedDepositPolicy::Reject, LockedDepositPolicy::Accept] {
+            let mut accounts = AccountStorage::builder()
+                .locked_deposit_policy(policy)
+                .build();
+            let inputs = [
+                Input::builder().deposit(1, 1, fp(10.0)),
+                Input::builder().withdrawal(1, 2, fp(4.0)),
+                Input::builder().dispute(1, 1),
+                Input::builder().chargeback(1, 1),
+            ];
+            for input in inputs {
+                accounts.handle_transaction(input.build().unwrap()).unwrap();
+            }
+            assert_eq!(-4.0, accounts.get(1).unwrap().available());
+
+            let deposit = Input::builder().deposit(1, 3, fp(5.0)).build().unwrap();
+            let res = accounts.handle_transaction(deposit);
+            match policy {
+                LockedDepositPolicy::Reject => {
+                    assert_eq!(Err(TransactionError::AccountLocked), res);
+                    assert_eq!(-4.0, accounts.get(1).unwrap().available());
+                }
+                LockedDepositPolicy::Accept => {
+                    assert_eq!(Ok(()), res);
+                    assert_eq!(1.0, accounts.get(1).unwrap().available());
+                }
+            }
+            assert!(accounts.get(1).unwrap().locked());
+
+            let withdrawal = Input::builder().withdrawal(1, 4, fp(1.0)).build().unwrap();
+            assert_eq!(
+                Err(TransactionError::AccountLocked),
+                accounts.handle_transaction(withdrawal)
+            );
+        }
+    }
+
+    #[test]
+    /// Tests that it is not possible to withdraw money from an accound after a
+    /// successfull chargeback, since the account should then be locked.
+    fn cannot_withdraw_after_chargeback() {
+        let mut account = Account::new();
+
+        let transaction = Input::from_f64(TransactionType::Deposit, 1, 1, Some(50.0));
+        let res = account.handle_transaction(transaction);
+        assert!(
+            res.is_ok(),
+            "Deposit failed when it should succeed: {:?}",
+            res
+        );
+
+        let transaction = Input::from_f64(TransactionType::Deposit, 1, 2, Some(0.1234));
+        let res = account.handle_transaction(transaction);
+        assert!(
+            res.is_ok(),
+            "Deposit failed when it should succeed: {:?}",
+            res
+        );
+
+        let transaction = Input::new(TransactionType::Dispute, 1, 1, None);
+        let res = account.handle_transaction(transaction);
+        assert!(
+            res.is_ok(),
+            "Dispute failed when it should succeed: {:?}",
+            res
+        );
+
+        let transaction = Input::new(TransactionType::Chargeback, 1, 1, None);
+        let res = account.handle_transaction(transaction);
+
+        assert!(res.is_ok(), "Chargeback shuld succeed");
+        assert!(account.locked(), "account should be locked");
+
+        let transaction = Input::from_f64(TransactionType::Withdrawal, 1, 3, Some(0.1234));
+        let res = account.handle_transaction(transaction);
+        assert!(
+            res.is_err(),
+            "Withdrawal should not succeed since account should be locked"
+        );
+    }
+
+    #[test]
+    /// An account that has been emptied should be evicted, while one with funds stays,
+    /// and the txids of the evicted account should still count as used
+    fn evict_cold_accounts() {
+        let mut accounts = AccountStorage::new();
+
+        let transaction = Input::from_f64(TransactionType::Deposit, 1, 1, Some(10.0));
+        accounts.handle_transaction(transaction).unwrap();
+        let transaction = Input::from_f64(TransactionType::Withdrawal, 1, 2, Some(10.0));
+        accounts.handle_transaction(transaction).unwrap();
+
+        let transaction = Input::from_f64(TransactionType::Deposit, 2, 3, Some(5.0));
+        accounts.handle_transaction(transaction).unwrap();
+
+        let evicted = accounts.evict_cold();
+        assert_eq!(1, evicted.len(), "only the idle account should be evicted");
+        assert_eq!(1, evicted[0].0);
+        assert_eq!(0.0, evicted[0].1.total());
+
+        assert!(!accounts.accounts.contains_key(&1));
+        assert_eq!(5.0, accounts.accounts.get(&2).unwrap().available());
+
+        let transaction = Input::from_f64(TransactionType::Deposit, 2, 1, Some(5.0));
+        let res = accounts.handle_transaction(transaction);
+        assert!(
+            res.is_err(),
+            "txid of an evicted account should still be used: {:?}",
+            res
+        );
+    }
+
+    #[test]
+    /// An inquiry should be recorded in the audit log, with the balances it read
+    fn inquiry_is_audited() {
+        let mut accounts = AccountStorage::builder().audit_log(true).build();
+        let deposit = Input::builder()
+            .deposit(1, 1, FixedPoint::from_f64(3.0))
+            .build()
+            .unwrap();
+        accounts.handle_transaction(deposit).unwrap();
+        let inquiry = Input::builder().inquiry(1, 2).build().unwrap();
+        accounts.handle_transaction(inquiry).unwrap();
+
+        let account = accounts.get(1).unwrap();
+        let entry = account.audit_log().last().unwrap();
+        assert_eq!(
+            ("inquiry", 2, None),
+            (entry.r#type.name(), entry.tx, entry.amount)
+        );
+        let three = FixedPoint::from_f64(3.0);
+        assert_eq!((three, FixedPoint::ZERO), (entry.available, entry.held));
+        assert_eq!(
+            (three, FixedPoint::ZERO),
+            (account.available(), account.held())
+        );
+    }
+
+    #[test]
+    /// An inquiry for a client without an account should not create one
+    fn inquiry_of_unknown_client() {
+        let mut accounts = AccountStorage::new();
+        let inquiry = Input::builder().inquiry(42, 1).build().unwrap();
+        assert_eq!(Ok(()), accounts.handle_transaction(inquiry));
+        assert!(accounts.accounts().is_empty());
+    }
+
+    #[test]
+    /// An inquiry should succeed even on a locked account, without changing any balances
+    fn inquiry_does_not_mutate() {
+        let mut account = Account::new();
+
+        let deposit = Input::from_f64(TransactionType::Deposit, 1, 1, Some(50.0));
+        account.handle_transaction(deposit).unwrap();
+
+        let inquiry = Input::new(TransactionType::Inquiry, 1, 2, None);
+        let res = account.handle_transaction(inquiry);
+        assert!(res.is_ok(), "Inquiry failed: {:?}", res);
+        assert_eq!(50.0, account.available());
+        assert_eq!(0.0, account.held());
+
+        let inquiry = Input::from_f64(TransactionType::Inquiry, 1, 2, Some(1.0));
+        let res = account.handle_transaction(inquiry);
+        assert_eq!(Err(TransactionError::UnexpectedAmount), res);
+
+        let dispute = Input::new(TransactionType::Dispute, 1, 1, None);
+        account.handle_transaction(dispute).unwrap();
+        let chargeback = Input::new(TransactionType::Chargeback, 1, 1, None);
+        account.handle_transaction(chargeback).unwrap();
+        assert!(account.locked());
+
+        let inquiry = Input::new(TransactionType::Inquiry, 1, 3, None);
+        let res = account.handle_transaction(inquiry);
+        assert!(res.is_ok(), "Inquiry on a locked account failed: {:?}", res);
+        assert_eq!(0.0, account.total());
+        assert!(account.locked());
+    }
+
+    #[test]
+    /// A deposit without a positive amount should be rejected as malformed
+    fn non_positive_deposit_is_malformed() {
+        let mut accounts = AccountStorage::new();
+
+        let transaction = Input::new(TransactionType::Deposit, 1, 1, Some(FixedPoint::ZERO));
+        let res = accounts.handle_transaction(transaction);
+        assert!(
+            matches!(res, Err(TransactionError::MalformedInput)),
+            "{:?}",
+            res
+        );
+
+        let transaction = Input::from_f64(TransactionType::Deposit, 1, 2, Some(-1.0));
+        let res = accounts.handle_transaction(transaction);
+        assert!(
+            matches!(res, Err(TransactionError::MalformedInput)),
+            "{:?}",
+            res
+        );
 
-        assert_eq!(55.1234, account.available());
-        assert_eq!(55.1234, account.total());
+        assert!(accounts.accounts.is_empty());
     }
 
     #[test]
-    /// Withdrawing more than the available balance should fail and leave balance unchanged.
-    fn test_account_withdraw_too_much() {
-        let mut account = Account::new();
-
-        // Start with a deposit
-        let deposit = Input::new(TransactionType::Deposit, 1, 1, Some(55.1234));
-        account.handle_transaction(deposit).unwrap();
-
-        // Attempt to overdraw
-        let withdraw = Input::new(TransactionType::Withdrawal, 1, 2, Some(56.1234));
-        let res = account.handle_transaction(withdraw);
-        assert!(res.is_err(), "Expected withdrawal to fail");
+    /// A resolve carrying an amount should say so, instead of being generally malformed
+    fn resolve_with_amount_is_unexpected() {
+        let mut accounts = AccountStorage::new();
+        let deposit = Input::from_f64(TransactionType::Deposit, 1, 1, Some(5.0));
+        accounts.handle_transaction(deposit).unwrap();
+        let dispute = Input::new(TransactionType::Dispute, 1, 1, None);
+        accounts.handle_transaction(dispute).unwrap();
 
-        // Balance should remain unchanged
-        assert_eq!(55.1234, account.available());
-        assert_eq!(55.1234, account.total());
+        let resolve = Input::from_f64(TransactionType::Resolve, 1, 1, Some(5.0));
+        let res = accounts.handle_transaction(resolve);
+        assert_eq!(Err(TransactionError::UnexpectedAmount), res);
+        assert_eq!(5.0, accounts.accounts[&1].held());
     }
 
     #[test]
-    /// Withdrawing a valid small amount should succeed and reduce the balance.
-    fn test_account_withdraw_partial() {
-        let mut account = Account::new();
+    /// Disputing part of a deposit should only hold that part, and resolving or charging
+    /// it back should only move that part
+    fn partial_dispute() {
+        let fp = FixedPoint::from_f64;
+        for chargeback in [false, true] {
+            let mut accounts = AccountStorage::new();
+            let deposit = Input::builder().deposit(1, 1, fp(10.0)).build().unwrap();
+            accounts.handle_transaction(deposit).unwrap();
 
-        // Start with a deposit
-        let deposit = Input::new(TransactionType::Deposit, 1, 1, Some(55.1234));
-        account.handle_transaction(deposit).unwrap();
+            let dispute = Input::builder()
+                .partial_dispute(1, 1, fp(10.5))
+                .build()
+                .unwrap();
+            assert_eq!(
+                Err(TransactionError::DisputeExceedsTx),
+                accounts.handle_transaction(dispute)
+            );
+            let dispute = Input::builder()
+                .partial_dispute(1, 1, fp(4.0))
+                .build()
+                .unwrap();
+            accounts.handle_transaction(dispute).unwrap();
+            let account = accounts.get(1).unwrap();
+            assert_eq!(6.0, account.available());
+            assert_eq!(4.0, account.held());
+            assert_eq!(
+                Some(&DisputeState::Started { held: fp(4.0) }),
+                account.dispute_state(1)
+            );
 
-        // Withdraw a small amount
-        let withdraw = Input::new(TransactionType::Withdrawal, 1, 2, Some(0.1234));
-        let res = account.handle_transaction(withdraw);
-        assert!(res.is_ok(), "Partial withdrawal failed: {:?}", res);
+            let settle = if chargeback {
+                Input::builder().chargeback(1, 1)
+            } else {
+                Input::builder().resolve(1, 1)
+            };
+            accounts
+                .handle_transaction(settle.build().unwrap())
+                .unwrap();
+            let account = accounts.get(1).unwrap();
+            assert_eq!(0.0, account.held());
+            if chargeback {
+                assert_eq!(6.0, account.available());
+                assert!(account.locked());
+            } else {
+                assert_eq!(10.0, account.available());
+                assert!(!account.locked());
+            }
+        }
 
-        // Balance should decrease
-        assert_eq!(55.0, account.available());
-        assert_eq!(55.0, account.total());
+        // a dispute can not be for nothing
+        let dispute = Input::new(TransactionType::Dispute, 1, 1, Some(FixedPoint::ZERO));
+        assert!(!dispute.valid());
     }
 
     #[test]
-    /// Testing that chargebacks after a withdrawal of partial funds still succeeds
-    /// the assumption here is that a chargeback is not something that we can control,
-    /// but something that someone else is forcing upon us
-    fn test_account_chargeback_after_withdrawal() {
-        let mut account = Account::new();
+    /// A charged back dispute on an unlocked account, and a dispute without a transaction,
+    /// should both be found by the integrity check
+    fn integrity_check_finds_inconsistent_disputes() {
+        let mut accounts = AccountStorage::new();
 
-        // Start with a deposit
-        let deposit = Input::new(TransactionType::Deposit, 1, 1, Some(55.1234));
-        account.handle_transaction(deposit).unwrap();
+        let transaction = Input::from_f64(TransactionType::Deposit, 1, 1, Some(10.0));
+        accounts.handle_transaction(transaction).unwrap();
+        let transaction = Input::new(TransactionType::Dispute, 1, 1, None);
+        accounts.handle_transaction(transaction).unwrap();
+        let transaction = Input::new(TransactionType::Chargeback, 1, 1, None);
+        accounts.handle_transaction(transaction).unwrap();
 
-        // Withdraw a small amount
-        let withdraw = Input::new(TransactionType::Withdrawal, 1, 2, Some(0.1234));
-        let res = account.handle_transaction(withdraw);
-        assert!(res.is_ok(), "Partial withdrawal failed: {:?}", res);
+        let transaction = Input::from_f64(TransactionType::Deposit, 2, 2, Some(10.0));
+        accounts.handle_transaction(transaction).unwrap();
 
-        // Balance should decrease
-        assert_eq!(55.0, account.available());
-        assert_eq!(55.0, account.total());
+        assert!(accounts.check_integrity().is_empty());
 
-        let dispute = Input::new(TransactionType::Dispute, 1, 1, None);
-        let res = account.handle_transaction(dispute);
-        assert!(
-            res.is_ok(),
-            "dispute should fail since the funds are no longer there {:?}",
-            res
+        accounts.accounts.get_mut(&1).unwrap().status = AccountStatus::Active;
+        accounts.accounts.get_mut(&2).unwrap().disputes.insert(
+            3,
+            DisputeState::Started {
+                held: FixedPoint::ZERO,
+            },
         );
-        // Balance should stay the same
-        assert_eq!(-0.1234, account.available());
-        assert_eq!(55., account.total());
 
-        let chargeback = Input::new(TransactionType::Chargeback, 1, 1, None);
-        let res = account.handle_transaction(chargeback);
-        assert!(
-            res.is_ok(),
-            "dispute should fail since the funds are no longer there {:?}",
-            res
+        assert_eq!(
+            vec![
+                IntegrityIssue::ReimbursedButUnlocked { client: 1, tx: 1 },
+                IntegrityIssue::DisputeWithoutTx { client: 2, tx: 3 },
+            ],
+            accounts.check_integrity()
         );
-        // Balance should stay the same
-        assert_eq!(-0.1234, account.available());
-        assert_eq!(-0.1234, account.total());
-        assert_eq!(0.0, account.held());
-        assert_eq!(true, account.locked());
     }
 
     #[test]
-    /// Withdrawing the full remaining balance should succeed and leave account at zero.
-    fn test_account_withdraw_all() {
-        let mut account = Account::new();
+    /// A retried transaction with an already used idempotency key should return the first
+    /// outcome without applying anything, while a new key applies as usual
+    fn idempotency_key_deduplicates_retries() {
+        let mut accounts = AccountStorage::new();
 
-        // Start with a deposit
-        let deposit = Input::new(TransactionType::Deposit, 1, 1, Some(55.0));
-        account.handle_transaction(deposit).unwrap();
+        let transaction =
+            Input::from_f64(TransactionType::Deposit, 1, 1, Some(10.0)).with_idempotency_key("a");
+        assert_eq!(Ok(()), accounts.handle_transaction(transaction));
 
-        // Withdraw everything
-        let withdraw = Input::new(TransactionType::Withdrawal, 1, 2, Some(55.0));
-        let res = account.handle_transaction(withdraw);
-        assert!(res.is_ok(), "Full withdrawal failed: {:?}", res);
+        // retried with the same txid, and then with a new one
+        let transaction =
+            Input::from_f64(TransactionType::Deposit, 1, 1, Some(10.0)).with_idempotency_key("a");
+        assert_eq!(Ok(()), accounts.handle_transaction(transaction));
+        let transaction =
+            Input::from_f64(TransactionType::Deposit, 1, 2, Some(10.0)).with_idempotency_key("a");
+        assert_eq!(Ok(()), accounts.handle_transaction(transaction));
+        assert_eq!(10.0, accounts.accounts.get(&1).unwrap().available());
 
-        // Account should be empty
-        assert_eq!(0.0, account.available());
-        assert_eq!(0.0, account.total());
+        let transaction =
+            Input::from_f64(TransactionType::Deposit, 1, 3, Some(5.0)).with_idempotency_key("b");
+        assert_eq!(Ok(()), accounts.handle_transaction(transaction));
+        assert_eq!(15.0, accounts.accounts.get(&1).unwrap().available());
+
+        // failures are remembered as well
+        let transaction = Input::from_f64(TransactionType::Withdrawal, 1, 4, Some(20.0))
+            .with_idempotency_key("c");
+        let first = accounts.handle_transaction(transaction);
+        assert_eq!(Err(TransactionError::NotEnoughAvailableFunds), first);
+        let transaction = Input::from_f64(TransactionType::Withdrawal, 1, 5, Some(20.0))
+            .with_idempotency_key("c");
+        assert_eq!(first, accounts.handle_transaction(transaction));
+
+        // keys are per client
+        let transaction =
+            Input::from_f64(TransactionType::Deposit, 2, 6, Some(1.0)).with_idempotency_key("a");
+        assert_eq!(Ok(()), accounts.handle_transaction(transaction));
+        assert_eq!(1.0, accounts.accounts.get(&2).unwrap().available());
     }
 
     #[test]
-    /// tests that withdrawing not held funds still works even though an active dispute is underway
-    fn account_deposited_dispute() {
+    /// Only the available funds can be withdrawn, never a negative amount and nothing
+    /// at all from a locked account
+    fn withdrawable_amount() {
         let mut account = Account::new();
+        assert_eq!(0.0, account.withdrawable());
 
-        let transaction = Input::new(TransactionType::Deposit, 1, 1, Some(50.0));
-        let res = account.handle_transaction(transaction);
-        if let Err(e) = res {
-            assert!(true, "{:?}", e);
-        }
+        let deposit = Input::from_f64(TransactionType::Deposit, 1, 1, Some(50.0));
+        account.handle_transaction(deposit).unwrap();
+        let deposit = Input::from_f64(TransactionType::Deposit, 1, 2, Some(10.0));
+        account.handle_transaction(deposit).unwrap();
+        assert_eq!(60.0, account.withdrawable());
 
-        let transaction = Input::new(TransactionType::Deposit, 1, 2, Some(5.1234));
-        let res = account.handle_transaction(transaction);
-        if let Err(e) = res {
-            assert!(true, "{:?}", e);
-        }
-        // Withdrawing to much should fail
-        assert_eq!(55.1234, account.available());
+        // held funds are not withdrawable
+        let dispute = Input::new(TransactionType::Dispute, 1, 2, None);
+        account.handle_transaction(dispute).unwrap();
+        assert_eq!(50.0, account.withdrawable());
 
-        // Withdrawing to much should fail
-        let transaction = Input::new(TransactionType::Dispute, 1, 1, None);
-        let res = account.handle_transaction(transaction);
-        if let Err(e) = res {
-            assert!(true, "{:?}", e);
-        }
-        assert_eq!(55.1234, account.total());
-        assert_eq!(5.1234, account.available());
-        assert_eq!(50.0, account.held());
+        let withdraw = Input::from_f64(TransactionType::Withdrawal, 1, 3, Some(45.0));
+        account.handle_transaction(withdraw).unwrap();
+        let dispute = Input::new(TransactionType::Dispute, 1, 1, None);
+        account.handle_transaction(dispute).unwrap();
+        assert_eq!(-45.0, account.available());
+        assert_eq!(0.0, account.withdrawable());
 
-        // Withdrawing a small amount should work, and in this case leave exactly 5.0000 left
-        let transaction = Input::new(TransactionType::Withdrawal, 1, 3, Some(0.1234));
-        let res = account.handle_transaction(transaction);
-        if let Err(e) = res {
-            assert!(true, "{:?}", e);
-        }
+        let resolve = Input::new(TransactionType::Resolve, 1, 1, None);
+        account.handle_transaction(resolve).unwrap();
+        assert_eq!(5.0, account.withdrawable());
+
+        let chargeback = Input::new(TransactionType::Chargeback, 1, 2, None);
+        account.handle_transaction(chargeback).unwrap();
         assert_eq!(5.0, account.available());
-        assert_eq!(50.0, account.held());
-        assert_eq!(55.0, account.total());
+        assert_eq!(0.0, account.withdrawable());
     }
 
     #[test]
-    /// Tests the dispute and chargeback
-    /// by depositing into a clients account, then
-    /// disputing that deposit and returning the deposited amount
-    fn account_dispute_chargeback() {
+    /// Totals beyond what an i64 of decimillis could hold should not overflow
+    fn deposits_beyond_i64_range() {
         let mut account = Account::new();
 
-        let deposit = Input::new(TransactionType::Deposit, 1, 1, Some(50.0));
-        let res = account.handle_transaction(deposit);
-        if let Err(e) = res {
-            assert!(true, "{:?}", e);
-        }
-
-        let dispute = Input::new(TransactionType::Dispute, 1, 1, None);
-        let res = account.handle_transaction(dispute);
-        if let Err(e) = res {
-            assert!(true, "{:?}", e);
+        // i64::MAX decimillis is about 922 trillion coins
+        let amount = 900_000_000_000_000.0;
+        for tx in 0..20 {
+            let deposit = Input::from_f64(TransactionType::Deposit, 1, tx, Some(amount));
+            account.handle_transaction(deposit).unwrap();
         }
-        assert_eq!(0.0, account.available());
-        assert_eq!(50.0, account.held());
-        assert_eq!(50.0, account.total());
-        assert_eq!(false, account.locked(), "account locked state was wrong");
 
-        let chargeback = Input::new(TransactionType::Chargeback, 1, 1, None);
-        let res = account.handle_transaction(chargeback);
-        if let Err(e) = res {
-            assert!(true, "{:?}", e);
-        }
-        assert_eq!(0.0, account.held(), "held amount was wrong");
-        assert_eq!(0.0, account.available(), "available amount was wrong");
-        assert_eq!(0.0, account.total(), "total amount was wrong");
-        assert_eq!(true, account.locked(), "account locked state was wrong");
+        let expected = 20 * 900_000_000_000_000 * 10_000i128;
+        assert!(expected > i64::MAX as i128);
+        assert_eq!(expected, account.total().as_decimillis());
+        assert_eq!(expected, account.available().as_decimillis());
     }
 
     #[test]
-    fn account_dispute_resolve() {
+    /// A deposit that would overflow the balance should fail with an error instead of
+    /// panicking or wrapping around, and leave the account as it was
+    fn deposit_overflow_is_an_error() {
         let mut account = Account::new();
 
-        // Deposit funds into the account
-        let deposit = Input::new(TransactionType::Deposit, 1, 1, Some(50.0));
-        let res = account.handle_transaction(deposit);
-        assert!(res.is_ok(), "Deposit failed: {:?}", res);
-
-        // Dispute the deposit: should move funds to `held`
-        let dispute = Input::new(TransactionType::Dispute, 1, 1, None);
-        let res = account.handle_transaction(dispute);
-        assert!(res.is_ok(), "Dispute failed: {:?}", res);
+        // the largest balance is about 1.7e34
+        let deposit = Input::from_f64(TransactionType::Deposit, 1, 1, Some(9e33));
+        account.handle_transaction(deposit).unwrap();
 
-        assert_eq!(0.0, account.available());
-        assert_eq!(50.0, account.held());
-        assert_eq!(50.0, account.total());
+        let deposit = Input::from_f64(TransactionType::Deposit, 1, 2, Some(9e33));
+        let res = account.handle_transaction(deposit);
+        assert_eq!(Err(TransactionError::BalanceOverflow), res);
+        assert_eq!(9e33, account.available());
         assert!(
-            !account.locked(),
-            "Account should not be locked after a dispute"
+            !account.contains_txid(2),
+            "a failed deposit should not be disputable"
         );
+    }
 
-        // Resolve the dispute: should move funds back to `available`
-        let resolve = Input::new(TransactionType::Resolve, 1, 1, None);
-        let res = account.handle_transaction(resolve);
-        assert!(res.is_ok(), "Resolve failed: {:?}", res);
+    #[test]
+    /// The total with the held funds should not overflow either, neither through a deposit
+    /// while another is disputed nor through a disputed withdrawal
+    fn total_overflow_is_an_error() {
+        let mut account = Account::new();
+        let inputs = [
+            Input::from_f64(TransactionType::Deposit, 1, 1, Some(9e33)),
+            Input::new(TransactionType::Dispute, 1, 1, None),
+        ];
+        for input in inputs {
+            account.handle_transaction(input).unwrap();
+        }
+        let deposit = Input::from_f64(TransactionType::Deposit, 1, 2, Some(9e33));
+        let res = account.handle_transaction(deposit);
+        assert_eq!(Err(TransactionError::BalanceOverflow), res);
+        assert_eq!(0.0, account.available());
+        assert_eq!(9e33, account.held());
+
+        let mut account = Account::new();
+        let inputs = [
+            Input::from_f64(TransactionType::Deposit, 1, 1, Some(9e33)),
+            Input::from_f64(TransactionType::Withdrawal, 1, 2, Some(8.5e33)),
+            Input::from_f64(TransactionType::Deposit, 1, 3, Some(8.5e33)),
+        ];
+        for input in inputs {
+            account.handle_transaction(input).unwrap();
+        }
+        let dispute = Input::new(TransactionType::Dispute, 1, 2, None);
+        let res = account.handle_transaction(dispute);
+        assert_eq!(Err(TransactionError::BalanceOverflow), res);
+        assert_eq!(9e33, account.available());
+        assert_eq!(0.0, account.held());
+        assert_eq!(9e33, account.total());
+    }
 
+    #[test]
+    fn transaction_error_messages() {
         assert_eq!(
-            50.0,
-            account.available(),
-            "Available amount was not restored"
+            "there was not enough funds on the account to handle the transaction",
+            TransactionError::NotEnoughAvailableFunds.to_string()
         );
         assert_eq!(
-            0.0,
-            account.held(),
-            "Held amount should be cleared after resolve"
+            "the account has been locked",
+            TransactionError::AccountLocked.to_string()
         );
+
+        let boxed: Box<dyn std::error::Error> =
+            Box::new(TransactionError::DuplicateTxId { client: 2, tx: 7 });
         assert_eq!(
-            50.0,
-            account.total(),
-            "Total amount should remain unchanged"
-        );
-        assert!(
-            !account.locked(),
-            "Account should be not be locked after resolve"
+            "the transaction 7 of client 2 has already been handled",
+            boxed.to_string()
         );
     }
 
     #[test]
-    /// Depositing using a previously used TXID should fail to deposit.
-    fn test_duplicate_transaction_same_client() {
+    /// Restoring a snapshot should give the same balances, and disputes should still work
+    fn snapshot_round_trip() {
         let mut accounts = AccountStorage::new();
+        let inputs = [
+            Input::from_f64(TransactionType::Deposit, 1, 1, Some(10.5)),
+            Input::from_f64(TransactionType::Deposit, 1, 2, Some(0.1)),
+            Input::new(TransactionType::Dispute, 1, 1, None),
+            Input::from_f64(TransactionType::Deposit, 2, 3, Some(7.0)).with_idempotency_key("a"),
+            Input::new(TransactionType::Dispute, 2, 3, None),
+            Input::new(TransactionType::Chargeback, 2, 3, None),
+        ];
+        for input in inputs {
+            accounts.handle_transaction(input).unwrap();
+        }
 
-        let transaction = Input::new(TransactionType::Deposit, 1, 1234, Some(55.1234));
-        let res = accounts.handle_transaction(transaction);
-        assert!(res.is_ok(), "Deposit failed: {:?}", res);
+        let json = serde_json::to_string(&accounts.to_snapshot().unwrap()).unwrap();
+        let mut restored = AccountStorage::from_snapshot(serde_json::from_str(&json).unwrap());
 
-        let transaction = Input::new(TransactionType::Deposit, 1, 1234, Some(55.1234));
-        let res = accounts.handle_transaction(transaction);
-        assert!(res.is_err(), "Deposit failed: {:?}", res);
+        let balances = |accounts: &AccountStorage| -> Vec<_> {
+            accounts
+                .accounts()
+                .iter()
+                .map(|(client, a)| (*client, a.available(), a.held(), a.locked()))
+                .collect()
+        };
+        assert_eq!(balances(&accounts), balances(&restored));
 
-        assert_eq!(55.1234, accounts.accounts.get(&1).unwrap().available());
-        assert_eq!(55.1234, accounts.accounts.get(&1).unwrap().total());
+        // the history, disputes, used ids and idempotency keys came along
+        let resolve = Input::new(TransactionType::Resolve, 1, 1, None);
+        assert_eq!(Ok(()), restored.handle_transaction(resolve));
+        assert_eq!(10.6, restored.accounts[&1].available());
+        let duplicate = Input::from_f64(TransactionType::Deposit, 1, 2, Some(1.0));
+        assert_eq!(
+            Err(TransactionError::DuplicateTxId { client: 1, tx: 2 }),
+            restored.handle_transaction(duplicate)
+        );
+        let retry =
+            Input::from_f64(TransactionType::Deposit, 2, 3, Some(7.0)).with_idempotency_key("a");
+        assert_eq!(Ok(()), restored.handle_transaction(retry));
     }
 
     #[test]
-    /// Depositing into an different clients account using a previously used TXID should not be a valid transaction
-    fn test_duplicate_transaction_different_clients() {
-        let mut accounts = AccountStorage::new();
-
-        let transaction = Input::new(TransactionType::Deposit, 1, 1234, Some(55.1234));
-        let res = accounts.handle_transaction(transaction);
-        assert!(
-            res.is_ok(),
-            "Deposit failed when it should succeed: {:?}",
-            res
-        );
+    /// A restored storage should keep the retention window and policies, so that a replayed
+    /// txid in the window is still rejected and those past it are still forgotten
+    fn snapshot_keeps_retention() {
+        let mut accounts = AccountStorage::builder()
+            .retention(2)
+            .allow_unlock(true)
+            .dispute_policy(DisputePolicy::RequireAvailable)
+            .build();
+        let deposit = |tx| Input::from_f64(TransactionType::Deposit, 1, tx, Some(1.0));
+        for tx in 1..=3 {
+            accounts.handle_transaction(deposit(tx)).unwrap();
+        }
 
-        let transaction = Input::new(TransactionType::Deposit, 2, 1234, Some(55.1234));
-        let res = accounts.handle_transaction(transaction);
-        assert!(
-            res.is_err(),
-            "Deposit succeded when it should fail: {:?}",
-            res
-        );
+        let json = serde_json::to_string(&accounts.to_snapshot().unwrap()).unwrap();
+        let mut restored: AccountStorage =
+            AccountStorage::from_snapshot(serde_json::from_str(&json).unwrap());
+        assert_eq!(Some(2), restored.retention);
+        assert!(restored.allow_unlock);
+        assert_eq!(DisputePolicy::RequireAvailable, restored.dispute_policy);
 
-        assert_eq!(55.1234, accounts.accounts.get(&1).unwrap().available());
-        assert!(
-            accounts.accounts.get(&2).is_none(),
-            "Account 2 should not exist due to invalid input"
+        assert_eq!(
+            Err(TransactionError::DuplicateTxId { client: 1, tx: 3 }),
+            restored.handle_transaction(deposit(3))
         );
+        // the first deposit was already out of the window, and the next one pushes the second
+        // out of it as well
+        restored.handle_transaction(deposit(1)).unwrap();
+        restored.handle_transaction(deposit(2)).unwrap();
+        let account = restored.get(1).unwrap();
+        assert_eq!(vec![1, 2], Vec::from(account.recent_txids.clone()));
+        assert!(!account.contains_txid(3));
+        assert!(restored.check_integrity().is_empty());
     }
 
     #[test]
-    /// Tests that it is not possible to withdraw money from an accound after a
-    /// successfull chargeback, since the account should then be locked.
-    fn cannot_withdraw_after_chargeback() {
-        let mut account = Account::new();
+    /// The view should have the balances, disputes and history of every account, with the
+    /// amounts as exact decimal strings
+    fn storage_view_to_json() {
+        let mut accounts = AccountStorage::new();
+        let f = FixedPoint::from_f64;
+        let inputs = [
+            Input::new(TransactionType::Deposit, 1, 1, Some(f(10.5))),
+            Input::new(TransactionType::Withdrawal, 1, 2, Some(f(2.0))),
+            Input::new(TransactionType::Dispute, 1, 1, None),
+            Input::new(TransactionType::Deposit, 2, 3, Some(f(0.1))),
+        ];
+        for input in inputs {
+            accounts.handle_transaction(input).unwrap();
+        }
 
-        let transaction = Input::new(TransactionType::Deposit, 1, 1, Some(50.0));
-        let res = account.handle_transaction(transaction);
-        assert!(
-            res.is_ok(),
-            "Deposit failed when it should succeed: {:?}",
-            res
-        );
+        let json = serde_json::to_value(accounts.view().unwrap()).unwrap();
 
-        let transaction = Input::new(TransactionType::Deposit, 1, 2, Some(0.1234));
-        let res = account.handle_transaction(transaction);
-        assert!(
-            res.is_ok(),
-            "Deposit failed when it should succeed: {:?}",
-            res
-        );
+        let row = |r#type: &str, client: u16, tx: u32, amount: &str| {
+            serde_json::json!({
+                "type": r#type,
+                "client": client,
+                "tx": tx,
+                "amount": amount,
+                "to": null,
+                "idempotency_key": null,
+            })
+        };
+        let expected = serde_json::json!({
+            "accounts": {
+                "1": {
+                    "available": "-2.0000",
+                    "held": "10.5000",
+                    "total": "8.5000",
+                    "locked": false,
+                    "disputes": { "1": { "Started": { "held": "10.5000" } } },
+                    "tx_history": [
+                        row("deposit", 1, 1, "10.5000"),
+                        row("withdrawal", 1, 2, "2.0000"),
+                    ],
+                },
+                "2": {
+                    "available": "0.1000",
+                    "held": "0.0000",
+                    "total": "0.1000",
+                    "locked": false,
+                    "disputes": {},
+                    "tx_history": [row("deposit", 2, 3, "0.1000")],
+                },
+            }
+        });
+        assert_eq!(expected, json);
+    }
 
-        let transaction = Input::new(TransactionType::Dispute, 1, 1, None);
-        let res = account.handle_transaction(transaction);
-        assert!(
-            res.is_ok(),
-            "Dispute failed when it should succeed: {:?}",
-            res
-        );
+    #[test]
+    /// The sink should see every transaction in order, with its outcome
+    fn sink_records_outcomes() {
+        #[derive(Default)]
+        struct Recorder(Vec<(u32, Result<(), TransactionError>)>);
 
-        let transaction = Input::new(TransactionType::Chargeback, 1, 1, None);
-        let res = account.handle_transaction(transaction);
+        impl TransactionSink for Recorder {
+            fn on_applied(&mut self, input: &Input) {
+                self.0.push((input.tx(), Ok(())));
+            }
+            fn on_rejected(&mut self, input: &Input, err: &TransactionError) {
+                self.0.push((input.tx(), Err(err.clone())));
+            }
+        }
 
-        assert!(res.is_ok(), "Chargeback shuld succeed");
-        assert!(account.locked(), "account should be locked");
+        let mut accounts = AccountStorage::new();
+        let mut recorder = Recorder::default();
+        let inputs = [
+            Input::from_f64(TransactionType::Deposit, 1, 1, Some(5.0)),
+            Input::from_f64(TransactionType::Withdrawal, 1, 2, Some(6.0)),
+            Input::new(TransactionType::Dispute, 1, 1, None),
+        ];
+        for input in inputs {
+            let _ = accounts.handle_transaction_with_sink(input, &mut recorder);
+        }
 
-        let transaction = Input::new(TransactionType::Withdrawal, 1, 3, Some(0.1234));
-        let res = account.handle_transaction(transaction);
-        assert!(
-            res.is_err(),
-            "Withdrawal should not succeed since account should be locked"
+        assert_eq!(
+            vec![
+                (1, Ok(())),
+                (2, Err(TransactionError::NotEnoughAvailableFunds)),
+                (1, Ok(())),
+            ],
+            recorder.0
         );
     }
 }
@@ -0,0 +1,357 @@
+//! The arithmetic the accounts need from the type their balances are kept in, so that the
+//! ledger logic does not depend on the four decimals of `FixedPoint`
+
+use core::{
+    fmt,
+    ops::{Add, AddAssign, Sub, SubAssign},
+};
+
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::{FixedPoint, simple_fp::RoundingMode};
+
+/// A balance of an account. The amounts of the transactions are always parsed as
+/// `FixedPoint`, and converted with `from_fixed_point` when they reach the accounts
+pub trait AmountOps:
+    Copy
+    + Default
+    + Ord
+    + fmt::Debug
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + AddAssign
+    + SubAssign
+    + Serialize
+    + DeserializeOwned
+{
+    const ZERO: Self;
+
+    fn checked_add(self, rhs: Self) -> Option<Self>;
+
+    fn checked_sub(self, rhs: Self) -> Option<Self>;
+
+    /// The amount of a transaction in this type, None if it cannot be represented, such as
+    /// when it has more decimals than this type keeps
+    fn from_fixed_point(amount: FixedPoint) -> Option<Self>;
+
+    /// This amount with the four decimals of the output, where any decimals past them are
+    /// collapsed as the policy says. None if it does not fit, or if the policy is
+    /// `ErrorOnResidual` and there is something past the fourth decimal
+    fn to_fixed_point(self, policy: OutputScalePolicy) -> Option<FixedPoint>;
+
+    fn is_zero(self) -> bool {
+        self == Self::ZERO
+    }
+
+    fn is_negative(self) -> bool {
+        self < Self::ZERO
+    }
+}
+
+impl AmountOps for FixedPoint {
+    const ZERO: Self = FixedPoint::ZERO;
+
+    fn checked_add(self, rhs: Self) -> Option<Self> {
+        FixedPoint::checked_add(self, rhs)
+    }
+
+    fn checked_sub(self, rhs: Self) -> Option<Self> {
+        FixedPoint::checked_sub(self, rhs)
+    }
+
+    fn from_fixed_point(amount: FixedPoint) -> Option<Self> {
+        Some(amount)
+    }
+
+    fn to_fixed_point(self, _policy: OutputScalePolicy) -> Option<FixedPoint> {
+        Some(self)
+    }
+}
+
+/// How an amount that keeps more than four decimals is written with four of them, see
+/// `AmountOps::to_fixed_point`. It only applies to the output, the balances keep every
+/// decimal they have
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OutputScalePolicy {
+    /// round to the nearest, and half way to the even fourth decimal, like
+    /// `RoundingMode::HalfEven`
+    #[default]
+    RoundHalfEven,
+    /// drop the extra decimals, towards zero
+    Truncate,
+    /// fail the output if any amount has something past the fourth decimal
+    ErrorOnResidual,
+}
+
+impl OutputScalePolicy {
+    /// Collapses `n`, counted in steps `divisor` times finer than those of `FixedPoint`, to
+    /// a number of decimillis. For implementing `to_fixed_point` on an integer backend
+    pub fn scale(self, n: i128, divisor: i128) -> Option<i128> {
+        match self {
+            OutputScalePolicy::RoundHalfEven => Some(RoundingMode::HalfEven.divide(n, divisor)),
+            OutputScalePolicy::Truncate => Some(n / divisor),
+            OutputScalePolicy::ErrorOnResidual => (n % divisor == 0).then(|| n / divisor),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{
+        AccountStorage, Engine, TransactionError,
+        accounts::{AccountSnapshot, Snapshot},
+        output::{OutputConfig, write_accounts_with},
+    };
+    use serde::Deserialize;
+
+    /// A balance in whole cents
+    #[derive(
+        Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize,
+    )]
+    struct Cents(i64);
+
+    impl Add for Cents {
+        type Output = Self;
+
+        fn add(self, rhs: Self) -> Self {
+            Self(self.0 + rhs.0)
+        }
+    }
+
+    impl Sub for Cents {
+        type Output = Self;
+
+        fn sub(self, rhs: Self) -> Self {
+            Self(self.0 - rhs.0)
+        }
+    }
+
+    impl AddAssign for Cents {
+        fn add_assign(&mut self, rhs: Self) {
+            self.0 += rhs.0;
+        }
+    }
+
+    impl SubAssign for Cents {
+        fn sub_assign(&mut self, rhs: Self) {
+            self.0 -= rhs.0;
+        }
+    }
+
+    impl AmountOps for Cents {
+        const ZERO: Self = Cents(0);
+
+        fn checked_add(self, rhs: Self) -> Option<Self> {
+            self.0.checked_add(rhs.0).map(Cents)
+        }
+
+        fn checked_sub(self, rhs: Self) -> Option<Self> {
+            self.0.checked_sub(rhs.0).map(Cents)
+        }
+
+        fn from_fixed_point(amount: FixedPoint) -> Option<Self> {
+            let decimillis = amount.as_decimillis();
+            if decimillis % 100 != 0 {
+                return None;
+            }
+            i64::try_from(decimillis / 100).ok().map(Cents)
+        }
+
+        fn to_fixed_point(self, _policy: OutputScalePolicy) -> Option<FixedPoint> {
+            Some(FixedPoint::from_decimillis(i128::from(self.0) * 100))
+        }
+    }
+
+    /// A balance with six decimals, more than the output has
+    #[derive(
+        Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize,
+    )]
+    struct Micros(i64);
+
+    impl Add for Micros {
+        type Output = Self;
+
+        fn add(self, rhs: Self) -> Self {
+            Self(self.0 + rhs.0)
+        }
+    }
+
+    impl Sub for Micros {
+        type Output = Self;
+
+        fn sub(self, rhs: Self) -> Self {
+            Self(self.0 - rhs.0)
+        }
+    }
+
+    impl AddAssign for Micros {
+        fn add_assign(&mut self, rhs: Self) {
+            self.0 += rhs.0;
+        }
+    }
+
+    impl SubAssign for Micros {
+        fn sub_assign(&mut self, rhs: Self) {
+            self.0 -= rhs.0;
+        }
+    }
+
+    impl AmountOps for Micros {
+        const ZERO: Self = Micros(0);
+
+        fn checked_add(self, rhs: Self) -> Option<Self> {
+            self.0.checked_add(rhs.0).map(Micros)
+        }
+
+        fn checked_sub(self, rhs: Self) -> Option<Self> {
+            self.0.checked_sub(rhs.0).map(Micros)
+        }
+
+        fn from_fixed_point(amount: FixedPoint) -> Option<Self> {
+            let micros = amount.as_decimillis().checked_mul(100)?;
+            i64::try_from(micros).ok().map(Micros)
+        }
+
+        fn to_fixed_point(self, policy: OutputScalePolicy) -> Option<FixedPoint> {
+            policy
+                .scale(i128::from(self.0), 100)
+                .map(FixedPoint::from_decimillis)
+        }
+    }
+
+    #[test]
+    /// The same ledger logic should work on balances with two decimals, rejecting the
+    /// amounts that need more of them
+    fn engine_with_two_decimals() {
+        let input: &[u8] = b"type, client, tx, amount, to
+deposit, 1, 1, 10.25
+withdrawal, 1, 2, 0.5
+deposit, 1, 3, 0.001
+dispute, 1, 1,
+deposit, 2, 4, 1.10
+dispute, 2, 4, 0.105
+transfer, 2, 5, 0.35, 1
+";
+        let accounts = AccountStorage::builder().build_with_amount::<Cents>();
+        let mut engine = Engine::with_accounts(accounts);
+        let summary = engine.process(input).unwrap();
+        assert!(
+            summary.parse_errors.is_empty(),
+            "{:?}",
+            summary.parse_errors
+        );
+
+        assert_eq!(7, summary.processed);
+        assert_eq!(
+            Some(&2),
+            summary
+                .rejections
+                .get(TransactionError::UnrepresentableAmount.name())
+        );
+        let accounts = engine.accounts().accounts();
+        // the whole deposit is held, which is more than is left after the withdrawal
+        assert_eq!(Cents(-50 + 35), accounts[&1].available());
+        assert_eq!(Cents(1025), accounts[&1].held());
+        assert_eq!(Cents(75), accounts[&2].available());
+        assert_eq!(Cents(0), accounts[&2].held());
+    }
+
+    #[test]
+    /// Every policy should collapse the fifth decimal as it says, and only
+    /// `ErrorOnResidual` should refuse it
+    fn output_scale_policies() {
+        let amount = Micros(55_123_450);
+        let scaled = |policy| amount.to_fixed_point(policy).map(|a| a.to_string());
+        assert_eq!(
+            Some("55.1234".to_owned()),
+            scaled(OutputScalePolicy::RoundHalfEven)
+        );
+        assert_eq!(
+            Some("55.1234".to_owned()),
+            scaled(OutputScalePolicy::Truncate)
+        );
+        assert_eq!(None, scaled(OutputScalePolicy::ErrorOnResidual));
+
+        // half way rounds to the even decimal, which is up from an odd one, and above half
+        // way differs from truncating
+        let amount = Micros(55_123_550);
+        assert_eq!(
+            Some(FixedPoint::from_decimillis(551_236)),
+            amount.to_fixed_point(OutputScalePolicy::RoundHalfEven)
+        );
+        let amount = Micros(-55_123_470);
+        assert_eq!(
+            Some(FixedPoint::from_decimillis(-551_235)),
+            amount.to_fixed_point(OutputScalePolicy::RoundHalfEven)
+        );
+        assert_eq!(
+            Some(FixedPoint::from_decimillis(-551_234)),
+            amount.to_fixed_point(OutputScalePolicy::Truncate)
+        );
+
+        let amount = Micros(55_123_400);
+        for policy in [
+            OutputScalePolicy::RoundHalfEven,
+            OutputScalePolicy::Truncate,
+            OutputScalePolicy::ErrorOnResidual,
+        ] {
+            assert_eq!(
+                Some(FixedPoint::from_decimillis(551_234)),
+                amount.to_fixed_point(policy)
+            );
+        }
+    }
+
+    #[test]
+    /// The output should use the policy of the config, and fail the write under
+    /// `ErrorOnResidual` when an amount has more than four decimals
+    fn output_with_more_decimals() {
+        let account = AccountSnapshot {
+            available: Micros(55_123_450),
+            held: Micros(20),
+            status: Default::default(),
+            reactivated: false,
+            disputes: Default::default(),
+            history: Vec::new(),
+            idempotency_keys: Default::default(),
+            dispute_policy: Default::default(),
+            chargeback_policy: Default::default(),
+            locked_deposit_policy: Default::default(),
+            transaction_count: 1,
+            recent_txids: Vec::new(),
+        };
+        let accounts = AccountStorage::from_snapshot(Snapshot {
+            accounts: [(1, account)].into(),
+            txid_scope: Default::default(),
+            used_txids: Default::default(),
+            used_client_txids: Default::default(),
+            retention: None,
+            allow_unlock: false,
+            dispute_policy: Default::default(),
+            chargeback_policy: Default::default(),
+            locked_deposit_policy: Default::default(),
+            disputes_disabled: false,
+        });
+
+        let write = |scale| {
+            let config = OutputConfig {
+                scale,
+                ..Default::default()
+            };
+            let mut written = Vec::new();
+            write_accounts_with(&accounts, &mut written, &config)
+                .map(|()| String::from_utf8(written).unwrap())
+        };
+        assert_eq!(
+            "client, available, held, total, locked\n1, 55.1234, 0.0000, 55.1235, false\n",
+            write(OutputScalePolicy::RoundHalfEven).unwrap()
+        );
+        assert_eq!(
+            "client, available, held, total, locked\n1, 55.1234, 0.0000, 55.1234, false\n",
+            write(OutputScalePolicy::Truncate).unwrap()
+        );
+        let error = write(OutputScalePolicy::ErrorOnResidual).unwrap_err();
+        assert_eq!(std::io::ErrorKind::InvalidData, error.kind());
+    }
+}
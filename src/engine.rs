@@ -0,0 +1,174 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::{self, Read, Write},
+};
+
+use crate::{
+    FixedPoint,
+    accounts::{AccountStorage, TransactionError, TransactionSink},
+    amount::AmountOps,
+    input::{self, Input, ParseErrorRecord},
+    output::{self, OutputConfig},
+};
+
+/// Ties the parsing of the input, the accounts and the output together, for embedding the
+/// engine without having to replicate what the binary does
+#[derive(Default)]
+pub struct Engine<A: AmountOps = FixedPoint> {
+    accounts: AccountStorage<A>,
+    output_config: OutputConfig,
+}
+
+/// How the rows of one or more inputs were handled
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Summary {
+    /// Rows that parsed and were handed to the accounts, including the rejected ones
+    pub processed: usize,
+    /// Rows the accounts refused, such as withdrawals without enough funds
+    pub rejected: usize,
+    /// The rejected rows by the `TransactionError::name` of why they were refused
+    pub rejections: BTreeMap<&'static str, usize>,
+    /// Rows that could not be parsed into a transaction at all
+    pub parse_errors: Vec<ParseErrorRecord>,
+}
+
+impl Summary {
+    /// Every row of the input, whether it could be parsed or not
+    pub fn read(&self) -> usize {
+        self.processed + self.parse_errors.len()
+    }
+
+    /// Rows that changed the accounts, or were inquiries about them
+    pub fn applied(&self) -> usize {
+        self.processed - self.rejected
+    }
+
+    /// Counts a transaction that was handed to the accounts, with how they handled it
+    pub(crate) fn count(&mut self, outcome: Result<(), TransactionError>) {
+        self.processed += 1;
+        if let Err(e) = outcome {
+            self.rejected += 1;
+            *self.rejections.entry(e.name()).or_default() += 1;
+        }
+    }
+
+    /// Adds the counts of another input to this summary
+    pub fn extend(&mut self, other: Summary) {
+        self.processed += other.processed;
+        self.rejected += other.rejected;
+        for (name, count) in other.rejections {
+            *self.rejections.entry(name).or_default() += count;
+        }
+        self.parse_errors.extend(other.parse_errors);
+    }
+}
+
+/// The counts on one line, followed by a line for every kind of rejection
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "read {} rows, applied {}, rejected {}, failed to parse {}",
+            self.read(),
+            self.applied(),
+            self.rejected,
+            self.parse_errors.len()
+        )?;
+        for (name, count) in &self.rejections {
+            write!(f, "\n  {}: {}", name, count)?;
+        }
+        Ok(())
+    }
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads every transaction of the csv and applies it to the accounts, then writes the
+    /// accounts to `output`. Rows that fail to parse or are rejected are counted in the
+    /// summary, only failing to read or write gives an error
+    pub fn run<R: Read, W: Write>(&mut self, input: R, output: W) -> io::Result<Summary> {
+        let summary = self.process(input)?;
+        output::write_buffered(&self.accounts, output, &self.output_config)?;
+        Ok(summary)
+    }
+}
+
+impl<A: AmountOps> Engine<A> {
+    /// Runs the engine on accounts configured up front, such as with `AccountStorage::builder`
+    pub fn with_accounts(accounts: AccountStorage<A>) -> Self {
+        Self {
+            accounts,
+            output_config: OutputConfig::default(),
+        }
+    }
+
+    /// Selects how `run` writes the accounts
+    pub fn output_config(mut self, output_config: OutputConfig) -> Self {
+        self.output_config = output_config;
+        self
+    }
+
+    pub fn accounts(&self) -> &AccountStorage<A> {
+        &self.accounts
+    }
+
+    pub fn into_accounts(self) -> AccountStorage<A> {
+        self.accounts
+    }
+
+    /// Applies every transaction of the csv to the accounts without writing anything, so
+    /// that several inputs can be fed to the same accounts before the output is written
+    pub fn process<R: Read>(&mut self, input: R) -> io::Result<Summary> {
+        self.process_with_sink(input, &mut IgnoreOutcomes)
+    }
+
+    /// Same as `process`, with the outcome of every transaction also reported to the sink
+    pub fn process_with_sink<R: Read, S: TransactionSink + ?Sized>(
+        &mut self,
+        input: R,
+        sink: &mut S,
+    ) -> io::Result<Summary> {
+        // partners use different delimiters, so it is guessed from the header
+        let mut csv_reader = input::untrimmed_autodetect(input)?;
+        let mut parse_errors = Vec::new();
+        let mut summary = self.apply_with_sink(
+            input::deserialize_with_errors(&mut csv_reader, &mut parse_errors),
+            sink,
+        );
+        summary.parse_errors = parse_errors;
+        Ok(summary)
+    }
+
+    /// Applies already parsed transactions, such as from another input format
+    pub fn apply(&mut self, transactions: impl IntoIterator<Item = Input>) -> Summary {
+        self.apply_with_sink(transactions, &mut IgnoreOutcomes)
+    }
+
+    /// Same as `apply`, with the outcome of every transaction also reported to the sink
+    pub fn apply_with_sink<S: TransactionSink + ?Sized>(
+        &mut self,
+        transactions: impl IntoIterator<Item = Input>,
+        sink: &mut S,
+    ) -> Summary {
+        let mut summary = Summary::default();
+        for transaction in transactions {
+            summary.count(
+                self.accounts
+                    .handle_transaction_with_sink(transaction, sink),
+            );
+        }
+        summary
+    }
+}
+
+/// The sink of `process` and `apply`, which only need the counts of the summary
+struct IgnoreOutcomes;
+
+impl TransactionSink for IgnoreOutcomes {
+    fn on_applied(&mut self, _input: &Input) {}
+    fn on_rejected(&mut self, _input: &Input, _err: &TransactionError) {}
+}
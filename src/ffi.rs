@@ -0,0 +1,222 @@
+//! A C ABI over an `AccountStorage`, for handling transactions from a C or C++ service.
+//! `include/payeng.h` declares the same functions and codes for C
+//!
+//! The library is only built as a `cdylib` when asked for, so that the other builds do not
+//! have to link one:
+//!
+//! ```text
+//! cargo rustc --release --lib --features ffi --crate-type cdylib
+//! ```
+//!
+//! Ownership: `peng_new` returns a handle owned by the caller, which has to be released with
+//! `peng_free` exactly once, and must not be used after that. Every function accepts a null
+//! handle and reports it instead of touching it. The amount of `peng_handle` is only read
+//! during the call. Amounts are whole decimillis, ten thousandths, the four decimals of
+//! `FixedPoint`
+//!
+//! A handle may be moved between threads, but must not be used from two at once
+
+use alloc::boxed::Box;
+
+use crate::{AccountStorage, FixedPoint, Input, TransactionError, TransactionType};
+
+/// The transaction was applied
+pub const PENG_OK: i32 = 0;
+/// The handle was null
+pub const PENG_NULL_HANDLE: i32 = -1;
+/// The type is none of the `PENG_` type codes
+pub const PENG_UNKNOWN_TYPE: i32 = -2;
+
+// The type codes of `peng_handle`. Transfers need a receiving client, which `peng_handle`
+// has no argument for, so they have no code
+pub const PENG_DEPOSIT: u32 = 0;
+pub const PENG_WITHDRAWAL: u32 = 1;
+pub const PENG_DISPUTE: u32 = 2;
+pub const PENG_RESOLVE: u32 = 3;
+pub const PENG_CHARGEBACK: u32 = 4;
+pub const PENG_INQUIRY: u32 = 5;
+pub const PENG_UNLOCK: u32 = 6;
+pub const PENG_VOID: u32 = 8;
+
+/// The accounts behind a handle, opaque to C
+pub struct PengHandle {
+    accounts: AccountStorage,
+}
+
+/// Creates an empty storage with the default settings, to be released with `peng_free`
+#[unsafe(no_mangle)]
+pub extern "C" fn peng_new() -> *mut PengHandle {
+    Box::into_raw(Box::new(PengHandle {
+        accounts: AccountStorage::new(),
+    }))
+}
+
+/// Releases the handle and its accounts, a null handle is ignored
+///
+/// # Safety
+///
+/// The handle has to be null or come from `peng_new`, and not have been released already
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn peng_free(handle: *mut PengHandle) {
+    if !handle.is_null() {
+        // Safety: the handle came from `Box::into_raw` in `peng_new`, and is released once
+        drop(unsafe { Box::from_raw(handle) });
+    }
+}
+
+/// Handles a transaction, `amount` points to its amount in decimillis, or is null for the
+/// types that have none. A dispute may have one, the part of the transaction it disputes.
+/// Returns `PENG_OK` if it was applied, the code of the `TransactionError` it was rejected
+/// with, which are all positive, or a negative code if the arguments were unusable
+///
+/// # Safety
+///
+/// The handle has to be null or a live handle from `peng_new`, not used by another thread
+/// during the call. The amount has to be null or point to an `int64_t`
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn peng_handle(
+    handle: *mut PengHandle,
+    r#type: u32,
+    client: u16,
+    tx: u32,
+    amount: *const i64,
+) -> i32 {
+    // Safety: a live handle is only used by this call, as guaranteed by the caller
+    let Some(handle) = (unsafe { handle.as_mut() }) else {
+        return PENG_NULL_HANDLE;
+    };
+    let Some(r#type) = transaction_type(r#type) else {
+        return PENG_UNKNOWN_TYPE;
+    };
+    // Safety: a non null amount points to an `int64_t`, as guaranteed by the caller
+    let amount =
+        unsafe { amount.as_ref() }.map(|amount| FixedPoint::from_decimillis((*amount).into()));
+
+    match handle
+        .accounts
+        .handle_transaction(Input::new(r#type, client, tx, amount))
+    {
+        Ok(()) => PENG_OK,
+        Err(e) => error_code(&e),
+    }
+}
+
+/// The available funds of the client in decimillis, saturated to the range of an `int64_t`.
+/// A client without an account and a null handle both have none
+///
+/// # Safety
+///
+/// The handle has to be null or a live handle from `peng_new`, not changed by another thread
+/// during the call
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn peng_available(handle: *const PengHandle, client: u16) -> i64 {
+    // Safety: a live handle, as guaranteed by the caller
+    let Some(handle) = (unsafe { handle.as_ref() }) else {
+        return 0;
+    };
+    let available = match handle.accounts.accounts().get(&client) {
+        Some(account) => account.available().as_decimillis(),
+        None => return 0,
+    };
+    available.clamp(i64::MIN.into(), i64::MAX.into()) as i64
+}
+
+fn transaction_type(code: u32) -> Option<TransactionType> {
+    Some(match code {
+        PENG_DEPOSIT => TransactionType::Deposit,
+        PENG_WITHDRAWAL => TransactionType::Withdrawal,
+        PENG_DISPUTE => TransactionType::Dispute,
+        PENG_RESOLVE => TransactionType::Resolve,
+        PENG_CHARGEBACK => TransactionType::Chargeback,
+        PENG_INQUIRY => TransactionType::Inquiry,
+        PENG_UNLOCK => TransactionType::Unlock,
+        PENG_VOID => TransactionType::Void,
+        _ => return None,
+    })
+}
+
+/// The code of the error returned by `peng_handle`, these stay the same so that they can be
+/// compiled into the callers, new errors get new codes
+fn error_code(error: &TransactionError) -> i32 {
+    match error {
+        TransactionError::MalformedInput => 1,
+        TransactionError::UnexpectedAmount => 2,
+        TransactionError::NotEnoughAvailableFunds => 3,
+        TransactionError::MissingTxId { .. } => 4,
+        TransactionError::DuplicateTxId { .. } => 5,
+        TransactionError::TxBelongsToOtherClient { .. } => 6,
+        TransactionError::AccountLocked => 7,
+        TransactionError::AccountFrozen => 8,
+        TransactionError::InvalidTx => 9,
+        TransactionError::InvalidTxForDispute => 10,
+        TransactionError::MissingDisputeTx => 11,
+        TransactionError::DisputeAlreadyExist => 12,
+        TransactionError::DisputeAlreadyHandled => 13,
+        TransactionError::HistoryUnavailable => 14,
+        TransactionError::BalanceOverflow => 15,
+        TransactionError::UnlockNotAllowed => 16,
+        TransactionError::HeldWouldGoNegative => 17,
+        TransactionError::InvalidTxForVoid => 18,
+        TransactionError::FundsAlreadyMoved => 19,
+        TransactionError::DisputeExceedsTx => 20,
+        TransactionError::UnknownAccount { .. } => 21,
+        TransactionError::AccountNotEmpty => 22,
+        TransactionError::OpenDisputes => 23,
+        TransactionError::DisputesDisabled => 24,
+        TransactionError::UnrepresentableAmount => 25,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::ptr;
+
+    use super::*;
+
+    #[test]
+    /// Every function should work through the raw handle, and report the rejections and a
+    /// null handle with their codes
+    fn drive_through_raw_pointers() {
+        let handle = peng_new();
+        assert!(!handle.is_null());
+        let (ten, three) = (100_000i64, 30_000i64);
+
+        unsafe {
+            assert_eq!(PENG_OK, peng_handle(handle, PENG_DEPOSIT, 1, 1, &ten));
+            assert_eq!(PENG_OK, peng_handle(handle, PENG_WITHDRAWAL, 1, 2, &three));
+            assert_eq!(70_000, peng_available(handle, 1));
+
+            // more than is available
+            assert_eq!(3, peng_handle(handle, PENG_WITHDRAWAL, 1, 3, &ten));
+            // the deposit id again
+            assert_eq!(5, peng_handle(handle, PENG_DEPOSIT, 1, 1, &ten));
+            // a withdrawal needs an amount
+            assert_ne!(
+                PENG_OK,
+                peng_handle(handle, PENG_WITHDRAWAL, 1, 4, ptr::null())
+            );
+            assert_eq!(PENG_UNKNOWN_TYPE, peng_handle(handle, 7, 1, 5, &three));
+
+            assert_eq!(
+                PENG_OK,
+                peng_handle(handle, PENG_DISPUTE, 1, 1, ptr::null())
+            );
+            assert_eq!(-30_000, peng_available(handle, 1));
+            assert_eq!(
+                PENG_OK,
+                peng_handle(handle, PENG_CHARGEBACK, 1, 1, ptr::null())
+            );
+            assert_eq!(7, peng_handle(handle, PENG_DEPOSIT, 1, 6, &ten));
+
+            assert_eq!(0, peng_available(handle, 2));
+            assert_eq!(
+                PENG_NULL_HANDLE,
+                peng_handle(ptr::null_mut(), PENG_DEPOSIT, 1, 7, &ten)
+            );
+            assert_eq!(0, peng_available(ptr::null(), 1));
+
+            peng_free(handle);
+            peng_free(ptr::null_mut());
+        }
+    }
+}
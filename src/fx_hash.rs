@@ -0,0 +1,117 @@
+//! The hash function of the Rust compiler, which is a lot faster than the default SipHash
+//! for small keys such as tx ids. It gives no protection against keys picked to collide, which
+//! is fine for the maps of this crate, as their keys come from the input of a batch run
+//!
+//! `alloc` has no hash maps, so without the `std` feature the maps are B-trees instead, which
+//! need ordered keys but no hasher
+
+#[cfg(not(feature = "std"))]
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::hash::Hasher;
+#[cfg(feature = "std")]
+use std::{
+    collections::{HashMap, HashSet},
+    hash::BuildHasherDefault,
+};
+
+/// A `HashMap` hashed with `FxHasher`
+#[cfg(feature = "std")]
+pub type FxHashMap<K, V> = HashMap<K, V, BuildHasherDefault<FxHasher>>;
+/// A `HashSet` hashed with `FxHasher`
+#[cfg(feature = "std")]
+pub type FxHashSet<T> = HashSet<T, BuildHasherDefault<FxHasher>>;
+#[cfg(not(feature = "std"))]
+pub type FxHashMap<K, V> = BTreeMap<K, V>;
+#[cfg(not(feature = "std"))]
+pub type FxHashSet<T> = BTreeSet<T>;
+
+/// Mixes every word written into the hash with a rotate, xor and multiply
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FxHasher {
+    hash: u64,
+}
+
+impl FxHasher {
+    const SEED: u64 = 0x51_7c_c1_b7_27_22_0a_95;
+
+    fn add_to_hash(&mut self, word: u64) {
+        self.hash = (self.hash.rotate_left(5) ^ word).wrapping_mul(Self::SEED);
+    }
+}
+
+impl Hasher for FxHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        let mut chunks = bytes.chunks_exact(8);
+        for chunk in &mut chunks {
+            self.add_to_hash(u64::from_le_bytes(chunk.try_into().unwrap()));
+        }
+        let mut rest = chunks.remainder();
+        if rest.len() >= 4 {
+            self.add_to_hash(u64::from(u32::from_le_bytes(rest[..4].try_into().unwrap())));
+            rest = &rest[4..];
+        }
+        if rest.len() >= 2 {
+            self.add_to_hash(u64::from(u16::from_le_bytes(rest[..2].try_into().unwrap())));
+            rest = &rest[2..];
+        }
+        if let Some(byte) = rest.first() {
+            self.add_to_hash(u64::from(*byte));
+        }
+    }
+
+    fn write_u8(&mut self, i: u8) {
+        self.add_to_hash(u64::from(i));
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.add_to_hash(u64::from(i));
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.add_to_hash(u64::from(i));
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.add_to_hash(i);
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.add_to_hash(i as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        hash::{BuildHasher, BuildHasherDefault},
+    };
+
+    use super::*;
+
+    #[test]
+    /// Consecutive ids, like those of an input, should not collide, and strings of any length
+    /// should hash all of their bytes
+    fn distinct_hashes() {
+        let build = BuildHasherDefault::<FxHasher>::default();
+        let hashes: HashSet<u64> = (0..100_000u32).map(|tx| build.hash_one(tx)).collect();
+        assert_eq!(100_000, hashes.len());
+
+        let keys = [
+            "",
+            "a",
+            "ab",
+            "abc",
+            "abcd",
+            "abcde",
+            "abcdefghi",
+            "abcdefghij",
+        ];
+        let hashes: HashSet<u64> = keys.iter().map(|key| build.hash_one(key)).collect();
+        assert_eq!(keys.len(), hashes.len());
+    }
+}
@@ -0,0 +1,297 @@
+use alloc::{collections::BTreeMap, vec::Vec};
+
+#[cfg(feature = "disk-history")]
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use crate::input::Input;
+#[cfg(feature = "disk-history")]
+use crate::{FixedPoint, input::TransactionType};
+
+/// Selects where the accounts of an `AccountStorage` keep their transaction history
+#[derive(Clone, Default)]
+pub enum HistoryBackend {
+    /// Every transaction is kept in memory, which is the fastest but grows with the input
+    #[default]
+    Memory,
+    /// The transactions are appended to a file shared by all accounts, only the offsets
+    /// into that file are kept in memory
+    #[cfg(feature = "disk-history")]
+    Disk(Arc<Mutex<DiskLog>>),
+}
+
+impl HistoryBackend {
+    /// Creates a disk backed history at the given path, any existing file is truncated
+    #[cfg(feature = "disk-history")]
+    pub fn disk<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::Disk(Arc::new(Mutex::new(DiskLog::create(path)?))))
+    }
+
+    /// Creates an empty history for a new account
+    pub(crate) fn new_history(&self) -> TxHistory {
+        match self {
+            HistoryBackend::Memory => TxHistory::Memory(BTreeMap::new()),
+            #[cfg(feature = "disk-history")]
+            HistoryBackend::Disk(log) => TxHistory::Disk {
+                log: log.clone(),
+                index: BTreeMap::new(),
+            },
+        }
+    }
+}
+
+/// The transactions performed on a single account, that disputes can be looked up from
+pub enum TxHistory {
+    Memory(BTreeMap<u32, Input>),
+    /// Nothing is kept, for accounts of an `AccountStorage` that rejects every dispute
+    Disabled,
+    #[cfg(feature = "disk-history")]
+    Disk {
+        log: Arc<Mutex<DiskLog>>,
+        /// offset into the log for every stored tx
+        index: BTreeMap<u32, u64>,
+    },
+}
+
+/// The history could not be written to or read from its backing storage
+#[derive(Debug)]
+pub struct HistoryError;
+
+impl TxHistory {
+    pub fn contains(&self, tx: u32) -> bool {
+        match self {
+            TxHistory::Memory(map) => map.contains_key(&tx),
+            TxHistory::Disabled => false,
+            #[cfg(feature = "disk-history")]
+            TxHistory::Disk { index, .. } => index.contains_key(&tx),
+        }
+    }
+
+    pub fn insert(&mut self, input: Input) -> Result<(), HistoryError> {
+        match self {
+            TxHistory::Memory(map) => {
+                map.insert(input.tx(), input);
+                Ok(())
+            }
+            TxHistory::Disabled => Ok(()),
+            #[cfg(feature = "disk-history")]
+            TxHistory::Disk { log, index } => {
+                let offset = log
+                    .lock()
+                    .map_err(|_| HistoryError)?
+                    .append(&input)
+                    .map_err(|_| HistoryError)?;
+                index.insert(input.tx(), offset);
+                Ok(())
+            }
+        }
+    }
+
+    /// Forgets the transaction, with the disk backend the record stays in the log file
+    pub fn remove(&mut self, tx: u32) {
+        match self {
+            TxHistory::Memory(map) => {
+                map.remove(&tx);
+            }
+            TxHistory::Disabled => {}
+            #[cfg(feature = "disk-history")]
+            TxHistory::Disk { index, .. } => {
+                index.remove(&tx);
+            }
+        }
+    }
+
+    /// Fetches a copy of the stored transaction
+    pub fn get(&self, tx: u32) -> Result<Option<Input>, HistoryError> {
+        match self {
+            TxHistory::Memory(map) => Ok(map.get(&tx).cloned()),
+            TxHistory::Disabled => Ok(None),
+            #[cfg(feature = "disk-history")]
+            TxHistory::Disk { log, index } => match index.get(&tx) {
+                Some(offset) => {
+                    let mut log = log.lock().map_err(|_| HistoryError)?;
+                    log.read(*offset).map(Some).map_err(|_| HistoryError)
+                }
+                None => Ok(None),
+            },
+        }
+    }
+
+    /// Reads back every stored transaction, in the order of their tx
+    pub fn inputs(&self) -> Result<Vec<Input>, HistoryError> {
+        match self {
+            TxHistory::Memory(map) => Ok(map.values().cloned().collect()),
+            TxHistory::Disabled => Ok(Vec::new()),
+            #[cfg(feature = "disk-history")]
+            TxHistory::Disk { index, .. } => index
+                .keys()
+                .map(|tx| self.get(*tx)?.ok_or(HistoryError))
+                .collect(),
+        }
+    }
+}
+
+impl Default for TxHistory {
+    fn default() -> Self {
+        TxHistory::Memory(BTreeMap::new())
+    }
+}
+
+/// Append only file of fixed size transaction records
+#[cfg(feature = "disk-history")]
+pub struct DiskLog {
+    file: File,
+    len: u64,
+}
+
+#[cfg(feature = "disk-history")]
+impl DiskLog {
+    /// type, client, tx, amount flag, the decimillis of the amount, receiver flag and the
+    /// client a transfer goes to. The idempotency key is not kept, since retries are looked up
+    /// on the account and never in its history
+    const RECORD_LEN: usize = 1 + 2 + 4 + 1 + 16 + 1 + 2;
+
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        Ok(Self { file, len: 0 })
+    }
+
+    /// Writes the transaction at the end of the log, returning the offset it was written at
+    fn append(&mut self, input: &Input) -> io::Result<u64> {
+        let mut record = [0u8; Self::RECORD_LEN];
+        record[0] = match input.r#type() {
+            TransactionType::Deposit => 0,
+            TransactionType::Withdrawal => 1,
+            TransactionType::Dispute => 2,
+            TransactionType::Resolve => 3,
+            TransactionType::Chargeback => 4,
+            TransactionType::Inquiry => 5,
+            TransactionType::Unlock => 6,
+            TransactionType::Transfer => 7,
+            TransactionType::Void => 8,
+        };
+        record[1..3].copy_from_slice(&input.client().to_le_bytes());
+        record[3..7].copy_from_slice(&input.tx().to_le_bytes());
+        if let Some(amount) = input.amount() {
+            record[7] = 1;
+            record[8..24].copy_from_slice(&amount.as_decimillis().to_le_bytes());
+        }
+        if let Some(to) = input.to() {
+            record[24] = 1;
+            record[25..27].copy_from_slice(&to.to_le_bytes());
+        }
+
+        let offset = self.len;
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(&record)?;
+        self.len += Self::RECORD_LEN as u64;
+        Ok(offset)
+    }
+
+    fn read(&mut self, offset: u64) -> io::Result<Input> {
+        let mut record = [0u8; Self::RECORD_LEN];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut record)?;
+
+        let r#type = match record[0] {
+            0 => TransactionType::Deposit,
+            1 => TransactionType::Withdrawal,
+            2 => TransactionType::Dispute,
+            3 => TransactionType::Resolve,
+            4 => TransactionType::Chargeback,
+            5 => TransactionType::Inquiry,
+            6 => TransactionType::Unlock,
+            7 => TransactionType::Transfer,
+            8 => TransactionType::Void,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "bad tx type")),
+        };
+        let client = u16::from_le_bytes([record[1], record[2]]);
+        let tx = u32::from_le_bytes(record[3..7].try_into().unwrap());
+        let amount = if record[7] == 1 {
+            Some(FixedPoint::from_decimillis(i128::from_le_bytes(
+                record[8..24].try_into().unwrap(),
+            )))
+        } else {
+            None
+        };
+        let to = (record[24] == 1).then(|| u16::from_le_bytes([record[25], record[26]]));
+        Ok(Input::new(r#type, client, tx, amount).with_to(to))
+    }
+}
+
+#[cfg(all(test, feature = "disk-history"))]
+mod tests {
+    use super::*;
+    use crate::accounts::AccountStorage;
+
+    #[test]
+    /// Disputes, resolves and chargebacks should work the same when the history only has
+    /// its offsets in memory and the transactions themselves are read back from disk
+    fn disputes_with_disk_history() {
+        let path = std::env::temp_dir().join(format!(
+            "payeng-disputes-with-disk-history-{}.bin",
+            std::process::id()
+        ));
+        let history = HistoryBackend::disk(&path).unwrap();
+        let mut accounts = AccountStorage::builder().history(history).build();
+
+        for tx in 1..=100 {
+            let client = (tx % 4) as u16;
+            let deposit = Input::from_f64(TransactionType::Deposit, client, tx, Some(1.5));
+            accounts.handle_transaction(deposit).unwrap();
+        }
+        let len = std::fs::metadata(&path).unwrap().len();
+        assert_eq!(100 * DiskLog::RECORD_LEN as u64, len);
+
+        // client 1 has deposits 1, 5, 9 ...
+        let dispute = Input::new(TransactionType::Dispute, 1, 5, None);
+        accounts.handle_transaction(dispute).unwrap();
+        let dispute = Input::new(TransactionType::Dispute, 1, 9, None);
+        accounts.handle_transaction(dispute).unwrap();
+        let resolve = Input::new(TransactionType::Resolve, 1, 9, None);
+        accounts.handle_transaction(resolve).unwrap();
+
+        let account = accounts.accounts().get(&1).unwrap();
+        assert_eq!(1.5, account.held());
+        assert_eq!(36.0, account.available());
+        assert!(account.contains_txid(97));
+
+        // client 2 has deposits 2, 6, 10 ...
+        let dispute = Input::new(TransactionType::Dispute, 2, 10, None);
+        accounts.handle_transaction(dispute).unwrap();
+        let chargeback = Input::new(TransactionType::Chargeback, 2, 10, None);
+        accounts.handle_transaction(chargeback).unwrap();
+
+        let account = accounts.accounts().get(&2).unwrap();
+        assert_eq!(0.0, account.held());
+        assert_eq!(36.0, account.total());
+        assert!(account.locked());
+
+        // a tx from another client is not in this accounts history
+        let dispute = Input::new(TransactionType::Dispute, 3, 5, None);
+        assert!(accounts.handle_transaction(dispute).is_err());
+
+        // the receiving client of a transfer is kept
+        let history = HistoryBackend::disk(&path).unwrap();
+        let mut transfers = history.new_history();
+        let transfer = Input::builder()
+            .transfer(1, 2, 200, FixedPoint::from_f64(0.5))
+            .build()
+            .unwrap();
+        transfers.insert(transfer).unwrap();
+        let stored = transfers.get(200).unwrap().unwrap();
+        assert_eq!(Some(2), stored.to());
+        assert_eq!(Some(FixedPoint::from_f64(0.5)), stored.amount());
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -1,11 +1,26 @@
-use std::fs::File;
+use alloc::string::String;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::{
+    borrow::BorrowMut,
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    num::IntErrorKind,
+    path::Path,
+};
+
+#[cfg(feature = "std")]
+use flate2::read::GzDecoder;
+#[cfg(feature = "std")]
+use serde_json::value::RawValue;
 
 use crate::FixedPoint;
 
-use csv::Reader;
-use serde::Deserialize;
+#[cfg(feature = "std")]
+use csv::{Reader, StringRecord};
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de};
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Input {
     /// This is the type of the input, it can only be a fixed amount of values
     r#type: TransactionType,
@@ -14,8 +29,17 @@ pub struct Input {
     client: u16,
 
     tx: u32,
-    /// These are fixed point numbers, but we will treat them as f64 for simple serialization and deserialization
-    amount: Option<f64>,
+    /// Parsed straight from its text into a fixed point number, so that amounts such as `0.1`,
+    /// or ones with more digits than a float keeps, are exact. An empty value is the same as
+    /// no amount
+    amount: Option<FixedPoint>,
+
+    /// The client receiving the funds of a transfer
+    to: Option<u16>,
+
+    /// Client supplied key identifying the logical operation, a retried transaction with the
+    /// same key for the same client is only handled once
+    idempotency_key: Option<String>,
 }
 
 impl Input {
@@ -24,15 +48,36 @@ impl Input {
         match self.r#type {
             TransactionType::Deposit | TransactionType::Withdrawal => {
                 // We dont allow negative values, since that is basically what the type is declaring
-                if let Some(amount) = self.amount {
-                    if amount > 0.0 { true } else { false }
-                } else {
-                    false
-                }
+                self.amount_as_fp().is_some_and(FixedPoint::is_positive)
             }
-            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
-                self.amount.is_none()
+            TransactionType::Transfer => {
+                self.amount_as_fp().is_some_and(FixedPoint::is_positive)
+                    && self.to.is_some_and(|to| to != self.client)
             }
+            // a dispute of only part of the transaction has the disputed amount
+            TransactionType::Dispute => self.amount_as_fp().is_none_or(FixedPoint::is_positive),
+            TransactionType::Resolve
+            | TransactionType::Chargeback
+            | TransactionType::Inquiry
+            | TransactionType::Unlock
+            | TransactionType::Void => self.amount.is_none(),
+        }
+    }
+
+    /// If an amount was given for a type that refers to another transaction, or takes no
+    /// amount at all, which is one of the reasons the input is not `valid`. Only a dispute
+    /// may refer to another transaction with an amount, the part of it that is disputed
+    pub fn has_unexpected_amount(&self) -> bool {
+        match self.r#type {
+            TransactionType::Deposit
+            | TransactionType::Withdrawal
+            | TransactionType::Transfer
+            | TransactionType::Dispute => false,
+            TransactionType::Resolve
+            | TransactionType::Chargeback
+            | TransactionType::Inquiry
+            | TransactionType::Unlock
+            | TransactionType::Void => self.amount.is_some(),
         }
     }
 
@@ -47,8 +92,18 @@ impl Input {
     }
 
     /// Get the input's amount
+    pub fn amount(&self) -> Option<FixedPoint> {
+        self.amount
+    }
+
+    /// Get the input's amount, the same as `amount`
     pub fn amount_as_fp(&self) -> Option<FixedPoint> {
-        self.amount.map(|v| FixedPoint::from_f64(v))
+        self.amount
+    }
+
+    /// Get the client a transfer goes to
+    pub fn to(&self) -> Option<u16> {
+        self.to
     }
 
     /// Get the input's tx.
@@ -56,45 +111,640 @@ impl Input {
         self.tx
     }
 
-    /// only to create easier test transactions
-    #[cfg(test)]
-    pub fn new(r#type: TransactionType, client: u16, tx: u32, amount: Option<f64>) -> Self {
+    /// Get the input's idempotency key
+    pub fn idempotency_key(&self) -> Option<&str> {
+        self.idempotency_key.as_deref()
+    }
+
+    /// Sets the idempotency key of the input
+    pub fn with_idempotency_key(mut self, key: impl Into<String>) -> Self {
+        self.idempotency_key = Some(key.into());
+        self
+    }
+
+    /// Sets the client a transfer goes to, for reading back stored transactions
+    #[cfg(feature = "disk-history")]
+    pub(crate) fn with_to(mut self, to: Option<u16>) -> Self {
+        self.to = to;
+        self
+    }
+
+    /// Creates an input directly, for tests and for reading back stored transactions, see
+    /// `builder` for creating inputs that are checked to be valid
+    pub fn new(r#type: TransactionType, client: u16, tx: u32, amount: Option<FixedPoint>) -> Self {
         Self {
             r#type,
             client,
             tx,
             amount,
+            to: None,
+            idempotency_key: None,
+        }
+    }
+
+    /// Same as `new` with the amount as a float, an amount that cannot be represented is
+    /// left out, for tests
+    #[cfg(test)]
+    pub(crate) fn from_f64(
+        r#type: TransactionType,
+        client: u16,
+        tx: u32,
+        amount: Option<f64>,
+    ) -> Self {
+        Self::new(
+            r#type,
+            client,
+            tx,
+            amount.and_then(FixedPoint::try_from_f64),
+        )
+    }
+
+    /// Builds a transaction without going through csv
+    ///
+    /// ```
+    /// use payeng::input::{Input, TransactionType};
+    ///
+    /// let dispute = Input::builder().dispute(1, 42).build().unwrap();
+    /// assert!(matches!(dispute.r#type(), TransactionType::Dispute));
+    /// assert_eq!(None, dispute.amount());
+    /// ```
+    pub fn builder() -> InputBuilder {
+        InputBuilder::default()
+    }
+}
+
+/// The input given to `InputBuilder::build` was not `valid`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidInput;
+
+/// Builder for an `Input`, created with `Input::builder`. Pick the kind of transaction with
+/// one of the methods named after it, then `build`
+#[derive(Debug, Clone, Default)]
+pub struct InputBuilder {
+    input: Option<Input>,
+    idempotency_key: Option<String>,
+}
+
+impl InputBuilder {
+    fn transaction(
+        mut self,
+        r#type: TransactionType,
+        client: u16,
+        tx: u32,
+        amount: Option<FixedPoint>,
+    ) -> Self {
+        self.input = Some(Input::new(r#type, client, tx, amount));
+        self
+    }
+
+    pub fn deposit(self, client: u16, tx: u32, amount: FixedPoint) -> Self {
+        self.transaction(TransactionType::Deposit, client, tx, Some(amount))
+    }
+
+    pub fn withdrawal(self, client: u16, tx: u32, amount: FixedPoint) -> Self {
+        self.transaction(TransactionType::Withdrawal, client, tx, Some(amount))
+    }
+
+    /// Moves `amount` of the available funds of `client` to the client `to`
+    pub fn transfer(mut self, client: u16, to: u16, tx: u32, amount: FixedPoint) -> Self {
+        let mut input = Input::new(TransactionType::Transfer, client, tx, Some(amount));
+        input.to = Some(to);
+        self.input = Some(input);
+        self
+    }
+
+    /// Disputes the transaction `tx` of the client
+    pub fn dispute(self, client: u16, tx: u32) -> Self {
+        self.transaction(TransactionType::Dispute, client, tx, None)
+    }
+
+    /// Disputes only `amount` of the transaction `tx` of the client
+    pub fn partial_dispute(self, client: u16, tx: u32, amount: FixedPoint) -> Self {
+        self.transaction(TransactionType::Dispute, client, tx, Some(amount))
+    }
+
+    pub fn resolve(self, client: u16, tx: u32) -> Self {
+        self.transaction(TransactionType::Resolve, client, tx, None)
+    }
+
+    pub fn chargeback(self, client: u16, tx: u32) -> Self {
+        self.transaction(TransactionType::Chargeback, client, tx, None)
+    }
+
+    pub fn inquiry(self, client: u16, tx: u32) -> Self {
+        self.transaction(TransactionType::Inquiry, client, tx, None)
+    }
+
+    pub fn unlock(self, client: u16, tx: u32) -> Self {
+        self.transaction(TransactionType::Unlock, client, tx, None)
+    }
+
+    /// Voids the deposit `tx` of the client
+    pub fn void(self, client: u16, tx: u32) -> Self {
+        self.transaction(TransactionType::Void, client, tx, None)
+    }
+
+    pub fn idempotency_key(mut self, key: impl Into<String>) -> Self {
+        self.idempotency_key = Some(key.into());
+        self
+    }
+
+    /// Fails if no transaction was chosen, or it is not `valid`, such as a deposit of zero
+    pub fn build(self) -> Result<Input, InvalidInput> {
+        let mut input = self.input.filter(Input::valid).ok_or(InvalidInput)?;
+        input.idempotency_key = self.idempotency_key;
+        Ok(input)
+    }
+}
+
+/// The formats transactions can be read in
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    Csv,
+    /// One json object per line
+    JsonLines,
+}
+
+#[cfg(feature = "std")]
+impl InputFormat {
+    /// Picks the format from the extension of the path, ignoring a trailing `.gz`,
+    /// anything not recognized is read as csv
+    pub fn from_path(pathname: &str) -> Self {
+        let pathname = pathname.strip_suffix(".gz").unwrap_or(pathname);
+        match Path::new(pathname).extension().and_then(|ext| ext.to_str()) {
+            Some("jsonl" | "ndjson") => InputFormat::JsonLines,
+            _ => InputFormat::Csv,
         }
     }
 }
 
-pub fn create_input_deserializer(pathname: &str) -> Reader<File> {
-    let file = File::open(pathname).unwrap();
+/// Opens the file at the path, files ending in `.gz` are decompressed while they are read
+#[cfg(feature = "std")]
+pub fn open_input(pathname: &str) -> std::io::Result<Box<dyn Read>> {
+    let file = File::open(pathname)?;
+
+    if pathname.ends_with(".gz") {
+        Ok(Box::new(GzDecoder::new(file)))
+    } else {
+        Ok(Box::new(file))
+    }
+}
+
+/// The delimiter used when none is given
+pub const DEFAULT_DELIMITER: u8 = b',';
 
-    let rdr = csv::ReaderBuilder::new()
-        .delimiter(b',')
-        .trim(csv::Trim::All)
+/// Opens the csv at the path, files ending in `.gz` are decompressed while they are read
+#[cfg(feature = "std")]
+pub fn create_input_deserializer(pathname: &str) -> std::io::Result<Reader<Box<dyn Read>>> {
+    create_input_deserializer_with(pathname, DEFAULT_DELIMITER)
+}
+
+/// Same as `create_input_deserializer`, with the fields separated by `delimiter`, such as
+/// `b';'` or `b'\t'`
+#[cfg(feature = "std")]
+pub fn create_input_deserializer_with(
+    pathname: &str,
+    delimiter: u8,
+) -> std::io::Result<Reader<Box<dyn Read>>> {
+    Ok(create_input_deserializer_from_reader_with(
+        open_input(pathname)?,
+        delimiter,
+    ))
+}
+
+/// Same as `create_input_deserializer`, but reads the csv from any reader, such as stdin
+#[cfg(feature = "std")]
+pub fn create_input_deserializer_from_reader<R: Read>(reader: R) -> Reader<R> {
+    create_input_deserializer_from_reader_with(reader, DEFAULT_DELIMITER)
+}
+
+/// Same as `create_input_deserializer_from_reader`, with the fields separated by `delimiter`.
+///
+/// A leading UTF-8 byte order mark is always stripped by the csv reader, and empty lines
+/// as well as lines starting with `#` are skipped
+#[cfg(feature = "std")]
+pub fn create_input_deserializer_from_reader_with<R: Read>(reader: R, delimiter: u8) -> Reader<R> {
+    reader_with(reader, delimiter, csv::Trim::All)
+}
+
+/// Same as `create_input_deserializer_from_reader_with`, with only the headers trimmed, for
+/// `transactions` and `Engine::process`. They trim the fields into a reused record with
+/// `deserialize_trimmed`, since the reader allocates a new record for every row it trims
+#[cfg(feature = "std")]
+fn untrimmed_reader<R: Read>(reader: R, delimiter: u8) -> Reader<R> {
+    reader_with(reader, delimiter, csv::Trim::Headers)
+}
+
+#[cfg(feature = "std")]
+fn reader_with<R: Read>(reader: R, delimiter: u8, trim: csv::Trim) -> Reader<R> {
+    csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .comment(Some(b'#'))
+        .trim(trim)
         .flexible(true)
-        .from_reader(file);
-    rdr
+        .from_reader(reader)
+}
+
+/// The delimiters `sniff_delimiter` picks between
+#[cfg(feature = "std")]
+const SNIFFED_DELIMITERS: [u8; 3] = [b',', b';', b'\t'];
+
+/// Guesses the delimiter from the header line, the first line that is not blank or a comment,
+/// as the one of `,`, `;` and tab that occurs most. Falls back to comma when there is a tie
+#[cfg(feature = "std")]
+pub fn sniff_delimiter(data: &[u8]) -> u8 {
+    let line = data
+        .split(|b| *b == b'\n')
+        .find(|line| !line.starts_with(b"#") && !line.trim_ascii().is_empty())
+        .unwrap_or_default();
+
+    let count = |delimiter: u8| line.iter().filter(|b| **b == delimiter).count();
+    let mut counts = SNIFFED_DELIMITERS.map(|delimiter| (count(delimiter), delimiter));
+    counts.sort_by_key(|(count, _)| std::cmp::Reverse(*count));
+    match counts {
+        [(most, delimiter), (next, _), _] if most > next => delimiter,
+        _ => DEFAULT_DELIMITER,
+    }
+}
+
+/// Same as `create_input_deserializer_from_reader`, but with the delimiter picked by
+/// `sniff_delimiter` from the start of the input, for when it is not known up front
+#[cfg(feature = "std")]
+pub fn create_input_deserializer_autodetect<R: Read>(
+    reader: R,
+) -> std::io::Result<Reader<BufReader<R>>> {
+    let mut reader = BufReader::new(reader);
+    let delimiter = sniff_delimiter(reader.fill_buf()?);
+    Ok(create_input_deserializer_from_reader_with(
+        reader, delimiter,
+    ))
+}
+
+/// Same as `create_input_deserializer_autodetect`, with only the headers trimmed like
+/// `untrimmed_reader`, for `Engine::process`
+#[cfg(feature = "std")]
+pub(crate) fn untrimmed_autodetect<R: Read>(reader: R) -> std::io::Result<Reader<BufReader<R>>> {
+    let mut reader = BufReader::new(reader);
+    let delimiter = sniff_delimiter(reader.fill_buf()?);
+    Ok(untrimmed_reader(reader, delimiter))
+}
+
+/// A row of the input that could not be parsed into an `Input`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseErrorRecord {
+    /// The line of the input the row starts at, if known
+    pub line: Option<u64>,
+    /// What kind of problem the row had
+    pub kind: ParseErrorKind,
+    /// The error as reported by the parser
+    pub error: String,
+}
+
+/// Categories of rows that could not be parsed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// A number was too large for its field, such as a client above `u16::MAX`
+    OutOfRange {
+        /// The index of the field in the row
+        field: Option<u64>,
+    },
+    /// Any other problem with the row
+    Malformed,
 }
 
-#[derive(Copy, Clone, Debug, Deserialize)]
-#[serde(rename_all = "lowercase")]
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseErrorKind::OutOfRange { field: Some(field) } => {
+                write!(f, "field {} out of range", field)
+            }
+            ParseErrorKind::OutOfRange { field: None } => write!(f, "out of range"),
+            ParseErrorKind::Malformed => write!(f, "malformed"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<&serde_json::Error> for ParseErrorRecord {
+    fn from(error: &serde_json::Error) -> Self {
+        Self {
+            line: Some(error.line() as u64),
+            kind: ParseErrorKind::Malformed,
+            error: error.to_string(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<&csv::Error> for ParseErrorRecord {
+    fn from(error: &csv::Error) -> Self {
+        let kind = match error.kind() {
+            csv::ErrorKind::Deserialize { err, .. } => match err.kind() {
+                csv::DeserializeErrorKind::ParseInt(e)
+                    if matches!(
+                        e.kind(),
+                        IntErrorKind::PosOverflow | IntErrorKind::NegOverflow
+                    ) =>
+                {
+                    ParseErrorKind::OutOfRange { field: err.field() }
+                }
+                _ => ParseErrorKind::Malformed,
+            },
+            _ => ParseErrorKind::Malformed,
+        };
+        Self {
+            line: error.position().map(|pos| pos.line()),
+            kind,
+            error: error.to_string(),
+        }
+    }
+}
+
+/// Deserializes newline delimited json, one transaction per line, into the same `Input`
+/// as the csv rows. Every line is parsed on its own, so a line that fails to parse is
+/// reported with its line number and the lines after it are still read. Blank lines are
+/// skipped
+#[cfg(feature = "std")]
+pub fn deserialize_jsonl<R: Read>(
+    reader: R,
+) -> impl Iterator<Item = Result<Input, ParseErrorRecord>> {
+    let mut lines = BufReader::new(reader).lines().enumerate();
+    let mut failed = false;
+    std::iter::from_fn(move || {
+        // once reading fails nothing after it can be read
+        if failed {
+            return None;
+        }
+        for (i, line) in lines.by_ref() {
+            let line_number = i as u64 + 1;
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    failed = true;
+                    return Some(Err(ParseErrorRecord {
+                        line: Some(line_number),
+                        kind: ParseErrorKind::Malformed,
+                        error: e.to_string(),
+                    }));
+                }
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            return Some(input_from_json(line.as_bytes()).map_err(|e| {
+                let mut error = ParseErrorRecord::from(&e);
+                error.line = Some(line_number);
+                error
+            }));
+        }
+        None
+    })
+}
+
+/// Deserializes a single json object into an `Input`, one row of `deserialize_jsonl`
+#[cfg(feature = "std")]
+pub(crate) fn input_from_json(line: &[u8]) -> serde_json::Result<Input> {
+    serde_json::from_slice::<JsonInput>(line).map(Input::from)
+}
+
+/// The fields of an `Input` as they come in json, where an amount may also be a number, see
+/// `json_amount`
+#[cfg(feature = "std")]
+#[derive(Deserialize)]
+struct JsonInput {
+    r#type: TransactionType,
+    client: u16,
+    tx: u32,
+    #[serde(default, deserialize_with = "json_amount")]
+    amount: Option<FixedPoint>,
+    to: Option<u16>,
+    idempotency_key: Option<String>,
+}
+
+/// An amount as a string, or as a number parsed from its text the same way as in csv, so
+/// that a number such as `0.1` or one with more digits than a float keeps is exact
+#[cfg(feature = "std")]
+fn json_amount<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<FixedPoint>, D::Error> {
+    let Some(raw) = Option::<&RawValue>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    let text = raw.get();
+    if text.starts_with('"') {
+        // a string, which may still have escapes in it
+        return serde_json::from_str(text)
+            .map(Some)
+            .map_err(de::Error::custom);
+    }
+    text.parse()
+        .map(Some)
+        .map_err(|e| de::Error::custom(format_args!("invalid amount {}: {}", text, e)))
+}
+
+#[cfg(feature = "std")]
+impl From<JsonInput> for Input {
+    fn from(json: JsonInput) -> Self {
+        Self {
+            r#type: json.r#type,
+            client: json.client,
+            tx: json.tx,
+            amount: json.amount,
+            to: json.to,
+            idempotency_key: json.idempotency_key,
+        }
+    }
+}
+
+/// Deserializes a single csv record, a blank record gives None
+#[cfg(feature = "std")]
+fn deserialize_record(
+    record: &StringRecord,
+    headers: Option<&StringRecord>,
+) -> csv::Result<Option<Input>> {
+    // every field is trimmed, so a line of whitespace is only empty fields
+    if record.iter().all(str::is_empty) {
+        return Ok(None);
+    }
+    record.deserialize::<Input>(headers).map(Some)
+}
+
+/// Same as `deserialize_record`, with the fields of the record trimmed into `trimmed` first,
+/// which is reused for every row
+#[cfg(feature = "std")]
+pub(crate) fn deserialize_trimmed(
+    record: &StringRecord,
+    trimmed: &mut StringRecord,
+    headers: Option<&StringRecord>,
+) -> csv::Result<Option<Input>> {
+    trimmed.clear();
+    trimmed.extend(record.iter().map(str::trim));
+    // keeps the line numbers of the errors
+    trimmed.set_position(record.position().cloned());
+    deserialize_record(trimmed, headers)
+}
+
+/// Deserializes the rows of the reader with their fields trimmed, skipping blank ones. Every
+/// row is read and trimmed into the same two records, so once they have grown to fit the
+/// longest row, reading a row allocates nothing
+#[cfg(feature = "std")]
+fn deserialize_rows<R: Read, B: BorrowMut<Reader<R>>>(
+    mut reader: B,
+    headers: Option<StringRecord>,
+) -> impl Iterator<Item = csv::Result<Input>> {
+    let mut record = StringRecord::new();
+    let mut trimmed = StringRecord::new();
+    std::iter::from_fn(move || {
+        loop {
+            match reader.borrow_mut().read_record(&mut record) {
+                Ok(false) => return None,
+                Ok(true) => {}
+                Err(e) => return Some(Err(e)),
+            }
+            if let Some(input) =
+                deserialize_trimmed(&record, &mut trimmed, headers.as_ref()).transpose()
+            {
+                return Some(input);
+            }
+        }
+    })
+}
+
+/// Deserializes every row of the reader, rows that fail to parse are recorded in `errors`
+/// instead of silently being dropped. Rows with nothing but whitespace are skipped
+#[cfg(feature = "std")]
+pub fn deserialize_with_errors<'a, R: Read>(
+    reader: &'a mut Reader<R>,
+    errors: &'a mut Vec<ParseErrorRecord>,
+) -> impl Iterator<Item = Input> + 'a {
+    let headers = match reader.headers() {
+        Ok(headers) => Some(headers.clone()),
+        Err(e) => {
+            errors.push(ParseErrorRecord::from(&e));
+            None
+        }
+    };
+
+    deserialize_rows(reader, headers).filter_map(move |row| {
+        row.map_err(|e| errors.push(ParseErrorRecord::from(&e)))
+            .ok()
+    })
+}
+
+/// The transactions of a csv read from any source, such as a socket or a buffer, with the
+/// same settings as `create_input_deserializer_from_reader`
+#[cfg(feature = "std")]
+pub fn transactions<R: Read>(reader: R) -> impl Iterator<Item = Result<Input, csv::Error>> {
+    let mut reader = untrimmed_reader(reader, DEFAULT_DELIMITER);
+    let (headers, header_error) = match reader.headers() {
+        Ok(headers) => (Some(headers.clone()), None),
+        Err(e) => (None, Some(e)),
+    };
+
+    header_error
+        .map(Err)
+        .into_iter()
+        .chain(deserialize_rows(reader, headers))
+}
+
+#[derive(Copy, Clone, Debug)]
 pub enum TransactionType {
     Deposit,
     Withdrawal,
     Dispute,
     Resolve,
     Chargeback,
+    /// Reading the balance of an account, does not change anything but is still a recorded event
+    Inquiry,
+    /// Reactivates a locked account after it has been reviewed, only handled when the
+    /// `AccountStorage` is built to allow it
+    Unlock,
+    /// Moves available funds from the client to the client in the `to` column
+    Transfer,
+    /// Reverts a deposit that was entered in error, only possible while all of its funds are
+    /// still available
+    Void,
+}
+
+impl TransactionType {
+    /// The name the type is written as in the input
+    pub fn name(self) -> &'static str {
+        match self {
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::Inquiry => "inquiry",
+            TransactionType::Unlock => "unlock",
+            TransactionType::Transfer => "transfer",
+            TransactionType::Void => "void",
+        }
+    }
+
+    const ALL: [TransactionType; 9] = [
+        TransactionType::Deposit,
+        TransactionType::Withdrawal,
+        TransactionType::Dispute,
+        TransactionType::Resolve,
+        TransactionType::Chargeback,
+        TransactionType::Inquiry,
+        TransactionType::Unlock,
+        TransactionType::Transfer,
+        TransactionType::Void,
+    ];
+
+    /// The names the types are written as in the input, in the same order as `ALL`
+    const VARIANTS: &'static [&'static str] = &[
+        "deposit",
+        "withdrawal",
+        "dispute",
+        "resolve",
+        "chargeback",
+        "inquiry",
+        "unlock",
+        "transfer",
+        "void",
+    ];
+}
+
+impl Serialize for TransactionType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
 }
 
-#[cfg(test)]
+/// The type is matched without regard to case, since some producers write `Deposit` or
+/// `DEPOSIT`, anything else is still an error
+impl<'de> Deserialize<'de> for TransactionType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct TypeVisitor;
+
+        impl de::Visitor<'_> for TypeVisitor {
+            type Value = TransactionType;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a transaction type")
+            }
+
+            // borrowing the name avoids allocating it for every row
+            fn visit_str<E: de::Error>(self, name: &str) -> Result<Self::Value, E> {
+                TransactionType::ALL
+                    .into_iter()
+                    .find(|r#type| r#type.name().eq_ignore_ascii_case(name))
+                    .ok_or_else(|| E::unknown_variant(name, TransactionType::VARIANTS))
+            }
+        }
+
+        deserializer.deserialize_str(TypeVisitor)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use super::*;
     #[test]
     fn parsing_input_works() {
-        let mut rdr = create_input_deserializer("testdata/input.csv");
+        let mut rdr = create_input_deserializer("testdata/input.csv").unwrap();
 
         let amount: Vec<Input> = rdr
             .deserialize()
@@ -105,4 +755,432 @@ mod tests {
 
         assert_eq!(8, amount.len());
     }
+
+    #[test]
+    /// The public readers trim every field, so that they can be deserialized directly
+    fn public_reader_deserializes_spaced_fields() {
+        let data: &[u8] =
+            b"type, client, tx, amount\ndeposit, 1, 1, 1.5\nwithdrawal,  2 , 2 ,0.5 \n";
+        let mut rdr = create_input_deserializer_from_reader(data);
+
+        let inputs: Vec<Input> = rdr.deserialize().map(Result::unwrap).collect();
+        assert_eq!(2, inputs.len());
+        assert_eq!((1, 1), (inputs[0].client(), inputs[0].tx()));
+        assert_eq!(Some(FixedPoint::from_f64(1.5)), inputs[0].amount_as_fp());
+        assert_eq!((2, 2), (inputs[1].client(), inputs[1].tx()));
+        assert_eq!(Some(FixedPoint::from_f64(0.5)), inputs[1].amount_as_fp());
+    }
+
+    #[test]
+    fn parsing_tab_delimited_input_works() {
+        let mut rdr = create_input_deserializer_with("testdata/input.tsv", b'\t').unwrap();
+
+        let amount: Vec<Input> = rdr
+            .deserialize()
+            .map(|e: Result<Input, _>| e.unwrap())
+            .filter(|tx| tx.valid())
+            .collect();
+
+        assert_eq!(8, amount.len());
+    }
+
+    #[test]
+    /// amounts such as NaN and infinity parse as f64, but should never be valid
+    fn non_finite_amounts_are_invalid() {
+        for amount in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            let input = Input::from_f64(TransactionType::Deposit, 1, 1, Some(amount));
+            assert!(input.amount_as_fp().is_none());
+            assert!(!input.valid(), "{} should not be a valid amount", amount);
+        }
+    }
+
+    #[test]
+    /// amounts are parsed exactly, and ones that cannot be represented, such as NaN, infinity
+    /// or too many decimals, fail to parse instead of being rounded into something else
+    fn amounts_are_parsed_exactly() {
+        let data: &[u8] = b"type, client, tx, amount
+deposit, 1, 1, 0.1
+deposit, 1, 2, 5
+deposit, 1, 3, NaN
+deposit, 1, 4, inf
+deposit, 1, 5, 0.12345
+deposit, 1, 6, 0.12340
+";
+        let mut rdr = create_input_deserializer_from_reader(data);
+        let mut errors = Vec::new();
+
+        let inputs: Vec<Input> = deserialize_with_errors(&mut rdr, &mut errors).collect();
+
+        let amounts: Vec<_> = inputs.iter().map(|input| input.amount()).collect();
+        assert_eq!(
+            vec![
+                Some(FixedPoint::from_decimillis(1000)),
+                Some(FixedPoint::from_decimillis(50000)),
+                Some(FixedPoint::from_decimillis(1234)),
+            ],
+            amounts
+        );
+        let lines: Vec<_> = errors.iter().map(|error| error.line).collect();
+        assert_eq!(vec![Some(4), Some(5), Some(6)], lines, "{:?}", errors);
+    }
+
+    #[test]
+    /// amounts with more significant digits than a float keeps should come through as
+    /// written, through the public reader and through the reused records
+    fn long_amounts_are_exact() {
+        let data: &[u8] = b"type, client, tx, amount
+deposit, 1, 1, 1234567890123.4567
+deposit, 1, 2, 99999999999999.9999
+";
+        let expected = vec![
+            Some(FixedPoint::from_decimillis(12345678901234567)),
+            Some(FixedPoint::from_decimillis(999999999999999999)),
+        ];
+
+        let mut rdr = create_input_deserializer_from_reader(data);
+        let amounts: Vec<_> = rdr
+            .deserialize::<Input>()
+            .map(|input| input.unwrap().amount())
+            .collect();
+        assert_eq!(expected, amounts);
+
+        let amounts: Vec<_> = transactions(data)
+            .map(|input| input.unwrap().amount())
+            .collect();
+        assert_eq!(expected, amounts);
+    }
+
+    #[test]
+    /// a row that cannot be parsed should be reported with its line, without stopping the rest
+    fn parse_errors_are_collected() {
+        let mut rdr = create_input_deserializer("testdata/parse_errors.csv").unwrap();
+        let mut errors = Vec::new();
+
+        let inputs: Vec<Input> = deserialize_with_errors(&mut rdr, &mut errors).collect();
+
+        assert_eq!(2, inputs.len());
+        assert_eq!(1, errors.len(), "{:?}", errors);
+        assert_eq!(Some(3), errors[0].line);
+        assert_eq!(ParseErrorKind::Malformed, errors[0].kind);
+        assert!(
+            errors[0].error.contains("invalid digit"),
+            "{}",
+            errors[0].error
+        );
+    }
+
+    #[test]
+    /// the reader based deserializer should parse the same way as reading from a file
+    fn parsing_from_reader_works() {
+        let data: &[u8] = b"type, client, tx, amount\ndeposit, 1, 1, 1.5\nwithdrawal, 1, 2, 0.5\n";
+        let mut rdr = create_input_deserializer_from_reader(data);
+        let mut errors = Vec::new();
+
+        let inputs: Vec<Input> = deserialize_with_errors(&mut rdr, &mut errors).collect();
+
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(2, inputs.len());
+        assert_eq!(Some(FixedPoint::from_f64(1.5)), inputs[0].amount());
+        assert_eq!(2, inputs[1].tx());
+    }
+
+    #[test]
+    /// a gzipped file should give the same rows as the plain csv
+    fn parsing_gzip_input_works() {
+        use flate2::{Compression, write::GzEncoder};
+        use std::io::Write;
+
+        let plain = std::fs::read("testdata/input.csv").unwrap();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&plain).unwrap();
+        let path = std::env::temp_dir().join(format!("payeng-input-{}.csv.gz", std::process::id()));
+        std::fs::write(&path, encoder.finish().unwrap()).unwrap();
+
+        let rows = |mut rdr: Reader<_>| -> Vec<String> {
+            let mut errors = Vec::new();
+            deserialize_with_errors(&mut rdr, &mut errors)
+                .map(|input| format!("{:?}", input))
+                .collect()
+        };
+        let gzipped = rows(create_input_deserializer(path.to_str().unwrap()).unwrap());
+        let expected = rows(create_input_deserializer("testdata/input.csv").unwrap());
+        let _ = std::fs::remove_file(&path);
+
+        assert!(!expected.is_empty());
+        assert_eq!(expected, gzipped);
+    }
+
+    #[test]
+    /// json lines should parse into the same inputs as csv, with the amount as a string or
+    /// a number, and left out for the transactions that have none
+    fn parsing_jsonl_works() {
+        let data: &[u8] = br#"{"type":"deposit","client":1,"tx":1,"amount":"5.0"}
+{"type":"withdrawal","client":1,"tx":2,"amount":"1.5"}
+{"type":"dispute","client":1,"tx":1}
+{"type":"resolve","client":1,"tx":1,"amount":null}
+{"type":"chargeback","client":1,"tx":1,"amount":"2.0"}
+{"type":"deposit","client":1,"tx":3,"amount":7}
+"#;
+        let inputs: Vec<Input> = deserialize_jsonl(data).map(Result::unwrap).collect();
+
+        assert_eq!(6, inputs.len());
+        assert_eq!(Some(FixedPoint::from_f64(5.0)), inputs[0].amount());
+        assert_eq!(Some(FixedPoint::from_f64(1.5)), inputs[1].amount());
+        assert!(matches!(inputs[2].r#type(), TransactionType::Dispute));
+        assert!(inputs[2].valid());
+        assert!(inputs[3].valid());
+        // an amount on a chargeback is malformed, the same as in csv
+        assert!(!inputs[4].valid());
+        assert_eq!(Some(FixedPoint::from_f64(7.0)), inputs[5].amount());
+
+        // numbers are parsed from their text, the same as the amounts of a csv
+        let numbers: &[u8] = br#"{"type":"deposit","client":1,"tx":1,"amount":1.5}
+{"type":"deposit","client":1,"tx":2,"amount":1e2}
+{"type":"deposit","client":1,"tx":3,"amount":12345678901234.5678}
+{"type":"deposit","client":1,"tx":4,"amount":0.12345}
+"#;
+        let amounts: Vec<_> = deserialize_jsonl(numbers)
+            .map(|row| row.map(|input| input.amount()))
+            .collect();
+        assert_eq!(Ok(Some(FixedPoint::from_f64(1.5))), amounts[0]);
+        assert_eq!(Ok(Some(FixedPoint::from_f64(100.0))), amounts[1]);
+        assert_eq!(
+            Ok(Some(FixedPoint::from_decimillis(123456789012345678))),
+            amounts[2]
+        );
+        assert!(amounts[3].is_err());
+
+        assert_eq!(
+            InputFormat::JsonLines,
+            InputFormat::from_path("txns.jsonl.gz")
+        );
+        assert_eq!(InputFormat::Csv, InputFormat::from_path("txns.csv"));
+    }
+
+    #[test]
+    /// a line that fails to parse should be reported with its line number, and the lines
+    /// after it should still be read
+    fn jsonl_continues_after_bad_line() {
+        let data: &[u8] = br#"{"type":"deposit","client":1,"tx":1,"amount":"5.0"}
+{"type":"deposit","client":1,
+{"type":"deposit","client":2,"tx":2,"amount":"1.0"}
+
+{"type":"bogus","client":3,"tx":3}
+{"type":"withdrawal","client":2,"tx":4,"amount":"0.5"}
+"#;
+        let rows: Vec<_> = deserialize_jsonl(data).collect();
+
+        let clients: Vec<_> = rows.iter().flatten().map(Input::client).collect();
+        assert_eq!(vec![1, 2, 2], clients);
+        let lines: Vec<_> = rows
+            .iter()
+            .filter_map(|row| row.as_ref().err())
+            .map(|error| error.line)
+            .collect();
+        assert_eq!(vec![Some(2), Some(5)], lines);
+    }
+
+    #[test]
+    /// the type should parse regardless of its case, while unknown types still fail
+    fn transaction_type_ignores_case() {
+        let data: &[u8] = b"type, client, tx, amount
+deposit, 1, 1, 1.0
+Deposit, 1, 2, 1.0
+DEPOSIT, 1, 3, 1.0
+WithDrawal, 1, 4, 1.0
+Dispute, 1, 1,
+deposits, 1, 5, 1.0
+";
+        let mut rdr = create_input_deserializer_from_reader(data);
+        let mut errors = Vec::new();
+
+        let inputs: Vec<Input> = deserialize_with_errors(&mut rdr, &mut errors).collect();
+
+        let types: Vec<_> = inputs.iter().map(|input| *input.r#type()).collect();
+        assert!(
+            matches!(
+                types.as_slice(),
+                [
+                    TransactionType::Deposit,
+                    TransactionType::Deposit,
+                    TransactionType::Deposit,
+                    TransactionType::Withdrawal,
+                    TransactionType::Dispute,
+                ]
+            ),
+            "{:?}",
+            types
+        );
+        assert_eq!(1, errors.len(), "{:?}", errors);
+        assert_eq!(Some(7), errors[0].line);
+        assert!(
+            errors[0].error.contains("unknown variant"),
+            "{}",
+            errors[0].error
+        );
+    }
+
+    #[test]
+    /// a byte order mark and blank lines, as spreadsheet exports tend to have, should not
+    /// turn into malformed rows
+    fn bom_and_blank_lines_are_ignored() {
+        let data: &[u8] = b"\xEF\xBB\xBFtype, client, tx, amount
+deposit, 1, 1, 1.0
+
+deposit, 1, 2, 2.0
+  \t
+withdrawal, 1, 3, 0.5
+
+
+";
+        let mut rdr = create_input_deserializer_from_reader(data);
+        let mut errors = Vec::new();
+
+        let inputs: Vec<Input> = deserialize_with_errors(&mut rdr, &mut errors).collect();
+
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(3, inputs.len());
+        assert!(inputs.iter().all(Input::valid));
+        assert!(matches!(inputs[0].r#type(), TransactionType::Deposit));
+    }
+
+    #[test]
+    fn transactions_from_cursor() {
+        let data = b"type, client, tx, amount
+deposit, 1, 1, 1.0
+dispute, x, 1,
+withdrawal, 1, 2, 0.5
+"
+        .to_vec();
+
+        let rows: Vec<_> = transactions(std::io::Cursor::new(data)).collect();
+
+        assert_eq!(3, rows.len());
+        assert_eq!(1, rows[0].as_ref().unwrap().tx());
+        assert!(rows[1].is_err());
+        assert_eq!(2, rows[2].as_ref().unwrap().tx());
+    }
+
+    #[test]
+    /// a client or tx too large for its type should be reported as out of range
+    fn out_of_range_ids_are_labeled() {
+        let data: &[u8] = b"type, client, tx, amount
+deposit, 70000, 1, 1.0
+deposit, 1, 4294967296, 1.0
+deposit, 1, 2, 1.0
+";
+        let mut rdr = create_input_deserializer_from_reader(data);
+        let mut errors = Vec::new();
+
+        let inputs: Vec<Input> = deserialize_with_errors(&mut rdr, &mut errors).collect();
+
+        assert_eq!(1, inputs.len());
+        let kinds: Vec<_> = errors
+            .iter()
+            .map(|error| (error.line, error.kind))
+            .collect();
+        assert_eq!(
+            vec![
+                (Some(2), ParseErrorKind::OutOfRange { field: Some(1) }),
+                (Some(3), ParseErrorKind::OutOfRange { field: Some(2) }),
+            ],
+            kinds
+        );
+    }
+
+    #[test]
+    fn comment_lines_are_skipped() {
+        let data: &[u8] = b"# batch 2024-01-01
+type, client, tx, amount
+deposit, 1, 1, 1.0
+# the following are from the second system
+deposit, 1, 2, 2.0
+#withdrawal, 1, 3, 0.5
+withdrawal, 1, 4, 0.5
+";
+        let mut rdr = create_input_deserializer_from_reader(data);
+        let mut errors = Vec::new();
+
+        let inputs: Vec<Input> = deserialize_with_errors(&mut rdr, &mut errors).collect();
+
+        assert!(errors.is_empty(), "{:?}", errors);
+        let txs: Vec<_> = inputs.iter().map(Input::tx).collect();
+        assert_eq!(vec![1, 2, 4], txs);
+    }
+
+    #[test]
+    fn builder_checks_validity() {
+        let deposit = Input::builder()
+            .deposit(1, 1, FixedPoint::from_f64(2.5))
+            .idempotency_key("a")
+            .build()
+            .unwrap();
+        assert!(matches!(deposit.r#type(), TransactionType::Deposit));
+        assert_eq!(Some(FixedPoint::from_f64(2.5)), deposit.amount());
+        assert_eq!(Some("a"), deposit.idempotency_key());
+
+        let dispute = Input::builder().dispute(1, 1).build().unwrap();
+        assert_eq!(None, dispute.amount());
+
+        let zero = Input::builder().withdrawal(1, 2, FixedPoint::ZERO).build();
+        assert_eq!(Err(InvalidInput), zero.map(|_| ()));
+        assert!(Input::builder().build().is_err());
+    }
+
+    #[test]
+    fn delimiter_is_detected() {
+        assert_eq!(b';', sniff_delimiter(b"type;client;tx;amount\n"));
+        assert_eq!(
+            b'\t',
+            sniff_delimiter(b"# a, comment\ntype\tclient\ttx\tamount")
+        );
+        assert_eq!(b',', sniff_delimiter(b"type, client, tx, amount"));
+        // ambiguous or nothing to go on
+        assert_eq!(b',', sniff_delimiter(b"type;client,tx"));
+        assert_eq!(b',', sniff_delimiter(b""));
+
+        let data: &[u8] = b"type;client;tx;amount
+deposit;1;1;1.5
+withdrawal;1;2;0.5
+";
+        let mut rdr = create_input_deserializer_autodetect(data).unwrap();
+        let mut errors = Vec::new();
+
+        let inputs: Vec<Input> = deserialize_with_errors(&mut rdr, &mut errors).collect();
+
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(2, inputs.len());
+        assert_eq!(Some(FixedPoint::from_f64(1.5)), inputs[0].amount());
+    }
+
+    #[test]
+    /// transfers name the receiving client in the `to` column, which other rows leave out
+    fn parsing_transfers_works() {
+        let data: &[u8] = b"type, client, tx, amount, to
+deposit, 1, 1, 5.0
+transfer, 1, 2, 2.5, 2
+transfer, 1, 3, 2.5
+transfer, 1, 4, 2.5, 1
+";
+        let mut rdr = create_input_deserializer_from_reader(data);
+        let mut errors = Vec::new();
+
+        let inputs: Vec<Input> = deserialize_with_errors(&mut rdr, &mut errors).collect();
+
+        assert!(errors.is_empty(), "{:?}", errors);
+        let valid: Vec<_> = inputs
+            .iter()
+            .map(|input| (input.to(), input.valid()))
+            .collect();
+        // a transfer needs someone else to go to
+        assert_eq!(
+            vec![
+                (None, true),
+                (Some(2), true),
+                (None, false),
+                (Some(1), false)
+            ],
+            valid
+        );
+    }
 }
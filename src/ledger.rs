@@ -0,0 +1,228 @@
+//! Persisting the balances as they change, see `AccountStorage::with_ledger`
+
+use alloc::collections::BTreeMap;
+#[cfg(feature = "sqlite")]
+use std::io::{self, Write};
+
+use crate::{FixedPoint, accounts::BalanceDelta, amount::AmountOps};
+
+/// Where the balances of the accounts are kept outside of the process. Every applied
+/// transaction that changes an account is recorded as it happens, and an account that is not
+/// in memory yet is loaded from the ledger before a transaction is applied to it
+pub trait Ledger<A: AmountOps = FixedPoint> {
+    /// Records how an applied transaction changed the account of the client, a transfer is
+    /// recorded once for each of its accounts
+    fn record(&mut self, client: u16, delta: BalanceDelta<A>);
+
+    /// The persisted balances of the client, None if it has none
+    fn load(&self, client: u16) -> Option<AccountState<A>>;
+}
+
+/// The balances of an account as persisted by a `Ledger`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AccountState<A: AmountOps = FixedPoint> {
+    pub available: A,
+    pub held: A,
+    /// a frozen account is loaded back as locked
+    pub locked: bool,
+}
+
+impl<A: AmountOps> AccountState<A> {
+    fn apply(&mut self, delta: BalanceDelta<A>) {
+        self.available += delta.available;
+        self.held += delta.held;
+        self.locked = delta.locked.unwrap_or(self.locked);
+    }
+}
+
+/// Keeps the recorded balances in memory, for tests and for sharing the balances with
+/// something that reads them back later
+#[derive(Debug, Default, Clone)]
+pub struct MemoryLedger<A: AmountOps = FixedPoint> {
+    states: BTreeMap<u16, AccountState<A>>,
+}
+
+impl<A: AmountOps> MemoryLedger<A> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every recorded account, by client
+    pub fn states(&self) -> &BTreeMap<u16, AccountState<A>> {
+        &self.states
+    }
+}
+
+impl<A: AmountOps> Ledger<A> for MemoryLedger<A> {
+    fn record(&mut self, client: u16, delta: BalanceDelta<A>) {
+        self.states.entry(client).or_default().apply(delta);
+    }
+
+    fn load(&self, client: u16) -> Option<AccountState<A>> {
+        self.states.get(&client).copied()
+    }
+}
+
+/// A stand in for a sqlite backed ledger, until the crate links against sqlite. It writes
+/// the statements that keep a `balances` table up to date, with the amounts in decimillis,
+/// for piping into `sqlite3`. Nothing can be read back, so `load` never finds an account
+#[cfg(feature = "sqlite")]
+pub struct SqliteLedger<W: Write> {
+    script: W,
+    /// the first error writing the script, every later record is skipped
+    error: Option<io::Error>,
+}
+
+#[cfg(feature = "sqlite")]
+impl<W: Write> SqliteLedger<W> {
+    /// Starts the script with the creation of the table, if it does not exist yet
+    pub fn new(mut script: W) -> io::Result<Self> {
+        writeln!(
+            script,
+            "CREATE TABLE IF NOT EXISTS balances (client INTEGER PRIMARY KEY, \
+             available INTEGER NOT NULL, held INTEGER NOT NULL, locked INTEGER NOT NULL);"
+        )?;
+        Ok(Self {
+            script,
+            error: None,
+        })
+    }
+
+    /// The script, or the first error writing it
+    pub fn finish(mut self) -> io::Result<W> {
+        match self.error {
+            Some(e) => Err(e),
+            None => self.script.flush().map(|()| self.script),
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl<W: Write> Ledger for SqliteLedger<W> {
+    fn record(&mut self, client: u16, delta: BalanceDelta) {
+        if self.error.is_some() {
+            return;
+        }
+        let locked = match delta.locked {
+            Some(locked) => format!(", locked = {}", u8::from(locked)),
+            None => String::new(),
+        };
+        let written = writeln!(
+            self.script,
+            "INSERT OR IGNORE INTO balances VALUES ({client}, 0, 0, 0);\n\
+             UPDATE balances SET available = available + {}, held = held + {}{locked} \
+             WHERE client = {client};",
+            delta.available.as_decimillis(),
+            delta.held.as_decimillis(),
+        );
+        self.error = written.err();
+    }
+
+    fn load(&self, _client: u16) -> Option<AccountState> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::{AccountStorage, Input, TransactionType};
+
+    /// Remembers every record, and loads the balances it was given up front
+    #[derive(Default)]
+    struct Mock {
+        recorded: Arc<Mutex<Vec<(u16, BalanceDelta)>>>,
+        persisted: MemoryLedger,
+    }
+
+    impl Ledger for Mock {
+        fn record(&mut self, client: u16, delta: BalanceDelta) {
+            self.recorded.lock().unwrap().push((client, delta));
+        }
+
+        fn load(&self, client: u16) -> Option<AccountState> {
+            self.persisted.load(client)
+        }
+    }
+
+    #[test]
+    /// Every applied transaction should be recorded with how it changed the balances, the
+    /// rejected ones not at all
+    fn records_applied_transactions() {
+        let f = FixedPoint::from_f64;
+        let mut persisted = MemoryLedger::new();
+        let restored = BalanceDelta {
+            available: f(4.0),
+            ..Default::default()
+        };
+        persisted.record(3, restored);
+        let mock = Mock {
+            persisted,
+            ..Default::default()
+        };
+        let recorded = mock.recorded.clone();
+        let mut accounts = AccountStorage::new().with_ledger(mock);
+
+        let inputs = [
+            Input::new(TransactionType::Deposit, 1, 1, Some(f(10.0))),
+            Input::new(TransactionType::Withdrawal, 1, 2, Some(f(2.5))),
+            // not enough funds
+            Input::new(TransactionType::Withdrawal, 1, 3, Some(f(100.0))),
+            Input::new(TransactionType::Dispute, 1, 1, None),
+            // the account of client 3 is only in the ledger
+            Input::builder().transfer(3, 1, 4, f(1.5)).build().unwrap(),
+            Input::new(TransactionType::Inquiry, 1, 0, None),
+            Input::new(TransactionType::Chargeback, 1, 1, None),
+        ];
+        for input in inputs {
+            let _ = accounts.handle_transaction(input);
+        }
+
+        let delta = |available: f64, held: f64, locked: Option<bool>| BalanceDelta {
+            available: f(available),
+            held: f(held),
+            locked,
+        };
+        assert_eq!(
+            vec![
+                (1, delta(10.0, 0.0, None)),
+                (1, delta(-2.5, 0.0, None)),
+                (1, delta(-10.0, 10.0, None)),
+                (3, delta(-1.5, 0.0, None)),
+                (1, delta(1.5, 0.0, None)),
+                (1, delta(0.0, -10.0, Some(true))),
+            ],
+            *recorded.lock().unwrap()
+        );
+        assert_eq!(f(2.5), accounts.accounts()[&3].available());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn sqlite_script() {
+        let mut ledger = SqliteLedger::new(Vec::new()).unwrap();
+        ledger.record(
+            7,
+            BalanceDelta {
+                available: FixedPoint::from_f64(-1.5),
+                held: FixedPoint::from_f64(1.5),
+                locked: Some(true),
+            },
+        );
+        let script = String::from_utf8(ledger.finish().unwrap()).unwrap();
+        let lines: Vec<_> = script.lines().collect();
+        assert_eq!(3, lines.len());
+        assert!(lines[0].starts_with("CREATE TABLE IF NOT EXISTS balances"));
+        assert_eq!(
+            "INSERT OR IGNORE INTO balances VALUES (7, 0, 0, 0);",
+            lines[1]
+        );
+        assert_eq!(
+            "UPDATE balances SET available = available + -15000, held = held + 15000, \
+             locked = 1 WHERE client = 7;",
+            lines[2]
+        );
+    }
+}
@@ -0,0 +1,62 @@
+//! A payments engine that applies deposits, withdrawals and disputes to client accounts
+//!
+//! Transactions are fed one at a time to an [`AccountStorage`], which creates the accounts
+//! as clients show up and keeps the history needed to dispute earlier transactions.
+//!
+//! ```
+//! use payeng::{AccountStorage, FixedPoint, Input};
+//!
+//! let mut accounts = AccountStorage::new();
+//! let deposit = Input::builder()
+//!     .deposit(1, 1, FixedPoint::from_f64(10.0))
+//!     .build()
+//!     .unwrap();
+//! let withdrawal = Input::builder()
+//!     .withdrawal(1, 2, FixedPoint::from_f64(2.5))
+//!     .build()
+//!     .unwrap();
+//! accounts.handle_transaction(deposit).unwrap();
+//! accounts.handle_transaction(withdrawal).unwrap();
+//!
+//! let account = &accounts.accounts()[&1];
+//! assert_eq!(FixedPoint::from_f64(7.5), account.available());
+//! assert_eq!(FixedPoint::from_f64(0.0), account.held());
+//! assert!(!account.locked());
+//! ```
+//!
+//! The default `std` feature adds the csv and file input, the output and the `Engine`
+//! running them. Without it the crate is `no_std`, needing only `alloc`, and keeps the
+//! accounts, their transactions and `FixedPoint`, for embedding the ledger where there is
+//! no operating system
+
+// the unit tests run on the test harness, which needs std anyway
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+pub mod accounts;
+pub mod amount;
+#[cfg(feature = "std")]
+pub mod engine;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod fx_hash;
+pub mod history;
+pub mod input;
+pub mod ledger;
+#[cfg(all(feature = "mmap", unix))]
+pub mod mmap;
+#[cfg(feature = "std")]
+pub mod output;
+pub mod simple_fp;
+#[cfg(feature = "tokio")]
+pub mod stream;
+#[cfg(feature = "trace")]
+pub mod trace;
+
+pub use accounts::{Account, AccountStorage, TransactionError};
+pub use amount::{AmountOps, OutputScalePolicy};
+#[cfg(feature = "std")]
+pub use engine::{Engine, Summary};
+pub use input::{Input, TransactionType};
+pub use simple_fp::FixedPoint;
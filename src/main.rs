@@ -1,29 +1,355 @@
-mod accounts;
-mod input;
-mod output;
-mod simple_fp;
+use std::{
+    error::Error,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
 
-use simple_fp::FixedPoint;
-// mod transaction;
+use payeng::{
+    Engine, Input, Summary, TransactionError,
+    accounts::{self, TransactionSink},
+    input, output,
+};
 
-fn main() {
-    let filename = std::env::args()
-        .into_iter()
-        .nth(1)
-        .expect("Expected file name as argument");
+/// The command line arguments,
+/// `payeng [--check] [--format csv|json] [--output <path>] [--withdrawable] [--counts] [--summary]
+/// [--allow-unlock] [--no-disputes] [--retention <n>] [files or directories...]`
+struct Args {
+    /// the csv files to read in order, stdin is read when none are given. The csv files
+    /// inside a directory are read in the order of their names
+    filenames: Vec<String>,
+    /// write the output to this file instead of stdout
+    output: Option<PathBuf>,
+    output_config: output::OutputConfig,
+    /// handle unlock transactions, for input from operations
+    allow_unlock: bool,
+    /// reject every dispute and keep no transaction history, for input that never has any
+    no_disputes: bool,
+    /// only keep this many of the latest transactions of each account, so that memory stays
+    /// bounded for huge inputs, see `AccountStorageBuilder::retention`
+    retention: Option<usize>,
+    /// only report the rows that would be rejected, without writing the accounts
+    check: bool,
+}
+
+/// An error that ends the program. `main` reports a returned error with its `Debug`
+/// output, so this shows only the message instead of the structure of the error
+struct Failure(String);
+
+impl fmt::Debug for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for Failure {}
+
+impl Args {
+    fn parse() -> Result<Self, Failure> {
+        let mut filenames = Vec::new();
+        let mut output = None;
+        let mut output_config = output::OutputConfig::default();
+        let mut allow_unlock = false;
+        let mut no_disputes = false;
+        let mut check = false;
+        let mut retention = None;
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--output" => {
+                    let path = args
+                        .next()
+                        .ok_or_else(|| Failure("Expected a path after --output".to_string()))?;
+                    output = Some(PathBuf::from(path))
+                }
+                "--format" => {
+                    output_config.format = match args.next().as_deref() {
+                        Some("csv") => output::OutputFormat::Csv,
+                        Some("json") => output::OutputFormat::Json,
+                        _ => {
+                            return Err(Failure("Expected csv or json after --format".to_string()));
+                        }
+                    }
+                }
+                "--withdrawable" => output_config.withdrawable = true,
+                "--counts" => output_config.counts = true,
+                "--summary" => output_config.summary = true,
+                "--allow-unlock" => allow_unlock = true,
+                "--no-disputes" => no_disputes = true,
+                "--check" => check = true,
+                "--retention" => {
+                    let n = args.next().and_then(|n| n.parse().ok()).ok_or_else(|| {
+                        Failure("Expected a number of transactions after --retention".to_string())
+                    })?;
+                    retention = Some(n);
+                }
+                _ => filenames.push(arg),
+            }
+        }
+
+        Ok(Self {
+            filenames,
+            output,
+            output_config,
+            allow_unlock,
+            no_disputes,
+            retention,
+            check,
+        })
+    }
+}
 
-    let mut csv_reader = input::create_input_deserializer(&filename);
+/// Replaces every directory among the paths with the `.csv` files directly inside it,
+/// sorted by name so that the files are always applied in the same order
+fn expand_directories(filenames: &[String]) -> io::Result<Vec<String>> {
+    let mut expanded = Vec::new();
+    for filename in filenames {
+        let path = Path::new(filename);
+        if !path.is_dir() {
+            expanded.push(filename.clone());
+            continue;
+        }
+
+        let mut files = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let path = entry?.path();
+            if path.is_file() && path.extension().is_some_and(|ext| ext == "csv") {
+                let name = path.into_os_string().into_string().map_err(|name| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{:?} is not a valid file name", name),
+                    )
+                })?;
+                files.push(name);
+            }
+        }
+        files.sort();
+        expanded.extend(files);
+    }
+    Ok(expanded)
+}
+
+/// Writes every rejected transaction to stderr when `report` is set, for `--check`
+struct Rejections {
+    report: bool,
+}
+
+impl TransactionSink for Rejections {
+    fn on_applied(&mut self, _input: &Input) {}
+
+    fn on_rejected(&mut self, input: &Input, err: &TransactionError) {
+        if self.report {
+            eprintln!(
+                "Rejected {} of client {} with tx {}: {}",
+                input.r#type().name(),
+                input.client(),
+                input.tx(),
+                err
+            );
+        }
+    }
+}
+
+/// Reads the file in the format given by its extension and applies it to the accounts
+fn process_file(
+    filename: &str,
+    engine: &mut Engine,
+    rejections: &mut Rejections,
+) -> io::Result<Summary> {
+    match input::InputFormat::from_path(filename) {
+        // a mapped file can be parsed in place, a compressed one still has to be read
+        #[cfg(all(feature = "mmap", unix))]
+        input::InputFormat::Csv if !filename.ends_with(".gz") => {
+            let map = payeng::mmap::Mmap::open(filename)?;
+            engine.process_with_sink(io::Cursor::new(&map[..]), rejections)
+        }
+        input::InputFormat::Csv => {
+            engine.process_with_sink(input::open_input(filename)?, rejections)
+        }
+        input::InputFormat::JsonLines => {
+            let mut parse_errors = Vec::new();
+            let transactions = input::deserialize_jsonl(input::open_input(filename)?)
+                .filter_map(|row| row.map_err(|e| parse_errors.push(e)).ok());
+            let mut summary = engine.apply_with_sink(transactions, rejections);
+            summary.parse_errors.extend(parse_errors);
+            Ok(summary)
+        }
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse()?;
 
     // initialize a new account database
-    let mut accounts = accounts::AccountStorage::new();
+    let mut builder = accounts::AccountStorage::builder()
+        .allow_unlock(args.allow_unlock)
+        .disable_disputes(args.no_disputes);
+    if let Some(retention) = args.retention {
+        builder = builder.retention(retention);
+    }
+    let accounts = builder.build();
+    let mut engine = Engine::with_accounts(accounts);
+    // a check goes through the same accounts as a real run, so that both reject the same
+    // rows, the accounts just never leave this process
+    let mut rejections = Rejections { report: args.check };
+
+    // every entry is a transaction, rows that fail to parse are collected and reported at the end
+    let mut summary = Summary::default();
+    if args.filenames.is_empty() {
+        let read = engine
+            .process_with_sink(io::stdin(), &mut rejections)
+            .map_err(|e| Failure(format!("Failed to read input: {}", e)))?;
+        summary.extend(read);
+    }
+    let filenames = expand_directories(&args.filenames)
+        .map_err(|e| Failure(format!("Failed to list the input files: {}", e)))?;
+    // the files share the same accounts, so a later file can dispute a deposit from an earlier one
+    for filename in &filenames {
+        let read = process_file(filename, &mut engine, &mut rejections)
+            .map_err(|e| Failure(format!("Failed to read {}: {}", filename, e)))?;
+        summary.extend(read);
+    }
 
-    let csv_iter = csv_reader.deserialize::<input::Input>();
-    // every entry is a transaction and we just ignore any faulty parsed inputs
-    for transaction in csv_iter.filter_map(|row| row.ok()) {
-        if let Err(_e) = accounts.handle_transaction(transaction) {
-            // here one would normally log any error to transactions
+    for error in &summary.parse_errors {
+        match error.line {
+            Some(line) => eprintln!(
+                "Failed to parse line {} ({}): {}",
+                line, error.kind, error.error
+            ),
+            None => eprintln!("Failed to parse row ({}): {}", error.kind, error.error),
         }
     }
+    // kept off stdout, which only has the accounts
+    eprintln!("{}", summary);
+
+    if args.check {
+        let failed = summary.rejected + summary.parse_errors.len();
+        if failed > 0 {
+            return Err(
+                Failure(format!("{} rows were rejected or failed to parse", failed)).into(),
+            );
+        }
+        return Ok(());
+    }
+
+    let accounts = engine.into_accounts();
+    match args.output {
+        Some(path) => {
+            output::write_to_file(&accounts, &path, &args.output_config).map_err(|e| {
+                Failure(format!(
+                    "Failed to write output to {}: {}",
+                    path.display(),
+                    e
+                ))
+            })?
+        }
+        None => output::print_from_accounts_with(accounts, &args.output_config)
+            .map_err(|e| Failure(format!("Failed to write output: {}", e)))?,
+    }
+    Ok(())
+}
 
-    output::print_from_accounts(accounts);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use payeng::FixedPoint;
+
+    fn balances(accounts: &accounts::AccountStorage) -> Vec<(u16, FixedPoint, FixedPoint)> {
+        accounts
+            .accounts()
+            .iter()
+            .map(|(client, account)| (*client, account.available(), account.held()))
+            .collect()
+    }
+
+    #[test]
+    /// splitting the input over several files should give the same result as a single file
+    fn multiple_files_match_a_single_file() {
+        let first: &[u8] = b"type, client, tx, amount\n\
+            deposit, 1, 1, 5.0\n\
+            deposit, 2, 2, 3.0\n";
+        let second: &[u8] = b"type, client, tx, amount\n\
+            dispute, 1, 1,\n\
+            deposit, 2, 2, 3.0\n\
+            withdrawal, 2, 3, 1.0\n";
+        let single: &[u8] = b"type, client, tx, amount\n\
+            deposit, 1, 1, 5.0\n\
+            deposit, 2, 2, 3.0\n\
+            dispute, 1, 1,\n\
+            deposit, 2, 2, 3.0\n\
+            withdrawal, 2, 3, 1.0\n";
+
+        let mut split = Engine::new();
+        let mut summary = Summary::default();
+        for data in [first, second] {
+            summary.extend(split.process(data).unwrap());
+        }
+
+        let mut whole = Engine::new();
+        summary.extend(whole.process(single).unwrap());
+
+        assert!(
+            summary.parse_errors.is_empty(),
+            "{:?}",
+            summary.parse_errors
+        );
+        let (whole, split) = (whole.accounts(), split.accounts());
+        assert_eq!(balances(whole), balances(split));
+        // the dispute in the second file found the deposit from the first
+        assert_eq!(FixedPoint::from_f64(5.0), split.accounts()[&1].held());
+        // and the repeated deposit id was rejected across files
+        assert_eq!(FixedPoint::from_f64(2.0), split.accounts()[&2].available());
+    }
+
+    #[test]
+    /// the csv files of a directory are applied in the order of their names, into the
+    /// same accounts
+    fn directory_of_files() {
+        let dir = std::env::temp_dir().join(format!("payeng-directory-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        // the dispute in the second file needs the deposit of the first one
+        fs::write(
+            dir.join("txns-1.csv"),
+            "type, client, tx, amount\ndeposit, 1, 1, 5.0\ndeposit, 2, 2, 3.0\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("txns-2.csv"),
+            "type, client, tx, amount\ndispute, 1, 1,\ndeposit, 2, 2, 3.0\n",
+        )
+        .unwrap();
+        fs::write(dir.join("notes.txt"), "not a transaction").unwrap();
+
+        let filenames = expand_directories(&[dir.to_str().unwrap().to_string()]).unwrap();
+        let expected: Vec<_> = ["txns-1.csv", "txns-2.csv"]
+            .iter()
+            .map(|name| dir.join(name).to_str().unwrap().to_string())
+            .collect();
+        assert_eq!(expected, filenames);
+
+        let mut engine = Engine::new();
+        let mut summary = Summary::default();
+        for filename in &filenames {
+            let mut rejections = Rejections { report: false };
+            summary.extend(process_file(filename, &mut engine, &mut rejections).unwrap());
+        }
+        let _ = fs::remove_dir_all(&dir);
+
+        assert!(
+            summary.parse_errors.is_empty(),
+            "{:?}",
+            summary.parse_errors
+        );
+        let f = FixedPoint::from_f64;
+        assert_eq!(
+            vec![(1, f(0.0), f(5.0)), (2, f(3.0), f(0.0))],
+            balances(engine.accounts())
+        );
+    }
 }
@@ -0,0 +1,116 @@
+//! Reads input files through a read only memory map, so that the csv reader parses straight
+//! from the page cache instead of copying every block of the file into its own buffer first.
+//! Only a few calls of the C library are needed, which std already links, so they are
+//! declared here instead of pulling in a crate for them
+
+use std::{fs::File, io, ops::Deref, os::fd::AsRawFd, path::Path, ptr, slice};
+
+unsafe extern "C" {
+    fn mmap(addr: *mut u8, len: usize, prot: i32, flags: i32, fd: i32, offset: isize) -> *mut u8;
+    fn munmap(addr: *mut u8, len: usize) -> i32;
+}
+
+/// The same on Linux and the BSDs, including macOS
+const PROT_READ: i32 = 1;
+const MAP_PRIVATE: i32 = 2;
+
+/// A file mapped into memory, read as a byte slice.
+///
+/// The file must not be truncated while it is mapped, reading the pages past its new end
+/// kills the process, and changes to it by other processes may show up in the slice
+pub struct Mmap {
+    ptr: *mut u8,
+    len: usize,
+}
+
+// the mapping is read only, so it is as safe to share as a `&[u8]`
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "file too large to map"))?;
+        // an empty mapping is an error, but an empty file is just empty input
+        if len == 0 {
+            return Ok(Self {
+                ptr: ptr::null_mut(),
+                len,
+            });
+        }
+
+        // SAFETY: a new private read only mapping of the open file, which stays valid after
+        // the file is closed
+        let ptr = unsafe {
+            mmap(
+                ptr::null_mut(),
+                len,
+                PROT_READ,
+                MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        // MAP_FAILED
+        if ptr as isize == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { ptr, len })
+    }
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        // SAFETY: the mapping covers `len` readable bytes until it is dropped
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            // SAFETY: unmaps exactly the mapping created in `open`, nothing borrows it anymore
+            unsafe { munmap(self.ptr, self.len) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::input::{open_input, transactions};
+
+    #[test]
+    /// Parsing the mapped file should give the same rows, and the same errors, as reading it
+    fn mapped_matches_buffered() {
+        let rows = |rows: Vec<Result<_, csv::Error>>| {
+            rows.into_iter()
+                .map(|row| format!("{:?}", row.map_err(|e| e.to_string())))
+                .collect::<Vec<_>>()
+        };
+
+        let map = Mmap::open("testdata/input.csv").unwrap();
+        let mapped = rows(transactions(Cursor::new(&map[..])).collect());
+        let buffered = rows(transactions(open_input("testdata/input.csv").unwrap()).collect());
+
+        assert!(!mapped.is_empty());
+        assert_eq!(buffered, mapped);
+    }
+
+    #[test]
+    fn empty_file() {
+        let path = std::env::temp_dir().join(format!("payeng-empty-{}.csv", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        let map = Mmap::open(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert!(map.is_empty());
+    }
+}
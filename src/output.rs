@@ -1,42 +1,854 @@
-use crate::accounts;
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::{AmountOps, FixedPoint, OutputScalePolicy, accounts};
+
+/// The header of the output with the default `OutputConfig`
+pub const HEADER: &str = "client, available, held, total, locked";
+
+/// A column of the CSV output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    Client,
+    Available,
+    Held,
+    Total,
+    Locked,
+    /// the amount that can be withdrawn right now
+    Withdrawable,
+    /// the number of disputes that are not yet settled
+    DisputesOpen,
+    /// the number of transactions applied
+    TxCount,
+}
+
+impl Column {
+    /// The name of the column in the default header
+    pub fn name(self) -> &'static str {
+        match self {
+            Column::Client => "client",
+            Column::Available => "available",
+            Column::Held => "held",
+            Column::Total => "total",
+            Column::Locked => "locked",
+            Column::Withdrawable => "withdrawable",
+            Column::DisputesOpen => "disputes_open",
+            Column::TxCount => "tx_count",
+        }
+    }
+}
+
+/// The encoding of the output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    /// a JSON array of the accounts, see `write_json`
+    Json,
+}
+
+/// Which optional columns to include in the output, by default none are, how many decimals
+/// the amounts are written with, by default four, and optionally a layout of its own
+#[derive(Debug, Clone)]
+pub struct OutputConfig {
+    /// csv by default, the other options only apply to the csv output
+    pub format: OutputFormat,
+    /// add a `withdrawable` column, the amount that can be withdrawn right now
+    pub withdrawable: bool,
+    /// add `disputes_open` and `tx_count` columns, the number of disputes that are not yet
+    /// settled and of transactions applied
+    pub counts: bool,
+    /// append a summary row, with the amounts of all accounts together
+    pub summary: bool,
+    /// the amounts are rounded to this many decimals, and padded with zeroes to it
+    pub precision: u32,
+    /// how amounts with more than four decimals are brought down to four before that, for
+    /// amount types that keep more of them than `FixedPoint`
+    pub scale: OutputScalePolicy,
+    /// the columns in order with their names in the header, for consumers that expect
+    /// another layout. Replaces the columns selected by `withdrawable` and `counts`
+    pub layout: Option<Vec<(Column, String)>>,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            format: OutputFormat::Csv,
+            withdrawable: false,
+            counts: false,
+            summary: false,
+            precision: 4,
+            scale: OutputScalePolicy::default(),
+            layout: None,
+        }
+    }
+}
+
+impl OutputConfig {
+    /// The columns to write, in order, with their header names
+    fn columns(&self) -> Vec<(Column, String)> {
+        if let Some(layout) = &self.layout {
+            return layout.clone();
+        }
+        let mut columns = vec![
+            Column::Client,
+            Column::Available,
+            Column::Held,
+            Column::Total,
+            Column::Locked,
+        ];
+        if self.withdrawable {
+            columns.push(Column::Withdrawable);
+        }
+        if self.counts {
+            columns.extend([Column::DisputesOpen, Column::TxCount]);
+        }
+        columns
+            .into_iter()
+            .map(|column| (column, column.name().to_owned()))
+            .collect()
+    }
+
+    fn header(&self) -> String {
+        let names: Vec<String> = self.columns().into_iter().map(|(_, name)| name).collect();
+        names.join(", ")
+    }
+
+    /// The row after all accounts with the totals of their amounts, where the client is
+    /// `total` and any column that is not summed is left blank
+    fn summary_line<A: AmountOps>(
+        &self,
+        accountstore: &accounts::AccountStorage<A>,
+    ) -> io::Result<String> {
+        let precision = self.precision as usize;
+        let available = scaled(accountstore.total_available(), self)?;
+        let held = scaled(accountstore.total_held(), self)?;
+        let fields: Vec<String> = self
+            .columns()
+            .into_iter()
+            .map(|(column, _)| match column {
+                Column::Client => "total".to_owned(),
+                Column::Available => format!("{:.*}", precision, available),
+                Column::Held => format!("{:.*}", precision, held),
+                Column::Total => format!("{:.*}", precision, available + held),
+                _ => String::new(),
+            })
+            .collect();
+        Ok(fields.join(", "))
+    }
+}
+
+/// The amount with the four decimals of the output, as the scale of the config says, or an
+/// `InvalidData` error if it cannot be written with them
+fn scaled<A: AmountOps>(amount: A, config: &OutputConfig) -> io::Result<FixedPoint> {
+    amount.to_fixed_point(config.scale).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "the amount {:?} cannot be written with four decimals",
+                amount
+            ),
+        )
+    })
+}
 
 pub struct Output {
     /// The client the text is regarding
     client: u16,
     /// the amount available for usage, should equal total - held
-    available: f64,
+    available: FixedPoint,
     /// the amount locked during a dispute, should be the total - available
-    held: f64,
+    held: FixedPoint,
     /// the total amount of funds
-    total: f64,
+    total: FixedPoint,
     /// if the account is currently locked due to an ongoing chargeback
     locked: bool,
+    /// the amount that can currently be withdrawn, if that column is included
+    withdrawable: Option<FixedPoint>,
+    /// the number of open disputes and of applied transactions, if those columns are included
+    counts: Option<(usize, u64)>,
+    /// the number of decimals the amounts are rounded to
+    precision: usize,
 }
 
 impl Output {
-    pub fn csv_line(&self) -> String {
-        format!(
-            "{}, {:0.4}, {:0.4}, {:0.4}, {}",
-            self.client, self.available, self.held, self.total, self.locked
-        )
+    /// The row with the given columns, a column that was not included when creating the
+    /// output is left blank
+    pub fn csv_line(&self, columns: &[Column]) -> String {
+        let precision = self.precision;
+        let fields: Vec<String> = columns
+            .iter()
+            .map(|column| match column {
+                Column::Client => self.client.to_string(),
+                Column::Available => format!("{:.*}", precision, self.available),
+                Column::Held => format!("{:.*}", precision, self.held),
+                Column::Total => format!("{:.*}", precision, self.total),
+                Column::Locked => self.locked.to_string(),
+                Column::Withdrawable => self
+                    .withdrawable
+                    .map(|withdrawable| format!("{:.*}", precision, withdrawable))
+                    .unwrap_or_default(),
+                Column::DisputesOpen => self
+                    .counts
+                    .map(|(disputes_open, _)| disputes_open.to_string())
+                    .unwrap_or_default(),
+                Column::TxCount => self
+                    .counts
+                    .map(|(_, tx_count)| tx_count.to_string())
+                    .unwrap_or_default(),
+            })
+            .collect();
+        fields.join(", ")
     }
 }
 
-pub fn print_from_accounts(accountstore: accounts::AccountStorage) -> () {
+/// One output row per account, ordered by client, an error for an account with an amount
+/// that cannot be written with four decimals
+fn rows<'a, A: AmountOps>(
+    accountstore: &'a accounts::AccountStorage<A>,
+    config: &'a OutputConfig,
+) -> impl Iterator<Item = io::Result<Output>> + 'a {
+    let columns: Vec<Column> = config.columns().into_iter().map(|(c, _)| c).collect();
+    let withdrawable = columns.contains(&Column::Withdrawable);
+    let counts = columns.contains(&Column::DisputesOpen) || columns.contains(&Column::TxCount);
+    accountstore
+        .accounts()
+        .iter()
+        .map(move |(client, account)| {
+            Ok(Output {
+                client: *client,
+                available: scaled(account.available(), config)?,
+                held: scaled(account.held(), config)?,
+                total: scaled(account.total(), config)?,
+                locked: account.locked(),
+                withdrawable: withdrawable
+                    .then(|| scaled(account.withdrawable(), config))
+                    .transpose()?,
+                counts: counts.then(|| {
+                    let open = account.disputes().values().filter(|d| d.is_active());
+                    (open.count(), account.transaction_count())
+                }),
+                precision: config.precision as usize,
+            })
+        })
+}
+
+pub fn print_from_accounts(accountstore: accounts::AccountStorage) -> io::Result<()> {
+    print_from_accounts_with(accountstore, &OutputConfig::default())
+}
+
+pub fn print_from_accounts_with(
+    accountstore: accounts::AccountStorage,
+    config: &OutputConfig,
+) -> io::Result<()> {
+    write_buffered(&accountstore, io::stdout().lock(), config)
+}
+
+/// Writes the accounts through a buffer, which is flushed once at the end, since writing
+/// every row on its own is slow for large storages such as with stdout
+pub(crate) fn write_buffered<W: Write>(
+    accountstore: &accounts::AccountStorage,
+    w: W,
+    config: &OutputConfig,
+) -> io::Result<()> {
+    let mut w = BufWriter::new(w);
+    write_output(accountstore, &mut w, config)?;
+    w.flush()
+}
+
+/// Writes the accounts in the format selected by the config
+pub fn write_output<W: Write>(
+    accountstore: &accounts::AccountStorage,
+    w: &mut W,
+    config: &OutputConfig,
+) -> io::Result<()> {
+    match config.format {
+        OutputFormat::Csv => write_accounts_with(accountstore, w, config),
+        OutputFormat::Json => {
+            write_json(accountstore, &mut *w)?;
+            writeln!(w)
+        }
+    }
+}
+
+/// Writes the accounts as CSV, with a header row
+pub fn write_accounts<W: Write>(
+    accountstore: &accounts::AccountStorage,
+    w: &mut W,
+) -> io::Result<()> {
+    write_accounts_with(accountstore, w, &OutputConfig::default())
+}
+
+/// Writes the accounts as CSV as the config says, for any amount type. Fails with
+/// `InvalidData` if an amount cannot be written with four decimals, which only happens with
+/// `OutputScalePolicy::ErrorOnResidual`
+pub fn write_accounts_with<W: Write, A: AmountOps>(
+    accountstore: &accounts::AccountStorage<A>,
+    w: &mut W,
+    config: &OutputConfig,
+) -> io::Result<()> {
+    write_rows(w, config, rows(accountstore, config))?;
+    if config.summary {
+        writeln!(w, "{}", config.summary_line(accountstore)?)?;
+    }
+    Ok(())
+}
+
+/// Writes only the locked accounts as CSV, such as for reviewing chargebacks
+pub fn write_locked_accounts<W: Write>(
+    accountstore: &accounts::AccountStorage,
+    w: &mut W,
+) -> io::Result<()> {
+    let config = OutputConfig::default();
+    write_rows(
+        w,
+        &config,
+        rows(accountstore, &config).filter(|out| out.as_ref().map_or(true, |out| out.locked)),
+    )
+}
+
+fn write_rows<W: Write>(
+    w: &mut W,
+    config: &OutputConfig,
+    rows: impl Iterator<Item = io::Result<Output>>,
+) -> io::Result<()> {
     // using csv writer for this, just seems uneccesary...
     // especially since no formatting rules are really in effect
 
-    println!("client, available, held, total, locked");
+    let columns: Vec<Column> = config.columns().into_iter().map(|(c, _)| c).collect();
+    writeln!(w, "{}", config.header())?;
+    for out in rows {
+        writeln!(w, "{}", out?.csv_line(&columns))?;
+    }
+    Ok(())
+}
+
+/// An account as written by `write_json`, the amounts are serialized as exact strings
+#[derive(Serialize)]
+struct JsonRow {
+    client: u16,
+    available: FixedPoint,
+    held: FixedPoint,
+    total: FixedPoint,
+    locked: bool,
+}
+
+/// Writes the accounts as a JSON array of objects, ordered by client, with the amounts as
+/// strings with four decimals so that no precision is lost to floats
+pub fn write_json<W: Write>(accountstore: &accounts::AccountStorage, w: W) -> io::Result<()> {
+    let rows: Vec<JsonRow> = json_rows(accountstore).collect();
+    serde_json::to_writer(w, &rows)?;
+    Ok(())
+}
+
+/// Writes the accounts as newline delimited JSON, one object like those of `write_json` per
+/// line, ordered by client. Every line is written as soon as it is serialized, so nothing
+/// is collected in memory
+pub fn write_ndjson<W: Write>(accountstore: &accounts::AccountStorage, mut w: W) -> io::Result<()> {
+    for row in json_rows(accountstore) {
+        serde_json::to_writer(&mut w, &row)?;
+        w.write_all(b"\n")?;
+    }
+    Ok(())
+}
 
-    for (client, account) in accountstore.accounts() {
-        let out = Output {
+fn json_rows(accountstore: &accounts::AccountStorage) -> impl Iterator<Item = JsonRow> + '_ {
+    accountstore
+        .accounts()
+        .iter()
+        .map(|(client, account)| JsonRow {
             client: *client,
-            available: account.available().to_f64(),
-            held: account.held().to_f64(),
-            total: account.total().to_f64(),
+            available: account.available(),
+            held: account.held(),
+            total: account.total(),
             locked: account.locked(),
+        })
+}
+
+/// The CSV output as a string, for embedding it somewhere else than stdout or a file
+pub fn accounts_to_csv_string(accountstore: &accounts::AccountStorage) -> String {
+    let mut buffer = Vec::new();
+    write_accounts(accountstore, &mut buffer).expect("writing to a Vec cannot fail");
+    String::from_utf8(buffer).expect("the output is always UTF-8")
+}
+
+/// The JSON output as a string, see `write_json`
+pub fn accounts_to_json_string(accountstore: &accounts::AccountStorage) -> String {
+    let mut buffer = Vec::new();
+    write_json(accountstore, &mut buffer).expect("writing to a Vec cannot fail");
+    String::from_utf8(buffer).expect("the output is always UTF-8")
+}
+
+/// Writes the accounts to a file, the file is only replaced once everything has been written,
+/// so a failure midway leaves any previous file at that path untouched
+pub fn write_to_file(
+    accountstore: &accounts::AccountStorage,
+    path: &Path,
+    config: &OutputConfig,
+) -> io::Result<()> {
+    let mut file = AtomicFile::create(path)?;
+    write_output(accountstore, &mut file, config)?;
+    file.commit()
+}
+
+/// A file that is written at a temporary path next to the target, `file.csv.tmp` for
+/// `file.csv`, and only renamed to the target once `commit` is called. Dropping it without
+/// committing, for example when returning early on an error, removes the temporary file
+pub struct AtomicFile {
+    file: Option<BufWriter<File>>,
+    tmp: PathBuf,
+    target: PathBuf,
+}
+
+impl AtomicFile {
+    pub fn create(target: &Path) -> io::Result<Self> {
+        let mut tmp = target.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+
+        let file = File::create(&tmp)?;
+        Ok(Self {
+            file: Some(BufWriter::new(file)),
+            tmp,
+            target: target.to_owned(),
+        })
+    }
+
+    /// Makes sure everything is on disk and moves the file into place
+    pub fn commit(mut self) -> io::Result<()> {
+        if let Some(file) = self.file.take() {
+            let file = file.into_inner().map_err(|e| e.into_error())?;
+            file.sync_all()?;
+        }
+        fs::rename(&self.tmp, &self.target)
+    }
+
+    fn file(&mut self) -> &mut BufWriter<File> {
+        // only taken when committing, which consumes self
+        self.file.as_mut().expect("file is only taken on commit")
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file().flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        // close the file before removing it, in case it was never committed
+        drop(self.file.take());
+        if self.tmp.exists() {
+            // not committed, or the rename into place failed
+            let _ = fs::remove_file(&self.tmp);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::{Input, TransactionType};
+
+    fn columns(config: &OutputConfig) -> Vec<Column> {
+        config.columns().into_iter().map(|(c, _)| c).collect()
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("payeng-{}-{}.csv", name, std::process::id()))
+    }
+
+    #[test]
+    /// Writing the accounts to a file should give the same lines as printing them
+    fn write_accounts_to_file() {
+        let mut accounts = accounts::AccountStorage::new();
+        let transaction = Input::from_f64(TransactionType::Deposit, 1, 1, Some(1.5));
+        accounts.handle_transaction(transaction).unwrap();
+
+        let path = temp_path("write-accounts-to-file");
+        write_to_file(&accounts, &path, &OutputConfig::default()).unwrap();
+
+        let written = fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            "client, available, held, total, locked\n1, 1.5000, 0.0000, 1.5000, false\n",
+            written
+        );
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    /// The withdrawable column should only be there when asked for
+    fn withdrawable_column() {
+        let mut accounts = accounts::AccountStorage::new();
+        let transaction = Input::from_f64(TransactionType::Deposit, 1, 1, Some(1.5));
+        accounts.handle_transaction(transaction).unwrap();
+        let transaction = Input::from_f64(TransactionType::Deposit, 1, 2, Some(1.0));
+        accounts.handle_transaction(transaction).unwrap();
+        let transaction = Input::new(TransactionType::Dispute, 1, 2, None);
+        accounts.handle_transaction(transaction).unwrap();
+
+        let config = OutputConfig {
+            withdrawable: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            "client, available, held, total, locked, withdrawable",
+            config.header()
+        );
+        let lines: Vec<String> = rows(&accounts, &config)
+            .map(|o| o.unwrap().csv_line(&columns(&config)))
+            .collect();
+        assert_eq!(vec!["1, 1.5000, 1.0000, 2.5000, false, 1.5000"], lines);
+
+        let config = OutputConfig::default();
+        assert_eq!(HEADER, config.header());
+        let lines: Vec<String> = rows(&accounts, &config)
+            .map(|o| o.unwrap().csv_line(&columns(&config)))
+            .collect();
+        assert_eq!(vec!["1, 1.5000, 1.0000, 2.5000, false"], lines);
+    }
+
+    #[test]
+    /// The CSV should be written to any writer, header first
+    fn write_accounts_to_buffer() {
+        let mut accounts = accounts::AccountStorage::new();
+        let inputs = [
+            Input::builder().deposit(2, 1, FixedPoint::from_f64(1.5)),
+            Input::builder().deposit(1, 2, FixedPoint::from_f64(3.0)),
+            Input::builder().withdrawal(1, 3, FixedPoint::from_f64(0.25)),
+        ];
+        for input in inputs {
+            accounts.handle_transaction(input.build().unwrap()).unwrap();
+        }
+
+        let mut written: Vec<u8> = Vec::new();
+        write_accounts(&accounts, &mut written).unwrap();
+        assert_eq!(
+            "client, available, held, total, locked\n\
+             1, 2.7500, 0.0000, 2.7500, false\n\
+             2, 1.5000, 0.0000, 1.5000, false\n",
+            String::from_utf8(written).unwrap()
+        );
+    }
+
+    #[test]
+    /// Only the locked accounts should be written, still in order
+    fn write_only_locked_accounts() {
+        let mut accounts = accounts::AccountStorage::new();
+        for client in 1..=4 {
+            let tx = client as u32;
+            let deposit = Input::builder()
+                .deposit(client, tx, FixedPoint::from_f64(1.0))
+                .build()
+                .unwrap();
+            accounts.handle_transaction(deposit).unwrap();
+            if client % 2 == 0 {
+                let dispute = Input::builder().dispute(client, tx).build().unwrap();
+                accounts.handle_transaction(dispute).unwrap();
+                let chargeback = Input::builder().chargeback(client, tx).build().unwrap();
+                accounts.handle_transaction(chargeback).unwrap();
+            }
+        }
+
+        let mut written = Vec::new();
+        write_locked_accounts(&accounts, &mut written).unwrap();
+        assert_eq!(
+            "client, available, held, total, locked\n\
+             2, 0.0000, 0.0000, 0.0000, true\n\
+             4, 0.0000, 0.0000, 0.0000, true\n",
+            String::from_utf8(written).unwrap()
+        );
+    }
+
+    #[test]
+    /// The summary row should have the sums of the amounts of the rows above it
+    fn summary_row() {
+        let mut accounts = accounts::AccountStorage::new();
+        let inputs = [
+            Input::builder().deposit(1, 1, FixedPoint::from_f64(1.25)),
+            Input::builder().deposit(2, 2, FixedPoint::from_f64(2.5)),
+            Input::builder().deposit(3, 3, FixedPoint::from_f64(0.0001)),
+            Input::builder().dispute(2, 2),
+        ];
+        for input in inputs {
+            accounts.handle_transaction(input.build().unwrap()).unwrap();
+        }
+
+        let config = OutputConfig {
+            summary: true,
+            ..Default::default()
+        };
+        let mut written = Vec::new();
+        write_accounts_with(&accounts, &mut written, &config).unwrap();
+        let written = String::from_utf8(written).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(5, lines.len());
+        assert_eq!("total, 1.2501, 2.5000, 3.7501, ", lines[4]);
+
+        // every summed column should match the rows of the accounts
+        let column = |index: usize| -> FixedPoint {
+            lines[1..4]
+                .iter()
+                .map(|line| {
+                    line.split(", ")
+                        .nth(index)
+                        .unwrap()
+                        .parse::<FixedPoint>()
+                        .unwrap()
+                })
+                .sum()
+        };
+        for (index, summed) in lines[4].split(", ").enumerate().take(4).skip(1) {
+            assert_eq!(column(index), summed.parse::<FixedPoint>().unwrap());
+        }
+
+        let config = OutputConfig {
+            summary: true,
+            withdrawable: true,
+            counts: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            config.header().split(", ").count(),
+            config.summary_line(&accounts).unwrap().split(", ").count()
+        );
+    }
+
+    #[test]
+    /// The count columns should only be there when asked for, after the other columns
+    fn count_columns() {
+        let mut accounts = accounts::AccountStorage::new();
+        let inputs = [
+            Input::builder().deposit(1, 1, FixedPoint::from_f64(1.0)),
+            Input::builder().deposit(1, 2, FixedPoint::from_f64(2.0)),
+            Input::builder().deposit(1, 3, FixedPoint::from_f64(3.0)),
+            Input::builder().dispute(1, 1),
+            Input::builder().dispute(1, 2),
+            Input::builder().resolve(1, 2),
+        ];
+        for input in inputs {
+            accounts.handle_transaction(input.build().unwrap()).unwrap();
+        }
+
+        let config = OutputConfig {
+            withdrawable: true,
+            counts: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            "client, available, held, total, locked, withdrawable, disputes_open, tx_count",
+            config.header()
+        );
+        let lines: Vec<String> = rows(&accounts, &config)
+            .map(|o| o.unwrap().csv_line(&columns(&config)))
+            .collect();
+        assert_eq!(
+            vec!["1, 5.0000, 1.0000, 6.0000, false, 5.0000, 1, 6"],
+            lines
+        );
+        assert_eq!(HEADER, OutputConfig::default().header());
+    }
+
+    #[test]
+    /// A custom layout should reorder and rename the columns, in the header and the rows
+    fn custom_column_layout() {
+        let mut accounts = accounts::AccountStorage::new();
+        let deposit = Input::builder()
+            .deposit(7, 1, FixedPoint::from_f64(2.5))
+            .build()
+            .unwrap();
+        accounts.handle_transaction(deposit).unwrap();
+
+        let config = OutputConfig {
+            layout: Some(vec![
+                (Column::Total, "Total".to_owned()),
+                (Column::Client, "Client".to_owned()),
+                (Column::Locked, "Frozen".to_owned()),
+                (Column::TxCount, "Transactions".to_owned()),
+            ]),
+            ..Default::default()
+        };
+        let mut written = Vec::new();
+        write_accounts_with(&accounts, &mut written, &config).unwrap();
+        assert_eq!(
+            "Total, Client, Frozen, Transactions\n2.5000, 7, false, 1\n",
+            String::from_utf8(written).unwrap()
+        );
+    }
+
+    #[test]
+    /// The amounts should be rounded to the precision, and padded to it, exactly even for
+    /// amounts with more digits than a float keeps
+    fn output_precision() {
+        let mut accounts = accounts::AccountStorage::new();
+        let large = "123456789012345.6789".parse().unwrap();
+        let inputs = [
+            Input::builder().deposit(1, 1, FixedPoint::from_f64(1.2345)),
+            Input::builder().deposit(2, 2, FixedPoint::from_f64(2.5)),
+            Input::builder().deposit(3, 3, large),
+        ];
+        for input in inputs {
+            accounts.handle_transaction(input.build().unwrap()).unwrap();
+        }
+
+        let write = |precision| {
+            let config = OutputConfig {
+                precision,
+                ..Default::default()
+            };
+            let mut written = Vec::new();
+            write_accounts_with(&accounts, &mut written, &config).unwrap();
+            String::from_utf8(written).unwrap()
+        };
+        assert_eq!(
+            "client, available, held, total, locked\n\
+             1, 1.2345, 0.0000, 1.2345, false\n\
+             2, 2.5000, 0.0000, 2.5000, false\n\
+             3, 123456789012345.6789, 0.0000, 123456789012345.6789, false\n",
+            write(4)
+        );
+        assert_eq!(
+            "client, available, held, total, locked\n\
+             1, 1.23, 0.00, 1.23, false\n\
+             2, 2.50, 0.00, 2.50, false\n\
+             3, 123456789012345.68, 0.00, 123456789012345.68, false\n",
+            write(2)
+        );
+        let mut default = Vec::new();
+        write_accounts(&accounts, &mut default).unwrap();
+        assert_eq!(write(4), String::from_utf8(default).unwrap());
+    }
+
+    #[test]
+    /// The JSON should have every account in order, with exact amounts
+    fn write_accounts_as_json() {
+        let mut accounts = accounts::AccountStorage::new();
+        let inputs = [
+            Input::builder().deposit(2, 1, FixedPoint::from_f64(1.5)),
+            Input::builder().deposit(1, 2, FixedPoint::from_f64(0.0001)),
+            Input::builder().deposit(2, 3, FixedPoint::from_f64(2.0)),
+            Input::builder().dispute(2, 3),
+        ];
+        for input in inputs {
+            accounts.handle_transaction(input.build().unwrap()).unwrap();
+        }
+
+        let mut written = Vec::new();
+        write_json(&accounts, &mut written).unwrap();
+        assert_eq!(
+            concat!(
+                r#"[{"client":1,"available":"0.0001","held":"0.0000","total":"0.0001","locked":false},"#,
+                r#"{"client":2,"available":"1.5000","held":"2.0000","total":"3.5000","locked":false}]"#,
+            ),
+            String::from_utf8(written).unwrap()
+        );
+    }
+
+    #[test]
+    /// Every line should be a JSON object of one account, in order
+    fn write_accounts_as_ndjson() {
+        let mut accounts = accounts::AccountStorage::new();
+        for client in [3, 1, 2] {
+            let deposit = Input::builder()
+                .deposit(client, client as u32, FixedPoint::from_f64(client as f64))
+                .build()
+                .unwrap();
+            accounts.handle_transaction(deposit).unwrap();
+        }
+
+        let mut written = Vec::new();
+        write_ndjson(&accounts, &mut written).unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert!(written.ends_with('\n'));
+        let clients: Vec<u64> = written
+            .lines()
+            .map(|line| {
+                let value: serde_json::Value = serde_json::from_str(line).unwrap();
+                assert!(value["available"].is_string());
+                value["client"].as_u64().unwrap()
+            })
+            .collect();
+        assert_eq!(vec![1, 2, 3], clients);
+        assert_eq!(
+            r#"{"client":1,"available":"1.0000","held":"0.0000","total":"1.0000","locked":false}"#,
+            written.lines().next().unwrap()
+        );
+    }
+
+    #[test]
+    /// The strings should be the same as what the writers write
+    fn accounts_to_strings() {
+        let mut accounts = accounts::AccountStorage::new();
+        let deposit = Input::builder()
+            .deposit(1, 1, FixedPoint::from_f64(1.5))
+            .build()
+            .unwrap();
+        accounts.handle_transaction(deposit).unwrap();
+
+        assert_eq!(
+            "client, available, held, total, locked\n1, 1.5000, 0.0000, 1.5000, false\n",
+            accounts_to_csv_string(&accounts)
+        );
+        assert_eq!(
+            r#"[{"client":1,"available":"1.5000","held":"0.0000","total":"1.5000","locked":false}]"#,
+            accounts_to_json_string(&accounts)
+        );
+    }
+
+    #[test]
+    /// Everything should have been written once the buffered write returns, even for
+    /// many accounts
+    fn buffered_write_of_many_accounts() {
+        let mut accounts = accounts::AccountStorage::new();
+        for client in 0..u16::MAX {
+            let deposit = Input::builder()
+                .deposit(client, client as u32, FixedPoint::from_f64(1.0))
+                .build()
+                .unwrap();
+            accounts.handle_transaction(deposit).unwrap();
+        }
+
+        let mut written = Vec::new();
+        write_buffered(&accounts, &mut written, &OutputConfig::default()).unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert_eq!(u16::MAX as usize + 1, written.lines().count());
+        assert_eq!(Some(HEADER), written.lines().next());
+        assert!(written.ends_with("\n65534, 1.0000, 0.0000, 1.0000, false\n"));
+    }
+
+    #[test]
+    /// An error midway should leave neither a partial file nor the temporary file behind,
+    /// and an existing file at the target should be untouched
+    fn failed_write_leaves_no_partial_output() {
+        let path = temp_path("failed-write-leaves-no-partial-output");
+        let tmp = PathBuf::from(format!("{}.tmp", path.display()));
+
+        let write_then_fail = || -> io::Result<()> {
+            let mut file = AtomicFile::create(&path)?;
+            writeln!(file, "{}", HEADER)?;
+            writeln!(file, "1, 1.0000")?;
+            Err(io::Error::other("induced failure midway"))
         };
-        let s = out.csv_line();
-        println!("{}", s);
+
+        assert!(write_then_fail().is_err());
+        assert!(!path.exists(), "a partial file was left at the target");
+        assert!(!tmp.exists(), "the temporary file was left behind");
+
+        fs::write(&path, "previous output\n").unwrap();
+        assert!(write_then_fail().is_err());
+        assert_eq!("previous output\n", fs::read_to_string(&path).unwrap());
+        assert!(!tmp.exists(), "the temporary file was left behind");
+
+        let _ = fs::remove_file(&path);
     }
 }
@@ -1,58 +1,399 @@
-use std::ops::{Add, AddAssign, Sub, SubAssign};
+use alloc::string::ToString;
+use core::{
+    cmp::Ordering,
+    fmt,
+    iter::Sum,
+    ops::{Add, AddAssign, Neg, Sub, SubAssign},
+    str::FromStr,
+};
 
-#[derive(Debug, PartialOrd, Copy, Clone, PartialEq, Eq, Ord)]
+use serde::{
+    Deserialize, Deserializer, Serialize, Serializer,
+    de::{self, Visitor},
+};
+
+/// Number of decimals stored by a FixedPoint
+const DECIMALS: usize = 4;
+
+/// How a value is rounded when it has more precision than a FixedPoint can store
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoundingMode {
+    /// Round to nearest, and exactly half way to the closest even number, also known as bankers
+    /// rounding. `x.xxxx5` goes to the even fourth decimal, so `0.00005` becomes `0.0000`,
+    /// `0.00015` becomes `0.0002` and `0.00025` becomes `0.0002`. Unlike always rounding half
+    /// away from zero, it does not add a bias to long series of amounts
+    HalfEven,
+}
+
+impl RoundingMode {
+    /// If a value exactly half way is rounded up in magnitude, given the magnitude it has when
+    /// the extra precision is cut off
+    fn tie_rounds_up(self, truncated: i128) -> bool {
+        match self {
+            RoundingMode::HalfEven => truncated % 2 != 0,
+        }
+    }
+
+    /// Integer division of `n` by the positive `divisor`, rounding the quotient
+    pub(crate) fn divide(self, n: i128, divisor: i128) -> i128 {
+        let quotient = n / divisor;
+        let remainder = (n % divisor).abs();
+        let round_up = match (remainder * 2).cmp(&divisor) {
+            Ordering::Greater => true,
+            Ordering::Equal => self.tie_rounds_up(quotient.abs()),
+            Ordering::Less => false,
+        };
+        if round_up {
+            quotient + n.signum()
+        } else {
+            quotient
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialOrd, Copy, Clone, PartialEq, Eq, Ord, Hash)]
 /// Fixed point implementation for numbers with a 4 decimals point, achieved by instead of storing
 /// Decimal numbers, only allow operations on integers representing TenThoushanth's of a coin
+///
+/// Every value has exactly one representation, so equality and hashing both just use the integer
 pub struct FixedPoint(i128);
 
 impl FixedPoint {
+    pub const ZERO: Self = Self(0);
+    /// The smallest representable value
+    pub const MIN: Self = Self(i128::MIN);
+    /// The largest representable value
+    pub const MAX: Self = Self(i128::MAX);
+
+    /// Converts from an f64, panics if it is not representable, see `try_from_f64`
     pub fn from_f64(n: f64) -> Self {
-        let s = format!("{:0.4}", n);
-        let s = s.replace(".", "");
-        let n: i128 = s.parse().unwrap();
-        Self(n)
+        Self::try_from_f64(n).expect("f64 is not representable as a FixedPoint")
     }
+
+    /// Converts from an f64, returns None for NaN, infinities and values too large to represent.
+    ///
+    /// Anything past the fourth decimal is rounded with `RoundingMode::HalfEven`
+    pub fn try_from_f64(n: f64) -> Option<Self> {
+        if !n.is_finite() {
+            return None;
+        }
+        // The shortest representation that reads back as the same f64, so that `0.00015`
+        // is rounded as the decimal it was written as, and not as its binary approximation
+        Self::round_decimal(&n.to_string())
+    }
+
+    /// Converts from an f32, with the same rounding as `from_f64`
     pub fn from_f32(n: f32) -> Self {
-        let s = format!("{:0.4}", n);
-        let s = s.replace(".", "");
-        let n: i128 = s.parse().unwrap();
-        Self(n)
+        Self::try_from_f32(n).expect("f32 is not representable as a FixedPoint")
+    }
+
+    /// Converts from an f32, returns None for NaN and infinities
+    pub fn try_from_f32(n: f32) -> Option<Self> {
+        if !n.is_finite() {
+            return None;
+        }
+        Self::round_decimal(&n.to_string())
     }
+
+    /// Parses a plain decimal number, rounding anything past the fourth decimal according to
+    /// `RoundingMode::HalfEven`. Returns None if the value does not fit
+    fn round_decimal(s: &str) -> Option<Self> {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s),
+        };
+        let (int, frac) = digits.split_once('.').unwrap_or((digits, ""));
+        let (kept, rest) = frac.split_at(frac.len().min(DECIMALS));
+
+        let mut scaled: i128 = int.parse().ok()?;
+        for i in 0..DECIMALS {
+            let digit = kept.as_bytes().get(i).map_or(0, |d| d - b'0');
+            scaled = scaled.checked_mul(10)?.checked_add(digit as i128)?;
+        }
+
+        let mut rest = rest.bytes();
+        let round_up = match rest.next() {
+            Some(b'6'..=b'9') => true,
+            // exactly half way only when nothing but zeroes follow the five
+            Some(b'5') => rest.any(|d| d != b'0') || RoundingMode::HalfEven.tie_rounds_up(scaled),
+            _ => false,
+        };
+        if round_up {
+            scaled = scaled.checked_add(1)?;
+        }
+
+        Some(Self(if negative { -scaled } else { scaled }))
+    }
+
+    /// Converts to an f32 for interop with float based tooling, see `to_f64`, this loses
+    /// precision far sooner, already above roughly 1600 coins
     pub fn to_f32(self) -> f32 {
         let f = self.0 as f32;
-        let f = f / 10000.0;
-        f
+        f / 10000.0
     }
+    /// Converts to an f64 for interop with float based tooling, such as reporting.
+    ///
+    /// This is not exact, an f64 only represents every integer up to 2^53, so amounts above
+    /// roughly 900 billion coins lose decimals, very large magnitudes lose even more. Keep
+    /// calculations on the FixedPoint itself
     pub fn to_f64(self) -> f64 {
         let f = self.0 as f64;
-        let f = f / 10000.0;
-        f
+        f / 10000.0
     }
 
     pub fn as_decimillis(self) -> i128 {
         self.0
     }
+
+    /// The inverse of `as_decimillis`
+    pub fn from_decimillis(decimillis: i128) -> Self {
+        Self(decimillis)
+    }
+
+    /// Rounds to fewer decimals, with the same rounding as `from_f64`, while still being stored
+    /// with four decimals. Rounding to four or more decimals does nothing. A value so close to
+    /// `MIN` or `MAX` that rounding it away from zero is not representable saturates, at the
+    /// largest value with that many decimals
+    pub fn round_to(self, decimals: u32) -> Self {
+        if decimals as usize >= DECIMALS {
+            return self;
+        }
+        let divisor = 10i128.pow(DECIMALS as u32 - decimals);
+        let rounded = RoundingMode::HalfEven
+            .divide(self.0, divisor)
+            .checked_mul(divisor);
+        Self(rounded.unwrap_or(self.0 / divisor * divisor))
+    }
+
+    /// The given basis points of the value, `self * bps / 10000`, where anything past the fourth
+    /// decimal is rounded like `from_f64`. Panics if the intermediate product overflows
+    pub fn apply_bps(self, bps: i64) -> Self {
+        let product = self
+            .0
+            .checked_mul(bps as i128)
+            .expect("basis points of FixedPoint overflowed");
+        Self(RoundingMode::HalfEven.divide(product, 10000))
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    /// If the value is below zero, zero itself is neither negative nor positive
+    pub fn is_negative(self) -> bool {
+        self.0 < 0
+    }
+
+    /// If the value is above zero, zero itself is neither negative nor positive
+    pub fn is_positive(self) -> bool {
+        self.0 > 0
+    }
+
+    /// Adds two values, returns None if the result would overflow
+    pub fn checked_add(self, rhs: Self) -> Option<Self> {
+        self.0.checked_add(rhs.0).map(Self)
+    }
+
+    /// Subtracts two values, returns None if the result would overflow
+    pub fn checked_sub(self, rhs: Self) -> Option<Self> {
+        self.0.checked_sub(rhs.0).map(Self)
+    }
+
+    /// Adds two values, clamping at `MIN`/`MAX` instead of overflowing
+    pub fn saturating_add(self, rhs: Self) -> Self {
+        Self(self.0.saturating_add(rhs.0))
+    }
+
+    /// Subtracts two values, clamping at `MIN`/`MAX` instead of overflowing
+    pub fn saturating_sub(self, rhs: Self) -> Self {
+        Self(self.0.saturating_sub(rhs.0))
+    }
+
+    /// Negates the value, returns None if the result is not representable, which only happens
+    /// for the smallest possible value since it has no positive counterpart
+    pub fn checked_neg(self) -> Option<Self> {
+        self.0.checked_neg().map(Self)
+    }
+
+    /// The magnitude of the value, for example for reporting how far below zero a balance is.
+    ///
+    /// Panics for the smallest possible value, same as `Neg`, use `checked_abs` to avoid that
+    pub fn abs(self) -> Self {
+        self.checked_abs()
+            .expect("absolute value of the smallest FixedPoint is not representable")
+    }
+
+    /// The magnitude of the value, returns None if the result is not representable
+    pub fn checked_abs(self) -> Option<Self> {
+        self.0.checked_abs().map(Self)
+    }
 }
 
+/// Written with all four decimals, such as `1.5000` or `-0.0001`, or with the precision of
+/// the format, so `{:.2}` rounds like `round_to` and writes `1.50`. Any decimals past the
+/// fourth are zero
+impl fmt::Display for FixedPoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let decimals = f.precision().unwrap_or(DECIMALS);
+        let kept = decimals.min(DECIMALS);
+        let rounded = self.round_to(kept as u32);
+        let sign = if rounded.is_negative() { "-" } else { "" };
+        let magnitude = rounded.0.unsigned_abs();
+        let scale = 10u128.pow(DECIMALS as u32);
+        write!(f, "{}{}", sign, magnitude / scale)?;
+        if decimals == 0 {
+            return Ok(());
+        }
+        let fraction = magnitude % scale / 10u128.pow((DECIMALS - kept) as u32);
+        write!(
+            f,
+            ".{:0kept$}{:0<padding$}",
+            fraction,
+            "",
+            kept = kept,
+            padding = decimals - kept
+        )
+    }
+}
+
+/// Serializes as the `Display` string, which reads back exactly
+impl Serialize for FixedPoint {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Why a string could not be parsed as a FixedPoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseFixedPointError {
+    /// Not a decimal number
+    Invalid,
+    /// There are non zero digits past the fourth decimal, which would be lost
+    TooManyDecimals,
+    /// The value is too large to be represented
+    OutOfRange,
+}
+
+impl fmt::Display for ParseFixedPointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseFixedPointError::Invalid => write!(f, "not a decimal number"),
+            ParseFixedPointError::TooManyDecimals => {
+                write!(f, "more than {} decimals", DECIMALS)
+            }
+            ParseFixedPointError::OutOfRange => write!(f, "too large"),
+        }
+    }
+}
+
+impl core::error::Error for ParseFixedPointError {}
+
+/// Parses a decimal number exactly, such as `1`, `-0.5`, `0.1234` or in scientific notation
+/// such as `5.5e-1`. Unlike the float conversions nothing is rounded, so more than four
+/// decimals is an error, unless they are zero
+impl FromStr for FixedPoint {
+    type Err = ParseFixedPointError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (negative, number) = match s.strip_prefix('-') {
+            Some(number) => (true, number),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (mantissa, exponent) = match number.split_once(['e', 'E']) {
+            Some((mantissa, exponent)) => (
+                mantissa,
+                // no amount needs more, and it bounds the zeroes added below
+                exponent
+                    .parse::<i16>()
+                    .map_err(|_| ParseFixedPointError::Invalid)?,
+            ),
+            None => (number, 0),
+        };
+        let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        if int.is_empty() && frac.is_empty() {
+            return Err(ParseFixedPointError::Invalid);
+        }
+        if !int.bytes().chain(frac.bytes()).all(|d| d.is_ascii_digit()) {
+            return Err(ParseFixedPointError::Invalid);
+        }
+
+        // moving the decimal point by the exponent gives a plain decimal number, the digits
+        // are only looked up by their index so that parsing a row never allocates
+        let digit = |i: usize| {
+            let d = match i.checked_sub(int.len()) {
+                None => int.as_bytes()[i],
+                Some(i) => frac.as_bytes().get(i).copied().unwrap_or(b'0'),
+            };
+            d - b'0'
+        };
+        let len = int.len() + frac.len();
+        // one past the index of the last decimal that is kept
+        let end = (int.len() as i64 + exponent as i64 + DECIMALS as i64).max(0) as usize;
+        if (end..len).any(|i| digit(i) != 0) {
+            return Err(ParseFixedPointError::TooManyDecimals);
+        }
+
+        let mut scaled: i128 = 0;
+        for i in 0..end {
+            // negative values are accumulated as such, since `MIN` has no positive counterpart
+            scaled = scaled
+                .checked_mul(10)
+                .and_then(|scaled| match negative {
+                    true => scaled.checked_sub(digit(i) as i128),
+                    false => scaled.checked_add(digit(i) as i128),
+                })
+                .ok_or(ParseFixedPointError::OutOfRange)?;
+        }
+
+        Ok(Self(scaled))
+    }
+}
+
+/// Deserializes from the text of the amount with `from_str`. Formats such as csv would
+/// otherwise hand over `1.5` as a float, rounding amounts with more digits than a float keeps
+impl<'de> Deserialize<'de> for FixedPoint {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct FixedPointVisitor;
+
+        impl Visitor<'_> for FixedPointVisitor {
+            type Value = FixedPoint;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a decimal number with at most four decimals")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                v.parse()
+                    .map_err(|e| E::custom(format_args!("invalid amount {}: {}", v, e)))
+            }
+        }
+
+        deserializer.deserialize_str(FixedPointVisitor)
+    }
+}
+
+/// Floats are compared by converting them with the same rounding as `from_f64`, so the
+/// comparison is exact on the four decimals. Floats without a representation, such as NaN,
+/// are never equal to anything
 impl PartialEq<FixedPoint> for f64 {
     fn eq(&self, other: &FixedPoint) -> bool {
-        FixedPoint::from_f64(*self) == *other
+        FixedPoint::try_from_f64(*self) == Some(*other)
     }
 }
 impl PartialEq<FixedPoint> for f32 {
     fn eq(&self, other: &FixedPoint) -> bool {
-        FixedPoint::from_f32(*self) == *other
+        FixedPoint::try_from_f32(*self) == Some(*other)
     }
 }
 
 impl PartialEq<f64> for FixedPoint {
     fn eq(&self, other: &f64) -> bool {
-        FixedPoint::from_f64(*other) == *self
+        FixedPoint::try_from_f64(*other) == Some(*self)
     }
 }
 impl PartialEq<f32> for FixedPoint {
     fn eq(&self, other: &f32) -> bool {
-        FixedPoint::from_f32(*other) == *self
+        FixedPoint::try_from_f32(*other) == Some(*self)
     }
 }
 
@@ -74,6 +415,17 @@ impl SubAssign for FixedPoint {
     }
 }
 
+/// Panics when negating the smallest possible value, since it has no positive counterpart,
+/// use `checked_neg` where that can happen
+impl Neg for FixedPoint {
+    type Output = Self;
+
+    fn neg(self) -> Self::Output {
+        self.checked_neg()
+            .expect("negation of the smallest FixedPoint is not representable")
+    }
+}
+
 impl Sub for FixedPoint {
     type Output = Self;
 
@@ -82,6 +434,22 @@ impl Sub for FixedPoint {
     }
 }
 
+/// Sums starting from zero, panics if the sum would overflow instead of wrapping around
+impl Sum for FixedPoint {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::ZERO, |acc, v| {
+            acc.checked_add(v)
+                .expect("sum of FixedPoint values overflowed")
+        })
+    }
+}
+
+impl<'a> Sum<&'a FixedPoint> for FixedPoint {
+    fn sum<I: Iterator<Item = &'a FixedPoint>>(iter: I) -> Self {
+        iter.copied().sum()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -106,6 +474,34 @@ mod tests {
         assert_eq!(-1.0, fp.to_f64());
     }
 
+    #[test]
+    /// a fifth decimal of exactly five should round to the closest even fourth decimal
+    fn test_fixedpoint_bankers_rounding() {
+        assert_eq!(0, FixedPoint::from_f64(0.00005).0);
+        assert_eq!(2, FixedPoint::from_f64(0.00015).0);
+        assert_eq!(2, FixedPoint::from_f64(0.00025).0);
+        assert_eq!(4, FixedPoint::from_f64(0.00035).0);
+        assert_eq!(-2, FixedPoint::from_f64(-0.00025).0);
+
+        // anything past half way is not a tie
+        assert_eq!(3, FixedPoint::from_f64(0.000251).0);
+        assert_eq!(2, FixedPoint::from_f64(0.000249).0);
+        assert_eq!(10000, FixedPoint::from_f64(0.99995).0);
+
+        assert_eq!(2, FixedPoint::from_f32(0.00025).0);
+    }
+
+    #[test]
+    /// values that have no fixed point representation should be rejected instead of panicking
+    fn test_fixedpoint_rejects_non_finite() {
+        assert_eq!(None, FixedPoint::try_from_f64(f64::NAN));
+        assert_eq!(None, FixedPoint::try_from_f64(f64::INFINITY));
+        assert_eq!(None, FixedPoint::try_from_f64(f64::NEG_INFINITY));
+        assert_eq!(None, FixedPoint::try_from_f64(1e40));
+        assert_eq!(None, FixedPoint::try_from_f64(-1e40));
+        assert_eq!(Some(FixedPoint(12345)), FixedPoint::try_from_f64(1.2345));
+    }
+
     #[test]
     /// just make sure that fixed point implementation didnt mess up any basic arithmetic
     fn test_fixedpoint_arithemtic() {
@@ -123,4 +519,259 @@ mod tests {
         let res = fp - fp;
         assert_eq!(res.as_decimillis(), 0);
     }
+
+    #[test]
+    /// zero should be the identity for addition
+    fn test_fixedpoint_zero() {
+        assert_eq!(FixedPoint::ZERO, FixedPoint::default());
+
+        let x = FixedPoint::from_f64(55.1234);
+        assert_eq!(x, FixedPoint::ZERO + x);
+        assert_eq!(x, x + FixedPoint::ZERO);
+        assert_eq!(0.0, FixedPoint::ZERO);
+        assert!(FixedPoint::MIN < FixedPoint::ZERO && FixedPoint::ZERO < FixedPoint::MAX);
+    }
+
+    #[test]
+    /// zero is neither positive nor negative
+    fn test_fixedpoint_sign_predicates() {
+        assert!(FixedPoint::ZERO.is_zero());
+        assert!(!FixedPoint::ZERO.is_negative());
+        assert!(!FixedPoint::ZERO.is_positive());
+        assert!(FixedPoint::from_f64(-0.0).is_zero());
+
+        let smallest = FixedPoint::from_f64(0.0001);
+        assert!(smallest.is_positive() && !smallest.is_negative() && !smallest.is_zero());
+        assert!((-smallest).is_negative() && !(-smallest).is_positive());
+    }
+
+    #[test]
+    /// a negative balance, like the one left after a chargeback on partially withdrawn funds,
+    /// should be reportable by its magnitude
+    fn test_fixedpoint_neg_abs() {
+        let balance = FixedPoint::from_f64(-0.1234);
+
+        assert_eq!(0.1234, -balance);
+        assert_eq!(0.1234, balance.abs());
+        assert_eq!(0.1234, balance.abs().abs());
+        assert_eq!(balance, -(-balance));
+
+        assert_eq!(None, FixedPoint::MIN.checked_neg());
+        assert_eq!(None, FixedPoint::MIN.checked_abs());
+        assert_eq!(FixedPoint::MIN + FixedPoint(1), -FixedPoint::MAX);
+    }
+
+    #[test]
+    /// summing should stay exact even where an f64 can no longer represent every integer
+    fn test_fixedpoint_sum() {
+        // 2^53 is where f64 stops being able to represent every integer
+        let values = vec![FixedPoint(1 << 53), FixedPoint(1), FixedPoint(1)];
+
+        let sum: FixedPoint = values.iter().sum();
+        assert_eq!((1 << 53) + 2, sum.as_decimillis());
+
+        let sum: FixedPoint = values.into_iter().sum();
+        assert_eq!((1 << 53) + 2, sum.as_decimillis());
+
+        let empty: Vec<FixedPoint> = Vec::new();
+        assert_eq!(0.0, empty.into_iter().sum::<FixedPoint>());
+    }
+
+    #[test]
+    /// going past the limits should clamp the values instead of wrapping around
+    fn test_fixedpoint_saturating() {
+        let one = FixedPoint::from_f64(1.0);
+        let near_max = FixedPoint::MAX - one;
+
+        assert_eq!(FixedPoint::MAX, near_max.saturating_add(one));
+        assert_eq!(
+            FixedPoint::MAX,
+            near_max.saturating_add(one).saturating_add(one)
+        );
+        assert_eq!(
+            FixedPoint::MAX,
+            FixedPoint::MAX.saturating_add(FixedPoint::MAX)
+        );
+        assert_eq!(FixedPoint::MIN, FixedPoint::MIN.saturating_sub(one));
+        assert_eq!(FixedPoint::MIN, (-near_max).saturating_sub(FixedPoint::MAX));
+
+        // within limits they behave as the regular operators
+        assert_eq!(near_max - one, near_max.saturating_sub(one));
+        assert_eq!(one + one, one.saturating_add(one));
+    }
+
+    #[test]
+    /// rounding to fewer decimals should round half way values to even, and do nothing for
+    /// values that already are as coarse as requested
+    fn test_fixedpoint_round_to() {
+        assert_eq!(0.0, FixedPoint::from_f64(-0.005).round_to(2));
+        assert_eq!(0.02, FixedPoint::from_f64(0.015).round_to(2));
+        assert_eq!(0.02, FixedPoint::from_f64(0.025).round_to(2));
+        assert_eq!(-0.02, FixedPoint::from_f64(-0.015).round_to(2));
+        assert_eq!(0.01, FixedPoint::from_f64(0.0051).round_to(2));
+        assert_eq!(0.0, FixedPoint::from_f64(0.0049).round_to(2));
+        assert_eq!(2.0, FixedPoint::from_f64(2.5).round_to(0));
+        assert_eq!(4.0, FixedPoint::from_f64(3.5).round_to(0));
+
+        let coarse = FixedPoint::from_f64(1.23);
+        assert_eq!(coarse, coarse.round_to(2));
+        let fine = FixedPoint::from_f64(1.2345);
+        assert_eq!(fine, fine.round_to(4));
+        assert_eq!(fine, fine.round_to(8));
+
+        // rounding these up in magnitude would overflow
+        assert_eq!(
+            FixedPoint(i128::MAX / 10000 * 10000),
+            FixedPoint::MAX.round_to(0)
+        );
+        assert_eq!(
+            FixedPoint(i128::MIN / 10000 * 10000),
+            FixedPoint::MIN.round_to(0)
+        );
+        assert_eq!(
+            FixedPoint(i128::MAX / 100 * 100),
+            FixedPoint::MAX.round_to(2)
+        );
+    }
+
+    #[test]
+    /// basis points should be exact where possible and round deterministically where not
+    fn test_fixedpoint_apply_bps() {
+        assert_eq!(2.5, FixedPoint::from_f64(100.0).apply_bps(250));
+        assert_eq!(100.0, FixedPoint::from_f64(100.0).apply_bps(10000));
+        assert_eq!(0.0, FixedPoint::from_f64(100.0).apply_bps(0));
+        assert_eq!(-2.5, FixedPoint::from_f64(-100.0).apply_bps(250));
+        assert_eq!(-2.5, FixedPoint::from_f64(100.0).apply_bps(-250));
+
+        // half of a minor unit rounds to even
+        assert_eq!(0.0, FixedPoint::from_f64(0.0001).apply_bps(5000));
+        assert_eq!(0.0002, FixedPoint::from_f64(0.0003).apply_bps(5000));
+        assert_eq!(0.0002, FixedPoint::from_f64(0.0005).apply_bps(5000));
+        // and anything else to nearest
+        assert_eq!(0.0001, FixedPoint::from_f64(0.0001).apply_bps(5001));
+        assert_eq!(0.0333, FixedPoint::from_f64(0.1).apply_bps(3333));
+    }
+
+    #[test]
+    /// the same value reached in different ways should be the same map key
+    fn test_fixedpoint_hash() {
+        use std::collections::HashMap;
+
+        let mut histogram: HashMap<FixedPoint, usize> = HashMap::new();
+        let computed = FixedPoint::from_f64(10.0) - FixedPoint::from_f64(4.5);
+        *histogram.entry(computed).or_default() += 1;
+        let parsed = FixedPoint::from_f64(5.5);
+        *histogram.entry(parsed).or_default() += 1;
+        let summed = FixedPoint::from_f64(2.75) + FixedPoint::from_f64(2.75);
+        *histogram.entry(summed).or_default() += 1;
+
+        assert_eq!(1, histogram.len());
+        assert_eq!(Some(&3), histogram.get(&FixedPoint::from_f64(5.5)));
+    }
+
+    #[test]
+    /// comparing against floats should work in both directions, on the rounded four decimals
+    fn test_fixedpoint_float_equality() {
+        let fp = FixedPoint::from_f64(0.3);
+
+        // 0.1 + 0.2 is 0.30000000000000004 as an f64
+        assert_ne!(0.3, 0.1 + 0.2);
+        assert_eq!(0.1 + 0.2, fp);
+        assert_eq!(fp, 0.1 + 0.2);
+        assert_eq!(0.3f32, fp);
+        assert_eq!(fp, 0.1f32 + 0.2f32);
+
+        assert_ne!(fp, 0.3001);
+        assert_ne!(0.3001, fp);
+
+        let nan = f64::NAN;
+        assert_ne!(fp, nan);
+        assert_ne!(nan, fp);
+        assert!(FixedPoint::MAX != f64::INFINITY);
+    }
+
+    #[test]
+    /// parsing a string should be exact, and never round away decimals
+    fn test_fixedpoint_from_str() {
+        assert_eq!(Ok(FixedPoint(1000)), "0.1".parse());
+        assert_eq!(Ok(FixedPoint(1234)), "0.1234".parse());
+        assert_eq!(Ok(FixedPoint(12340)), "1.234000".parse());
+        assert_eq!(Ok(FixedPoint(-5000)), "-0.5".parse());
+        assert_eq!(Ok(FixedPoint(50000)), "+5".parse());
+        assert_eq!(Ok(FixedPoint(5000)), ".5".parse());
+        assert_eq!(Ok(FixedPoint(50000)), "5.".parse());
+
+        assert_eq!(
+            Err(ParseFixedPointError::TooManyDecimals),
+            "0.12345".parse::<FixedPoint>()
+        );
+        for invalid in [
+            "", ".", "-", "1.2.3", "abc", "1,5", " 1", "NaN", "inf", "--1",
+        ] {
+            assert_eq!(
+                Err(ParseFixedPointError::Invalid),
+                invalid.parse::<FixedPoint>(),
+                "{:?}",
+                invalid
+            );
+        }
+        for invalid in ["1e", "e2", "1e2.5", "1e99999", "1ee2"] {
+            assert_eq!(
+                Err(ParseFixedPointError::Invalid),
+                invalid.parse::<FixedPoint>(),
+                "{:?}",
+                invalid
+            );
+        }
+        let too_large = "9".repeat(40);
+        assert_eq!(
+            Err(ParseFixedPointError::OutOfRange),
+            too_large.parse::<FixedPoint>()
+        );
+    }
+
+    #[test]
+    /// scientific notation, as some float serializers write, should give the same exact value
+    fn test_fixedpoint_from_str_scientific() {
+        assert_eq!(Ok(FixedPoint::from_f64(100.0)), "1e2".parse());
+        assert_eq!(Ok(FixedPoint(1_000_000)), "1E2".parse());
+        assert_eq!(Ok(FixedPoint(5500)), "5.5E-1".parse());
+        assert_eq!(Ok(FixedPoint(-25_000_000)), "-2.5e+3".parse());
+        assert_eq!(Ok(FixedPoint(1)), "1e-4".parse());
+        assert_eq!(Ok(FixedPoint(1234)), "1234e-4".parse());
+        assert_eq!(Ok(FixedPoint(0)), "0e-9".parse());
+        assert_eq!(Ok(FixedPoint(1230)), "0.0123e1".parse());
+
+        assert_eq!(
+            Err(ParseFixedPointError::TooManyDecimals),
+            "1.23456e-1".parse::<FixedPoint>()
+        );
+        assert_eq!(
+            Err(ParseFixedPointError::TooManyDecimals),
+            "1e-5".parse::<FixedPoint>()
+        );
+        assert_eq!(
+            Err(ParseFixedPointError::OutOfRange),
+            "1e40".parse::<FixedPoint>()
+        );
+    }
+
+    #[test]
+    fn test_fixedpoint_display() {
+        assert_eq!("1.5000", FixedPoint::from_f64(1.5).to_string());
+        assert_eq!("0.0000", FixedPoint::ZERO.to_string());
+        assert_eq!("-0.0001", FixedPoint(-1).to_string());
+        assert_eq!("-12.3400", FixedPoint::from_f64(-12.34).to_string());
+        for fp in [FixedPoint::MIN, FixedPoint::MAX, FixedPoint(-123456)] {
+            assert_eq!(Ok(fp), fp.to_string().parse());
+        }
+
+        // a precision rounds, or pads past the fourth decimal
+        let fp = FixedPoint(1234567890123456789);
+        assert_eq!("123456789012345.68", format!("{:.2}", fp));
+        assert_eq!("123456789012346", format!("{:.0}", fp));
+        assert_eq!("123456789012345.678900", format!("{:.6}", fp));
+        assert_eq!("0.00", format!("{:.2}", FixedPoint(-49)));
+        assert_eq!("-0.02", format!("{:.2}", FixedPoint(-150)));
+    }
 }
@@ -0,0 +1,315 @@
+use std::{
+    future::poll_fn,
+    io,
+    pin::{Pin, pin},
+};
+
+use csv::StringRecord;
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio_stream::{Stream, StreamExt};
+
+use crate::{
+    accounts::AccountStorage,
+    engine::Summary,
+    input::{self, Input, ParseErrorRecord},
+};
+
+/// Applies transactions to the storage as they arrive from an async source, such as a network
+/// socket. Only the ingestion is async, every transaction is handled just like when reading
+/// from a file
+pub async fn process_async<S: Stream<Item = Input>>(stream: S, storage: &mut AccountStorage) {
+    let mut stream = pin!(stream);
+    while let Some(transaction) = stream.next().await {
+        if let Err(_e) = storage.handle_transaction(transaction) {
+            // here one would normally log any error to transactions
+        }
+    }
+}
+
+/// Reads csv or newline delimited json from an async reader, such as a socket, and applies
+/// every transaction to the storage as soon as its line has arrived. Ends when the reader
+/// does, rows that fail to parse or are rejected are counted in the summary. To look at the
+/// balances while the feed is still open, drive `StreamTransactions` instead and apply the
+/// rows in between
+pub async fn process_stream<R: AsyncRead + Unpin>(
+    reader: R,
+    storage: &mut AccountStorage,
+) -> io::Result<Summary> {
+    let mut transactions = StreamTransactions::new(reader);
+    let mut summary = Summary::default();
+    while let Some(row) = transactions.next().await? {
+        match row {
+            Ok(transaction) => summary.count(storage.handle_transaction(transaction)),
+            Err(e) => summary.parse_errors.push(e),
+        }
+    }
+    Ok(summary)
+}
+
+/// The transactions of an async reader, parsed one line at a time as the lines arrive.
+///
+/// The format is picked from the first line that is not blank or a comment, a json object
+/// starts newline delimited json, anything else is the header of a csv, with its delimiter
+/// guessed by `input::sniff_delimiter`. Since a row is parsed as soon as its line is
+/// complete, quoted csv fields cannot span lines
+pub struct StreamTransactions<R> {
+    reader: R,
+    /// read from the reader but not yet parsed, always the start of a line
+    pending: Vec<u8>,
+    /// the reader has ended, what is pending is the last line
+    ended: bool,
+    /// the line of the input the latest row is from
+    line: u64,
+    format: Option<StreamFormat>,
+    record: StringRecord,
+    trimmed: StringRecord,
+}
+
+enum StreamFormat {
+    Csv {
+        delimiter: u8,
+        headers: StringRecord,
+    },
+    JsonLines,
+}
+
+impl<R: AsyncRead + Unpin> StreamTransactions<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            pending: Vec::new(),
+            ended: false,
+            line: 0,
+            format: None,
+            record: StringRecord::new(),
+            trimmed: StringRecord::new(),
+        }
+    }
+
+    /// Waits for the next row, None once the reader has ended. A row that fails to parse is
+    /// given as its error, only failing to read ends with an error
+    pub async fn next(&mut self) -> io::Result<Option<Result<Input, ParseErrorRecord>>> {
+        while let Some(line) = self.next_line().await? {
+            let line = line.strip_suffix(b"\r").unwrap_or(&line);
+            if line.trim_ascii().is_empty() || line.starts_with(b"#") {
+                continue;
+            }
+            if let Some(row) = self.parse(line) {
+                return Ok(Some(row));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The next complete line without its newline, reading from the reader until one is
+    async fn next_line(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            if let Some(end) = self.pending.iter().position(|b| *b == b'\n') {
+                self.line += 1;
+                let mut line: Vec<u8> = self.pending.drain(..=end).collect();
+                line.pop();
+                return Ok(Some(line));
+            }
+            if self.ended {
+                if self.pending.is_empty() {
+                    return Ok(None);
+                }
+                self.line += 1;
+                return Ok(Some(std::mem::take(&mut self.pending)));
+            }
+
+            let mut chunk = [0; 8 * 1024];
+            let read = poll_fn(|cx| {
+                let mut buf = ReadBuf::new(&mut chunk);
+                Pin::new(&mut self.reader)
+                    .poll_read(cx, &mut buf)
+                    .map_ok(|()| buf.filled().len())
+            })
+            .await?;
+            self.ended = read == 0;
+            self.pending.extend_from_slice(&chunk[..read]);
+        }
+    }
+
+    /// Parses a line that is not blank, None for the header of a csv
+    fn parse(&mut self, line: &[u8]) -> Option<Result<Input, ParseErrorRecord>> {
+        let (delimiter, headers) = match &self.format {
+            Some(StreamFormat::Csv { delimiter, headers }) => (*delimiter, headers),
+            Some(StreamFormat::JsonLines) => {
+                return Some(input::input_from_json(line).map_err(|e| {
+                    let mut error = ParseErrorRecord::from(&e);
+                    error.line = Some(self.line);
+                    error
+                }));
+            }
+            None if line.trim_ascii_start().starts_with(b"{") => {
+                self.format = Some(StreamFormat::JsonLines);
+                return self.parse(line);
+            }
+            None => {
+                let delimiter = input::sniff_delimiter(line);
+                let headers = match read_line(line, delimiter, &mut self.record) {
+                    Ok(()) => self.record.iter().map(str::trim).collect(),
+                    Err(e) => return Some(Err(self.error(&e))),
+                };
+                self.format = Some(StreamFormat::Csv { delimiter, headers });
+                return None;
+            }
+        };
+
+        let row = read_line(line, delimiter, &mut self.record).and_then(|()| {
+            let mut position = csv::Position::new();
+            position.set_line(self.line);
+            self.record.set_position(Some(position));
+            input::deserialize_trimmed(&self.record, &mut self.trimmed, Some(headers))
+        });
+        match row {
+            Ok(Some(transaction)) => Some(Ok(transaction)),
+            // a row of empty fields
+            Ok(None) => None,
+            Err(e) => Some(Err(self.error(&e))),
+        }
+    }
+
+    /// The error of a csv line, which is always parsed as the first line of its own reader
+    fn error(&self, e: &csv::Error) -> ParseErrorRecord {
+        let mut error = ParseErrorRecord::from(e);
+        error.line = Some(self.line);
+        error
+    }
+}
+
+/// Reads a single line of csv into the record
+fn read_line(line: &[u8], delimiter: u8, record: &mut StringRecord) -> csv::Result<()> {
+    csv::ReaderBuilder::new()
+        .has_headers(false)
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_reader(line)
+        .read_record(record)
+        .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::TransactionType;
+    use std::task::{Context, Poll};
+    use tokio::sync::mpsc;
+
+    /// The reading end of a pipe, giving the chunks sent to it as they arrive, and ending
+    /// when the sender is dropped
+    struct Pipe(mpsc::UnboundedReceiver<&'static [u8]>);
+
+    impl AsyncRead for Pipe {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            self.0.poll_recv(cx).map(|chunk| {
+                buf.put_slice(chunk.unwrap_or_default());
+                Ok(())
+            })
+        }
+    }
+
+    fn pipe() -> (mpsc::UnboundedSender<&'static [u8]>, Pipe) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        (sender, Pipe(receiver))
+    }
+
+    #[tokio::test]
+    /// Rows are applied as soon as their line is complete, also when it was split over
+    /// several chunks, and the balances can be read while the feed is still open
+    async fn balances_while_streaming() {
+        let (sender, pipe) = pipe();
+        let mut transactions = StreamTransactions::new(pipe);
+        let mut storage = AccountStorage::new();
+        let available = |storage: &AccountStorage, client| storage.accounts()[&client].available();
+
+        sender
+            .send(b"type, client, tx, amount\ndeposit, 1, 1, 10.0\nwithdr")
+            .unwrap();
+        let row = transactions.next().await.unwrap().unwrap().unwrap();
+        storage.handle_transaction(row).unwrap();
+        assert_eq!(10.0, available(&storage, 1));
+
+        sender.send(b"awal, 1, 2, 2.5\n\ndeposit, 2").unwrap();
+        sender.send(b", 3, 4.0\n").unwrap();
+        let row = transactions.next().await.unwrap().unwrap().unwrap();
+        storage.handle_transaction(row).unwrap();
+        assert_eq!(7.5, available(&storage, 1));
+        assert!(!storage.accounts().contains_key(&2));
+
+        let row = transactions.next().await.unwrap().unwrap().unwrap();
+        storage.handle_transaction(row).unwrap();
+        assert_eq!(4.0, available(&storage, 2));
+
+        // the last line needs no newline once the feed ends
+        sender.send(b"dispute, x, 3,\ndispute, 2, 3,").unwrap();
+        drop(sender);
+        let error = transactions.next().await.unwrap().unwrap().unwrap_err();
+        assert_eq!(Some(6), error.line);
+        let row = transactions.next().await.unwrap().unwrap().unwrap();
+        storage.handle_transaction(row).unwrap();
+        assert_eq!(4.0, storage.accounts()[&2].held());
+        assert!(transactions.next().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    /// Newline delimited json is picked from its first line, and the rows are counted like
+    /// those of a file
+    async fn process_json_lines_stream() {
+        let (sender, pipe) = pipe();
+        for chunk in [
+            &b"{\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amo"[..],
+            b"unt\": \"3.0\"}\n{\"type\": \"withdrawal\", \"client\": 1, \"tx\": 2, ",
+            b"\"amount\": \"5.0\"}\nnot json\n",
+            b"{\"type\": \"withdrawal\", \"client\": 1, \"tx\": 3, \"amount\": 1}\n",
+        ] {
+            sender.send(chunk).unwrap();
+        }
+        drop(sender);
+
+        let mut storage = AccountStorage::new();
+        let summary = process_stream(pipe, &mut storage).await.unwrap();
+
+        assert_eq!(3, summary.processed);
+        assert_eq!(1, summary.rejected);
+        assert_eq!(
+            vec![Some(3)],
+            summary
+                .parse_errors
+                .iter()
+                .map(|e| e.line)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(2.0, storage.accounts()[&1].available());
+    }
+
+    #[tokio::test]
+    /// The ledger after an async stream should be the same as handling the inputs one by one
+    async fn process_async_stream() {
+        let inputs = vec![
+            Input::from_f64(TransactionType::Deposit, 1, 1, Some(10.0)),
+            Input::from_f64(TransactionType::Deposit, 2, 2, Some(5.0)),
+            Input::from_f64(TransactionType::Withdrawal, 1, 3, Some(2.5)),
+            Input::new(TransactionType::Dispute, 2, 2, None),
+            // fails, not enough funds, but should not stop the stream
+            Input::from_f64(TransactionType::Withdrawal, 2, 4, Some(1.0)),
+            Input::from_f64(TransactionType::Deposit, 2, 5, Some(1.0)),
+        ];
+
+        let mut storage = AccountStorage::new();
+        process_async(tokio_stream::iter(inputs), &mut storage).await;
+
+        let account = storage.accounts().get(&1).unwrap();
+        assert_eq!(7.5, account.available());
+
+        let account = storage.accounts().get(&2).unwrap();
+        assert_eq!(1.0, account.available());
+        assert_eq!(5.0, account.held());
+        assert_eq!(6.0, account.total());
+    }
+}
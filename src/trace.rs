@@ -0,0 +1,213 @@
+//! Structured events from the processing of the transactions, for observing the engine
+//! without writing to stdout, which only has the accounts.
+//!
+//! A small stand in for the `tracing` crate, which is not available to build against here:
+//! every transaction handled by an `AccountStorage` runs in a span with its `client` and
+//! `tx`, and the events inside it are handed to the subscriber of the current thread
+//!
+//! ```
+//! use std::{cell::RefCell, rc::Rc};
+//!
+//! use payeng::{
+//!     AccountStorage, FixedPoint, Input,
+//!     trace::{self, Event, Subscriber},
+//! };
+//!
+//! #[derive(Default)]
+//! struct Names(RefCell<Vec<&'static str>>);
+//!
+//! impl Subscriber for Names {
+//!     fn event(&self, event: &Event) {
+//!         self.0.borrow_mut().push(event.name);
+//!     }
+//! }
+//!
+//! let names = Rc::new(Names::default());
+//! let mut accounts = AccountStorage::new();
+//! let withdrawal = Input::builder()
+//!     .withdrawal(1, 1, FixedPoint::from_f64(1.0))
+//!     .build()
+//!     .unwrap();
+//! trace::with_subscriber(names.clone(), || {
+//!     let _ = accounts.handle_transaction(withdrawal);
+//! });
+//! assert_eq!(vec!["rejected"], *names.0.borrow());
+//! ```
+
+use std::{cell::RefCell, rc::Rc};
+
+/// The handling of one transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub name: &'static str,
+    pub client: u16,
+    pub tx: u32,
+}
+
+/// Something that happened to an account
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    pub name: &'static str,
+    pub client: u16,
+    pub tx: u32,
+    /// the span the event happened in, if any
+    pub span: Option<Span>,
+    /// why the transaction was rejected, the name of its `TransactionError`
+    pub reason: Option<&'static str>,
+}
+
+/// Receives the spans and events of the current thread, see `with_subscriber`
+pub trait Subscriber {
+    fn event(&self, event: &Event);
+
+    fn enter(&self, _span: &Span) {}
+
+    fn exit(&self, _span: &Span) {}
+}
+
+thread_local! {
+    static SUBSCRIBER: RefCell<Option<Rc<dyn Subscriber>>> = const { RefCell::new(None) };
+    static CURRENT: RefCell<Vec<Span>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Runs `f` with the subscriber receiving the spans and events of this thread
+pub fn with_subscriber<T>(subscriber: Rc<dyn Subscriber>, f: impl FnOnce() -> T) -> T {
+    /// puts the previous subscriber back, also when `f` panics
+    struct Restore(Option<Rc<dyn Subscriber>>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            SUBSCRIBER.with(|current| *current.borrow_mut() = self.0.take());
+        }
+    }
+
+    let _restore = Restore(SUBSCRIBER.with(|current| current.borrow_mut().replace(subscriber)));
+    f()
+}
+
+/// The subscriber of this thread, cloned so that it may emit events itself
+fn subscriber() -> Option<Rc<dyn Subscriber>> {
+    SUBSCRIBER.with(|current| current.borrow().clone())
+}
+
+/// Leaves the span when dropped
+pub(crate) struct Entered(Span);
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.borrow_mut().pop());
+        if let Some(subscriber) = subscriber() {
+            subscriber.exit(&self.0);
+        }
+    }
+}
+
+/// Enters a span, the events until the guard is dropped belong to it
+pub(crate) fn span(name: &'static str, client: u16, tx: u32) -> Entered {
+    let span = Span { name, client, tx };
+    CURRENT.with(|current| current.borrow_mut().push(span));
+    if let Some(subscriber) = subscriber() {
+        subscriber.enter(&span);
+    }
+    Entered(span)
+}
+
+pub(crate) fn event(name: &'static str, client: u16, tx: u32, reason: Option<&'static str>) {
+    let Some(subscriber) = subscriber() else {
+        return;
+    };
+    let span = CURRENT.with(|current| current.borrow().last().copied());
+    subscriber.event(&Event {
+        name,
+        client,
+        tx,
+        span,
+        reason,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AccountStorage, FixedPoint, Input};
+
+    #[derive(Default)]
+    struct Collect {
+        events: RefCell<Vec<Event>>,
+        spans: RefCell<Vec<(bool, Span)>>,
+    }
+
+    impl Subscriber for Collect {
+        fn event(&self, event: &Event) {
+            self.events.borrow_mut().push(*event);
+        }
+
+        fn enter(&self, span: &Span) {
+            self.spans.borrow_mut().push((true, *span));
+        }
+
+        fn exit(&self, span: &Span) {
+            self.spans.borrow_mut().push((false, *span));
+        }
+    }
+
+    #[test]
+    /// A chargeback should fire an event inside the span of its transaction
+    fn chargeback_event() {
+        let inputs = [
+            Input::builder().deposit(1, 1, FixedPoint::from_f64(3.0)),
+            Input::builder().dispute(1, 1),
+            Input::builder().chargeback(1, 1),
+        ];
+        let collect = Rc::new(Collect::default());
+        let mut accounts = AccountStorage::new();
+        with_subscriber(collect.clone(), || {
+            for input in inputs {
+                accounts.handle_transaction(input.build().unwrap()).unwrap();
+            }
+        });
+
+        let span = Span {
+            name: "transaction",
+            client: 1,
+            tx: 1,
+        };
+        assert_eq!(
+            vec![Event {
+                name: "chargeback",
+                client: 1,
+                tx: 1,
+                span: Some(span),
+                reason: None,
+            }],
+            *collect.events.borrow()
+        );
+        // every transaction entered and left its span
+        assert_eq!(6, collect.spans.borrow().len());
+        assert!(accounts.accounts()[&1].locked());
+
+        // nothing is received once the subscriber is gone
+        accounts
+            .handle_transaction(Input::new(crate::TransactionType::Inquiry, 1, 2, None))
+            .ok();
+        assert_eq!(6, collect.spans.borrow().len());
+    }
+
+    #[test]
+    /// An inquiry should fire an event, even though it changes nothing
+    fn inquiry_event() {
+        let collect = Rc::new(Collect::default());
+        let mut accounts = AccountStorage::new();
+        let deposit = Input::builder().deposit(1, 1, FixedPoint::from_f64(3.0));
+        accounts
+            .handle_transaction(deposit.build().unwrap())
+            .unwrap();
+        with_subscriber(collect.clone(), || {
+            let inquiry = Input::builder().inquiry(1, 2).build().unwrap();
+            accounts.handle_transaction(inquiry).unwrap();
+        });
+
+        let names: Vec<_> = collect.events.borrow().iter().map(|e| e.name).collect();
+        assert_eq!(vec!["inquiry"], names);
+    }
+}
@@ -0,0 +1,153 @@
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+#[test]
+/// A file that does not exist is a routine mistake, which should be reported by name
+/// rather than with a panic
+fn missing_file_is_a_clean_error() {
+    let output = Command::new(env!("CARGO_BIN_EXE_payeng"))
+        .arg("testdata/does-not-exist.csv")
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.starts_with("Error: Failed to read testdata/does-not-exist.csv: "),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+}
+
+#[test]
+/// `--format json` writes the same balances as the csv output, as a JSON array
+fn json_format() {
+    let output = Command::new(env!("CARGO_BIN_EXE_payeng"))
+        .args(["--format", "json", "testdata/input.csv"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let accounts: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        serde_json::json!([
+            {"client": 1, "available": "1.5000", "held": "0.0000", "total": "1.5000", "locked": false},
+            {"client": 2, "available": "2.0000", "held": "0.0000", "total": "2.0000", "locked": false},
+        ]),
+        accounts
+    );
+}
+
+#[test]
+/// The summary of the run goes to stderr, with the rejected rows counted by why they were
+/// rejected, while stdout only has the accounts
+fn summary_on_stderr() {
+    let output = Command::new(env!("CARGO_BIN_EXE_payeng"))
+        .args(["testdata/input.csv", "testdata/parse_errors.csv"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("client, available, held, total, locked\n"));
+    assert!(!stdout.contains("rejected"));
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let summary: Vec<&str> = stderr
+        .lines()
+        .skip_while(|l| !l.starts_with("read "))
+        .collect();
+    // the second file reuses two tx ids of the first one
+    assert_eq!(
+        vec![
+            "read 12 rows, applied 4, rejected 7, failed to parse 1",
+            "  duplicate_tx_id: 2",
+            "  malformed_input: 1",
+            "  not_enough_available_funds: 1",
+            "  tx_belongs_to_other_client: 3",
+        ],
+        summary
+    );
+}
+
+#[test]
+/// `--check` reports every row a real run would reject and fails, without writing the
+/// accounts
+fn check_reports_rejected_rows() {
+    let output = Command::new(env!("CARGO_BIN_EXE_payeng"))
+        .args(["--check", "testdata/input.csv"])
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let rejected: Vec<&str> = stderr
+        .lines()
+        .filter(|l| l.starts_with("Rejected "))
+        .collect();
+    assert_eq!(5, rejected.len(), "{}", stderr);
+    assert!(rejected[1].starts_with("Rejected withdrawal of client 2 with tx 5: "));
+    assert!(stderr.ends_with("Error: 5 rows were rejected or failed to parse\n"));
+}
+
+#[test]
+/// A file without any problems passes the check
+fn check_passes_valid_file() {
+    let path = std::env::temp_dir().join(format!("payeng-check-{}.csv", std::process::id()));
+    std::fs::write(
+        &path,
+        "type, client, tx, amount\ndeposit, 1, 1, 5.0\nwithdrawal, 1, 2, 2.0\n",
+    )
+    .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_payeng"))
+        .arg("--check")
+        .arg(&path)
+        .output()
+        .unwrap();
+    let _ = std::fs::remove_file(&path);
+
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+/// Stdout only ever has the balances, so that it can be piped into another tool, nothing
+/// about the disputes may be printed before them
+fn stdout_has_only_balances() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_payeng"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(
+            b"type, client, tx, amount
+deposit, 1, 1, 5.0
+deposit, 2, 2, 1.0
+dispute, 1, 1,
+resolve, 2, 2,
+chargeback, 1, 1,
+chargeback, 1, 1,
+",
+        )
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(
+        "client, available, held, total, locked\n\
+         1, 0.0000, 0.0000, 0.0000, true\n\
+         2, 1.0000, 0.0000, 1.0000, false\n",
+        stdout
+    );
+}
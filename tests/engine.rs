@@ -0,0 +1,41 @@
+use std::fs::File;
+
+use payeng::{Engine, FixedPoint};
+
+#[test]
+/// The example input should give the same balances through the engine as through the binary
+fn input_csv_through_engine() {
+    let input = File::open("testdata/input.csv").unwrap();
+    let mut output = Vec::new();
+
+    let mut engine = Engine::new();
+    let summary = engine.run(input, &mut output).unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    assert_eq!(
+        vec![
+            "client, available, held, total, locked",
+            "1, 1.5000, 0.0000, 1.5000, false",
+            "2, 2.0000, 0.0000, 2.0000, false",
+        ],
+        output.lines().collect::<Vec<_>>()
+    );
+
+    assert!(summary.parse_errors.is_empty(), "{:?}", summary.parse_errors);
+    assert_eq!(9, summary.processed);
+    // the deposit without an amount, the withdrawal over the balance of client 2 and the
+    // dispute, resolve and chargeback of a tx that belongs to another client
+    assert_eq!(5, summary.rejected);
+    assert_eq!(
+        vec![
+            ("malformed_input", 1),
+            ("not_enough_available_funds", 1),
+            ("tx_belongs_to_other_client", 3),
+        ],
+        summary.rejections.into_iter().collect::<Vec<_>>()
+    );
+    assert_eq!(
+        FixedPoint::from_f64(1.5),
+        engine.accounts().get(1).unwrap().available()
+    );
+}
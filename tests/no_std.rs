@@ -0,0 +1,19 @@
+use std::{path::Path, process::Command};
+
+#[test]
+/// Without the `std` feature the library is `no_std`, so checking it that way fails on
+/// anything in the ledger that still needs std
+fn ledger_builds_without_std() {
+    let output = Command::new(env!("CARGO"))
+        .args(["check", "--lib", "--no-default-features", "--target-dir"])
+        .arg(Path::new(env!("CARGO_TARGET_TMPDIR")).join("no_std"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .unwrap();
+
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}